@@ -0,0 +1,440 @@
+/*
+Audio Processing Unit: https://gbdev.io/pandocs/Audio.html
+
+Four channels (two pulse, wave, noise) mixed into a stereo stream. Owns the sound registers at 0xFF10-0xFF26 and
+wave RAM at 0xFF30-0xFF3F.
+
+registers: NR10-NR51 as last written, reads OR in the bits that always read as 1
+sequencer_timer / sequencer_step: 512 Hz frame sequencer, clocks length (256 Hz), sweep (128 Hz) and envelope (64 Hz)
+sample_timer: clocks until the next output sample, one sample is produced every APU_CYCLES_PER_SAMPLE clocks
+samples: stereo output at APU_SAMPLE_RATE, each side in -1.0..=1.0, drained by the frontend
+recorder: WAV capture of the output (and optionally each channel), fed from the same samples
+recording_error: why the last recording ended on its own, kept until the next one starts
+ */
+use std::path::Path;
+
+use crate::emulator::constants;
+use crate::emulator::apu::pulse::Pulse;
+use crate::emulator::apu::wave::Wave;
+use crate::emulator::apu::noise::Noise;
+use crate::emulator::apu::wav::Recorder;
+
+const REGISTER_COUNT: usize = constants::NR52 - constants::NR10;
+
+//Bits that read back as 1 for NR10-NR51, unused addresses read as 0xFF
+const READ_MASKS: [u8; REGISTER_COUNT] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, //NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, //unused, NR21-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, //NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, //unused, NR41-NR44
+    0x00, 0x00,                   //NR50-NR51
+];
+
+pub struct APU {
+    channel1: Pulse,
+    channel2: Pulse,
+    channel3: Wave,
+    channel4: Noise,
+    registers: [u8; REGISTER_COUNT],
+    powered: bool,
+    sequencer_timer: u32,
+    sequencer_step: u8,
+    sample_timer: u32,
+    samples: Vec<[f32; 2]>,
+    recorder: Option<Recorder>,
+    recording_error: Option<std::io::Error>,
+}
+
+impl APU {
+    pub fn new() -> Self {
+        Self {
+            channel1: Pulse::new(true),
+            channel2: Pulse::new(false),
+            channel3: Wave::new(),
+            channel4: Noise::new(),
+            registers: [0; REGISTER_COUNT],
+            powered: true,
+            sequencer_timer: 0,
+            sequencer_step: 0,
+            sample_timer: 0,
+            samples: Vec::new(),
+            recorder: None,
+            recording_error: None,
+        }
+    }
+
+    /*
+    Advance by one clock. Runs at the normal 4 MHz rate in CGB double-speed mode too.
+     */
+    pub fn tick(&mut self) {
+        if self.powered {
+            self.channel1.tick();
+            self.channel2.tick();
+            self.channel3.tick();
+            self.channel4.tick();
+            self.sequencer_timer += 1;
+            if self.sequencer_timer == constants::FRAME_SEQUENCER_CYCLES {
+                self.sequencer_timer = 0;
+                self.clock_sequencer();
+            }
+        }
+        self.sample_timer += 1;
+        if self.sample_timer == constants::APU_CYCLES_PER_SAMPLE {
+            self.sample_timer = 0;
+            if self.samples.len() >= constants::APU_SAMPLE_BUFFER_LIMIT {
+                self.samples.drain(..constants::APU_SAMPLE_BUFFER_LIMIT / 2);
+            }
+            let sample = self.mix();
+            self.samples.push(sample);
+            self.record(sample);
+        }
+    }
+
+    /*
+    A failed write ends the recording rather than interrupting emulation, the error is kept for recording_error().
+    Finishing the files usually fails the same way, so the write error is the one reported.
+     */
+    fn record(&mut self, sample: [f32; 2]) {
+        let outputs = self.channel_outputs();
+        let result = match &mut self.recorder {
+            Some(recorder) => recorder.write(sample, outputs),
+            None => return,
+        };
+        match result {
+            Ok(_) => (),
+            Err(e) => {
+                let _ = self.stop_recording();
+                self.recording_error = Some(e);
+            }
+        }
+    }
+
+    /*
+    Start capturing audio to a .wav file, replacing any recording already in progress.
+    With `stems` set each channel is also written to its own file next to `path`.
+     */
+    pub fn start_recording(&mut self, path: &Path, stems: bool) -> std::io::Result<()> {
+        self.stop_recording()?;
+        self.recording_error = None;
+        self.recorder = Some(Recorder::new(path, stems)?);
+        return Ok(());
+    }
+
+    //Finish the current recording, if any, so the files have valid headers
+    pub fn stop_recording(&mut self) -> std::io::Result<()> {
+        match self.recorder.take() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    //Why the last recording stopped by itself, None if it is still running or was stopped on request
+    pub fn recording_error(&self) -> Option<&std::io::Error> {
+        self.recording_error.as_ref()
+    }
+
+    //Hand the error over when the APU is replaced, e.g. on a GBS track change
+    pub fn take_recording_error(&mut self) -> Option<std::io::Error> {
+        self.recording_error.take()
+    }
+
+    pub fn set_recording_error(&mut self, error: Option<std::io::Error>) {
+        self.recording_error = error;
+    }
+
+    /*
+    Frame sequencer steps:
+        * 0, 2, 4, 6: length counters
+        * 2, 6: channel 1 sweep
+        * 7: volume envelopes
+     */
+    fn clock_sequencer(&mut self) {
+        if self.sequencer_step % 2 == 0 {
+            self.channel1.clock_length();
+            self.channel2.clock_length();
+            self.channel3.clock_length();
+            self.channel4.clock_length();
+        }
+        if self.sequencer_step == 2 || self.sequencer_step == 6 {
+            self.channel1.clock_sweep();
+        }
+        if self.sequencer_step == 7 {
+            self.channel1.clock_envelope();
+            self.channel2.clock_envelope();
+            self.channel4.clock_envelope();
+        }
+        self.sequencer_step = (self.sequencer_step + 1) % 8;
+    }
+
+    /*
+    Each channel's DAC maps its digital output 0-15 to -1.0..=1.0, a DAC that is off outputs 0.
+    Returned in channel order 1-4.
+     */
+    pub fn channel_outputs(&self) -> [f32; 4] {
+        [
+            APU::dac(self.channel1.output(), self.channel1.dac_enabled()),
+            APU::dac(self.channel2.output(), self.channel2.dac_enabled()),
+            APU::dac(self.channel3.output(), self.channel3.dac_enabled()),
+            APU::dac(self.channel4.output(), self.channel4.dac_enabled()),
+        ]
+    }
+
+    fn dac(output: u8, enabled: bool) -> f32 {
+        if !enabled { return 0.0; }
+        return output as f32 / 7.5 - 1.0;
+    }
+
+    /*
+    NR51 routes each channel to the left (bits 4-7) and/or right (bits 0-3) output,
+    NR50 scales each side by (volume + 1) / 8 with the left volume in bits 4-6 and the right in bits 0-2.
+     */
+    fn mix(&self) -> [f32; 2] {
+        if !self.powered { return [0.0, 0.0]; }
+        let outputs = self.channel_outputs();
+        let panning = self.registers[constants::NR51 - constants::NR10];
+        let volume = self.registers[constants::NR50 - constants::NR10];
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for channel in 0..4 {
+            if panning & (0b00010000 << channel) > 0 { left += outputs[channel]; }
+            if panning & (0b00000001 << channel) > 0 { right += outputs[channel]; }
+        }
+        let left_volume = (((volume >> 4) & 0b111) + 1) as f32 / 8.0;
+        let right_volume = ((volume & 0b111) + 1) as f32 / 8.0;
+        return [left / 4.0 * left_volume, right / 4.0 * right_volume];
+    }
+
+    //Remove and return all samples produced since the last call
+    pub fn take_samples(&mut self) -> Vec<[f32; 2]> {
+        std::mem::take(&mut self.samples)
+    }
+
+    /*
+    NR52: bit 7 is the power switch, bits 0-3 report which channels are on (read-only).
+    Wave RAM is always accessible, every other register ignores writes while the APU is off.
+     */
+    pub fn read_register(&self, addr: u16) -> u8 {
+        match addr as usize {
+            constants::NR10..=constants::NR51 => {
+                let index = addr as usize - constants::NR10;
+                self.registers[index] | READ_MASKS[index]
+            },
+            constants::NR52 => {
+                let status = (self.channel1.enabled as u8)
+                    | (self.channel2.enabled as u8) << 1
+                    | (self.channel3.enabled as u8) << 2
+                    | (self.channel4.enabled as u8) << 3;
+                0b01110000 | (self.powered as u8) << 7 | status
+            },
+            constants::WAVE_RAM_START..=constants::WAVE_RAM_END => self.channel3.ram[addr as usize - constants::WAVE_RAM_START],
+            _ => 0xFF
+        }
+    }
+
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        match addr as usize {
+            constants::WAVE_RAM_START..=constants::WAVE_RAM_END => {
+                self.channel3.ram[addr as usize - constants::WAVE_RAM_START] = data;
+                return;
+            },
+            constants::NR52 => {
+                self.set_power(data & 0x80 > 0);
+                return;
+            },
+            _ => ()
+        }
+        if !self.powered { return; }
+        match addr as usize {
+            constants::NR10..=constants::NR51 => self.registers[addr as usize - constants::NR10] = data,
+            _ => return
+        }
+        match addr as usize {
+            constants::NR10..=constants::NR14 => self.channel1.write(addr as usize - constants::NR10, data),
+            constants::NR21..=constants::NR24 => self.channel2.write(addr as usize - constants::NR21 + 1, data),
+            constants::NR30..=constants::NR34 => self.channel3.write(addr as usize - constants::NR30, data),
+            constants::NR41..=constants::NR44 => self.channel4.write(addr as usize - constants::NR41, data),
+            _ => ()
+        }
+    }
+
+    //Powering off clears NR10-NR51 and resets every channel, wave RAM keeps its contents
+    fn set_power(&mut self, powered: bool) {
+        if self.powered && !powered {
+            let wave_ram = self.channel3.ram;
+            self.channel1 = Pulse::new(true);
+            self.channel2 = Pulse::new(false);
+            self.channel3 = Wave::new();
+            self.channel3.ram = wave_ram;
+            self.channel4 = Noise::new();
+            self.registers = [0; REGISTER_COUNT];
+        }
+        if !self.powered && powered {
+            self.sequencer_timer = 0;
+            self.sequencer_step = 0;
+        }
+        self.powered = powered;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(apu: &mut APU, ticks: u32) {
+        for _ in 0..ticks {
+            apu.tick();
+        }
+    }
+
+    //Highest DAC output of a channel over 32 clocks, enough for a pulse at period 2047 to reach its high duty steps
+    fn peak(apu: &mut APU, channel: usize) -> f32 {
+        let mut peak = -1.0;
+        for _ in 0..32 {
+            apu.tick();
+            peak = f32::max(peak, apu.channel_outputs()[channel]);
+        }
+        return peak;
+    }
+
+    //Noise output after each LFSR clock, with the divider code 0 and shift 0 the LFSR is clocked every 8 clocks
+    fn noise_bits(apu: &mut APU, nr43: u8, count: usize) -> Vec<bool> {
+        apu.write_register(constants::NR42 as u16, 0xF0);
+        apu.write_register(constants::NR43 as u16, nr43);
+        apu.write_register(constants::NR44 as u16, 0x80);
+        let mut bits = Vec::new();
+        for _ in 0..count {
+            run(apu, 8);
+            bits.push(apu.channel_outputs()[3] > 0.0);
+        }
+        return bits;
+    }
+
+    #[test]
+    fn sweep_overflow_on_trigger_disables_channel1() {
+        let mut apu = APU::new();
+        apu.write_register(constants::NR10 as u16, 0x11);
+        apu.write_register(constants::NR12 as u16, 0xF0);
+        apu.write_register(constants::NR13 as u16, 0xFF);
+        apu.write_register(constants::NR14 as u16, 0x87);
+        assert_eq!(apu.read_register(constants::NR52 as u16) & 0b1, 0);
+    }
+
+    #[test]
+    fn sweep_overflow_on_sweep_clock_disables_channel1() {
+        let mut apu = APU::new();
+        apu.write_register(constants::NR10 as u16, 0x11);
+        apu.write_register(constants::NR12 as u16, 0xF0);
+        apu.write_register(constants::NR13 as u16, 0x00);
+        apu.write_register(constants::NR14 as u16, 0x84);
+        //1024 + 512 fits, the check after writing 1536 back sees 1536 + 768 and disables the channel
+        run(&mut apu, 2 * constants::FRAME_SEQUENCER_CYCLES);
+        assert_eq!(apu.read_register(constants::NR52 as u16) & 0b1, 0b1);
+        run(&mut apu, constants::FRAME_SEQUENCER_CYCLES);
+        assert_eq!(apu.read_register(constants::NR52 as u16) & 0b1, 0);
+    }
+
+    #[test]
+    fn lfsr_7_bit_mode_repeats_every_127_clocks() {
+        let mut apu = APU::new();
+        let bits = noise_bits(&mut apu, 0x08, 254);
+        assert_eq!(bits[..127], bits[127..]);
+        assert!(bits.contains(&true) && bits.contains(&false));
+    }
+
+    #[test]
+    fn lfsr_15_bit_mode_does_not_repeat_after_127_clocks() {
+        let mut apu = APU::new();
+        let bits = noise_bits(&mut apu, 0x00, 254);
+        assert_ne!(bits[..127], bits[127..]);
+    }
+
+    #[test]
+    fn length_counter_silences_channel_when_enabled() {
+        let mut apu = APU::new();
+        apu.write_register(constants::NR21 as u16, 0x3F);
+        apu.write_register(constants::NR22 as u16, 0xF0);
+        apu.write_register(constants::NR24 as u16, 0xC0);
+        assert_eq!(apu.read_register(constants::NR52 as u16) & 0b10, 0b10);
+        run(&mut apu, constants::FRAME_SEQUENCER_CYCLES);
+        assert_eq!(apu.read_register(constants::NR52 as u16) & 0b10, 0);
+
+        //Without the length enable bit the channel keeps playing
+        apu.write_register(constants::NR21 as u16, 0x3F);
+        apu.write_register(constants::NR24 as u16, 0x80);
+        run(&mut apu, 4 * constants::FRAME_SEQUENCER_CYCLES);
+        assert_eq!(apu.read_register(constants::NR52 as u16) & 0b10, 0b10);
+    }
+
+    #[test]
+    fn envelope_steps_volume_at_64_hz() {
+        let mut apu = APU::new();
+        apu.write_register(constants::NR21 as u16, 0x80);
+        apu.write_register(constants::NR22 as u16, 0xF1);
+        apu.write_register(constants::NR23 as u16, 0xFF);
+        apu.write_register(constants::NR24 as u16, 0x87);
+        assert_eq!(peak(&mut apu, 1), 1.0);
+        //The envelope is clocked on sequencer step 7, the 8th step after power on
+        run(&mut apu, 8 * constants::FRAME_SEQUENCER_CYCLES - 32);
+        assert_eq!(peak(&mut apu, 1), APU::dac(14, true));
+        run(&mut apu, 8 * constants::FRAME_SEQUENCER_CYCLES - 32);
+        assert_eq!(peak(&mut apu, 1), APU::dac(13, true));
+    }
+
+    #[test]
+    fn power_off_clears_registers_and_ignores_writes() {
+        let mut apu = APU::new();
+        apu.write_register(constants::NR11 as u16, 0xC0);
+        apu.write_register(constants::NR50 as u16, 0x77);
+        apu.write_register(constants::NR42 as u16, 0xF0);
+        apu.write_register(constants::NR44 as u16, 0x80);
+        apu.write_register(constants::WAVE_RAM_START as u16, 0x5A);
+        apu.write_register(constants::NR52 as u16, 0x00);
+        assert_eq!(apu.read_register(constants::NR11 as u16), 0x3F);
+        assert_eq!(apu.read_register(constants::NR50 as u16), 0x00);
+        assert_eq!(apu.read_register(constants::NR52 as u16), 0x70);
+        assert_eq!(apu.read_register(constants::WAVE_RAM_START as u16), 0x5A);
+
+        apu.write_register(constants::NR50 as u16, 0x77);
+        assert_eq!(apu.read_register(constants::NR50 as u16), 0x00);
+        apu.write_register(constants::NR52 as u16, 0x80);
+        apu.write_register(constants::NR50 as u16, 0x77);
+        assert_eq!(apu.read_register(constants::NR50 as u16), 0x77);
+    }
+
+    #[test]
+    fn registers_read_back_with_unused_bits_set() {
+        let mut apu = APU::new();
+        for addr in constants::NR10..constants::NR52 {
+            apu.write_register(addr as u16, 0x00);
+            assert_eq!(apu.read_register(addr as u16), READ_MASKS[addr - constants::NR10], "{:#06x}", addr);
+        }
+        apu.write_register(constants::NR11 as u16, 0xC0);
+        assert_eq!(apu.read_register(constants::NR11 as u16), 0xFF);
+        apu.write_register(constants::NR32 as u16, 0x60);
+        assert_eq!(apu.read_register(constants::NR32 as u16), 0xFF);
+        assert_eq!(apu.read_register(constants::NR52 as u16), 0xF0);
+        assert_eq!(apu.read_register(0xFF27), 0xFF);
+    }
+
+    //The device accepts the header but fails once the buffered samples are flushed to it
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn failed_write_stops_the_recording_and_keeps_the_error() {
+        let mut apu = APU::new();
+        apu.start_recording(Path::new("/dev/full"), false).unwrap();
+        run(&mut apu, 4_194_304 / 8);
+        assert!(!apu.recording());
+        assert!(apu.recording_error().is_some());
+
+        let path = std::env::temp_dir().join(format!("gameboyo_apu_{}.wav", std::process::id()));
+        apu.start_recording(&path, false).unwrap();
+        assert!(apu.recording_error().is_none());
+        apu.stop_recording().unwrap();
+        assert!(apu.recording_error().is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
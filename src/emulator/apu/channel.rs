@@ -0,0 +1,92 @@
+/*
+Building blocks shared by the sound channels: https://gbdev.io/pandocs/Audio_details.html
+
+LengthCounter: silences the channel after (max - loaded value) ticks of the 256 Hz length clock, if enabled
+Envelope: steps the volume up or down by one every `period` ticks of the 64 Hz envelope clock
+ */
+pub struct LengthCounter {
+    pub enabled: bool,
+    counter: u16,
+    max: u16,
+}
+
+impl LengthCounter {
+    pub fn new(max: u16) -> Self {
+        Self {
+            enabled: false,
+            counter: 0,
+            max,
+        }
+    }
+
+    pub fn load(&mut self, value: u8) {
+        self.counter = self.max - value as u16;
+    }
+
+    //A trigger with an expired counter reloads it with the maximum length
+    pub fn trigger(&mut self) {
+        if self.counter == 0 {
+            self.counter = self.max;
+        }
+    }
+
+    //Returns true when the counter expires and the channel should be disabled
+    pub fn clock(&mut self) -> bool {
+        if !self.enabled || self.counter == 0 { return false; }
+        self.counter -= 1;
+        return self.counter == 0;
+    }
+}
+
+/*
+NRx2 layout: initial volume (bits 4-7), direction (bit 3, 1 = increase), period (bits 0-2, 0 = stopped).
+The channel DAC is powered whenever the upper 5 bits are not all 0.
+ */
+pub struct Envelope {
+    register: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    pub fn new() -> Self {
+        Self {
+            register: 0,
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.register = data;
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.register & 0b11111000 > 0
+    }
+
+    fn period(&self) -> u8 {
+        self.register & 0b111
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.register >> 4;
+        self.timer = self.period();
+    }
+
+    pub fn clock(&mut self) {
+        if self.period() == 0 { return; }
+        if self.timer > 0 { self.timer -= 1; }
+        if self.timer > 0 { return; }
+        self.timer = self.period();
+        if self.register & 0b00001000 > 0 {
+            if self.volume < 15 { self.volume += 1; }
+        } else {
+            if self.volume > 0 { self.volume -= 1; }
+        }
+    }
+
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+}
@@ -0,0 +1,7 @@
+pub mod apu;
+pub mod channel;
+pub mod pulse;
+pub mod wave;
+pub mod noise;
+pub mod resampler;
+pub mod wav;
@@ -0,0 +1,98 @@
+/*
+Noise channel 4: https://gbdev.io/pandocs/Audio_Registers.html#sound-channel-4--noise
+
+Registers (index relative to NR41):
+    0: initial length (bits 0-5)
+    1: volume envelope
+    2: clock shift (bits 4-7), LFSR width (bit 3, 1 = 7-bit), clock divider (bits 0-2)
+    3: trigger (bit 7), length enable (bit 6)
+
+The 15-bit LFSR is clocked every (divider << shift) clocks, where a divider code of 0 means 8 and n means 16 * n.
+Each clock XORs bits 0 and 1 into bit 14 (and bit 6 in 7-bit mode) and shifts right. The output is the inverse of bit 0.
+ */
+use crate::emulator::apu::channel::{Envelope, LengthCounter};
+
+pub struct Noise {
+    pub enabled: bool,
+    register: u8,
+    timer: u32,
+    lfsr: u16,
+    pub length: LengthCounter,
+    envelope: Envelope,
+}
+
+impl Noise {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            register: 0,
+            timer: 0,
+            lfsr: 0x7FFF,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+        }
+    }
+
+    pub fn write(&mut self, register: usize, data: u8) {
+        match register {
+            0 => self.length.load(data & 0x3F),
+            1 => {
+                self.envelope.write(data);
+                if !self.envelope.dac_enabled() { self.enabled = false; }
+            },
+            2 => self.register = data,
+            3 => {
+                self.length.enabled = data & 0x40 > 0;
+                if data & 0x80 > 0 { self.trigger(); }
+            },
+            _ => ()
+        }
+    }
+
+    fn period(&self) -> u32 {
+        let divider = match self.register & 0b111 {
+            0 => 8,
+            x => 16 * x as u32,
+        };
+        return divider << (self.register >> 4);
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger();
+        self.timer = self.period();
+        self.envelope.trigger();
+        self.lfsr = 0x7FFF;
+    }
+
+    pub fn tick(&mut self) {
+        if self.timer > 0 { self.timer -= 1; }
+        if self.timer > 0 { return; }
+        self.timer = self.period();
+        //Shifts of 14 and 15 stop the LFSR
+        if self.register >> 4 >= 14 { return; }
+        let bit = (self.lfsr & 0b1) ^ ((self.lfsr >> 1) & 0b1);
+        self.lfsr = (self.lfsr >> 1) | (bit << 14);
+        if self.register & 0b00001000 > 0 {
+            self.lfsr = (self.lfsr & !(0b1 << 6)) | (bit << 6);
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() { self.enabled = false; }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+
+    //Digital output, 0-15
+    pub fn output(&self) -> u8 {
+        if !self.enabled || self.lfsr & 0b1 > 0 { return 0; }
+        return self.envelope.volume();
+    }
+}
@@ -0,0 +1,200 @@
+/*
+Pulse channels 1 and 2: https://gbdev.io/pandocs/Audio_Registers.html#sound-channel-1--pulse-with-wavelength-sweep
+
+Registers (index relative to NR10 / NR20):
+    0: sweep pace (bits 4-6), direction (bit 3, 1 = decrease), step (bits 0-2). Channel 1 only
+    1: duty cycle (bits 6-7), initial length (bits 0-5)
+    2: volume envelope
+    3: period, low 8 bits
+    4: trigger (bit 7), length enable (bit 6), period high 3 bits (bits 0-2)
+
+The waveform advances one of 8 duty steps every (2048 - period) * 4 clocks.
+ */
+use crate::emulator::apu::channel::{Envelope, LengthCounter};
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1], //12.5%
+    [1, 0, 0, 0, 0, 0, 0, 1], //25%
+    [1, 0, 0, 0, 0, 1, 1, 1], //50%
+    [0, 1, 1, 1, 1, 1, 1, 0], //75%
+];
+
+/*
+Frequency sweep unit (channel 1 only)
+shadow: copy of the period the sweep calculations work from
+negated: a calculation in decrease mode happened since the last trigger
+ */
+struct Sweep {
+    register: u8,
+    enabled: bool,
+    shadow: u16,
+    timer: u8,
+    negated: bool,
+}
+
+impl Sweep {
+    fn new() -> Self {
+        Self {
+            register: 0,
+            enabled: false,
+            shadow: 0,
+            timer: 0,
+            negated: false,
+        }
+    }
+
+    fn pace(&self) -> u8 {
+        (self.register >> 4) & 0b111
+    }
+
+    fn decrease(&self) -> bool {
+        self.register & 0b00001000 > 0
+    }
+
+    fn step(&self) -> u8 {
+        self.register & 0b111
+    }
+
+    fn reload_timer(&mut self) {
+        //A pace of 0 is treated as 8 by the timer
+        self.timer = if self.pace() == 0 { 8 } else { self.pace() };
+    }
+
+    fn calculate(&mut self) -> u16 {
+        let delta = self.shadow >> self.step();
+        if self.decrease() {
+            self.negated = true;
+            return self.shadow - delta;
+        }
+        return self.shadow + delta;
+    }
+}
+
+pub struct Pulse {
+    pub enabled: bool,
+    duty: u8,
+    duty_step: usize,
+    period: u16,
+    timer: u16,
+    pub length: LengthCounter,
+    envelope: Envelope,
+    sweep: Option<Sweep>,
+}
+
+impl Pulse {
+    //Channel 1 has the sweep unit, channel 2 doesn't
+    pub fn new(has_sweep: bool) -> Self {
+        Self {
+            enabled: false,
+            duty: 0,
+            duty_step: 0,
+            period: 0,
+            timer: 0,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            sweep: if has_sweep { Some(Sweep::new()) } else { None },
+        }
+    }
+
+    pub fn write(&mut self, register: usize, data: u8) {
+        match register {
+            0 => match &mut self.sweep {
+                Some(sweep) => {
+                    //Leaving decrease mode after a decrease calculation disables the channel
+                    let was_decrease = sweep.decrease();
+                    sweep.register = data;
+                    if was_decrease && !sweep.decrease() && sweep.negated {
+                        self.enabled = false;
+                    }
+                },
+                None => ()
+            },
+            1 => {
+                self.duty = data >> 6;
+                self.length.load(data & 0x3F);
+            },
+            2 => {
+                self.envelope.write(data);
+                if !self.envelope.dac_enabled() { self.enabled = false; }
+            },
+            3 => self.period = (self.period & 0x700) | data as u16,
+            4 => {
+                self.period = (self.period & 0xFF) | (((data & 0b111) as u16) << 8);
+                self.length.enabled = data & 0x40 > 0;
+                if data & 0x80 > 0 { self.trigger(); }
+            },
+            _ => ()
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger();
+        self.timer = (2048 - self.period) * 4;
+        self.envelope.trigger();
+        let mut overflow = false;
+        match &mut self.sweep {
+            Some(sweep) => {
+                sweep.shadow = self.period;
+                sweep.negated = false;
+                sweep.reload_timer();
+                sweep.enabled = sweep.pace() != 0 || sweep.step() != 0;
+                if sweep.step() != 0 {
+                    overflow = sweep.calculate() > 2047;
+                }
+            },
+            None => ()
+        }
+        if overflow { self.enabled = false; }
+    }
+
+    //Advance the waveform by one clock
+    pub fn tick(&mut self) {
+        if self.timer > 0 { self.timer -= 1; }
+        if self.timer > 0 { return; }
+        self.timer = (2048 - self.period) * 4;
+        self.duty_step = (self.duty_step + 1) % 8;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() { self.enabled = false; }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    /*
+    128 Hz sweep clock. The new period is written back (and checked for overflow a second time) only when the step is
+    nonzero, any result above 2047 disables the channel.
+     */
+    pub fn clock_sweep(&mut self) {
+        let sweep = match &mut self.sweep {
+            Some(x) => x,
+            None => return,
+        };
+        if sweep.timer > 0 { sweep.timer -= 1; }
+        if sweep.timer > 0 { return; }
+        sweep.reload_timer();
+        if !sweep.enabled || sweep.pace() == 0 { return; }
+        let period = sweep.calculate();
+        if period > 2047 {
+            self.enabled = false;
+            return;
+        }
+        if sweep.step() == 0 { return; }
+        sweep.shadow = period;
+        self.period = period;
+        if sweep.calculate() > 2047 { self.enabled = false; }
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+
+    //Digital output, 0-15
+    pub fn output(&self) -> u8 {
+        if !self.enabled { return 0; }
+        return DUTY_TABLE[self.duty as usize][self.duty_step] * self.envelope.volume();
+    }
+}
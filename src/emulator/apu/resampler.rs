@@ -0,0 +1,259 @@
+/*
+Sample-rate conversion and output filtering for the APU stream. Neither depends on an audio device, so both can be
+driven with a synthetic stream.
+
+Resampler: band-limited (windowed sinc) conversion from the APU's native rate to an output rate. The kernel's cutoff
+sits just below the Nyquist frequency of the lower of the two rates, so the pulse channels' harmonics above it are
+filtered out instead of aliasing back into the audible range.
+
+DcBlocker: the high-pass filter formed by the capacitor on the real console's audio output. Without it a channel
+whose DAC is on but silent sits at -1.0 and produces a constant offset (and a pop when it turns off).
+ */
+use std::collections::VecDeque;
+
+use crate::emulator::constants;
+
+//Zero crossings of the sinc on each side of the kernel centre
+const ZERO_CROSSINGS: f64 = 8.0;
+//Kernel lookup table resolution, entries per input sample
+const KERNEL_PHASES: usize = 128;
+//Fraction of the Nyquist frequency kept by the filter
+const PASSBAND: f64 = 0.9;
+
+pub struct Resampler {
+    input_rate: u32,
+    output_rate: u32,
+    step: f64,
+    step_scale: f64,
+    position: f64,
+    half_width: usize,
+    kernel: Vec<f32>,
+    history: VecDeque<[f32; 2]>,
+}
+
+impl Resampler {
+    pub fn new(input_rate: u32, output_rate: u32) -> Self {
+        let cutoff = 0.5 * PASSBAND * f64::min(1.0, output_rate as f64 / input_rate as f64);
+        let half_width = (ZERO_CROSSINGS / (2.0 * cutoff)).ceil() as usize;
+        let mut resampler = Self {
+            input_rate,
+            output_rate,
+            step: input_rate as f64 / output_rate as f64,
+            step_scale: 1.0,
+            position: 0.0,
+            half_width,
+            kernel: Resampler::build_kernel(cutoff, half_width),
+            history: VecDeque::new(),
+        };
+        resampler.reset();
+        return resampler;
+    }
+
+    /*
+    Blackman-windowed sinc sampled at KERNEL_PHASES points per input sample, covering -half_width..=half_width.
+    cutoff is relative to the input rate (0.5 = input Nyquist).
+     */
+    fn build_kernel(cutoff: f64, half_width: usize) -> Vec<f32> {
+        let size = 2 * half_width * KERNEL_PHASES + 1;
+        let mut kernel: Vec<f32> = Vec::with_capacity(size);
+        for i in 0..size {
+            let x = i as f64 / KERNEL_PHASES as f64 - half_width as f64;
+            let sinc = if x == 0.0 {
+                2.0 * cutoff
+            } else {
+                (2.0 * std::f64::consts::PI * cutoff * x).sin() / (std::f64::consts::PI * x)
+            };
+            let n = i as f64 / (size - 1) as f64;
+            let window = 0.42 - 0.5 * (2.0 * std::f64::consts::PI * n).cos() + 0.08 * (4.0 * std::f64::consts::PI * n).cos();
+            kernel.push((sinc * window) as f32);
+        }
+        return kernel;
+    }
+
+    //Kernel value at offset x (in input samples) from the centre, linearly interpolated between table entries
+    fn kernel_at(&self, x: f64) -> f32 {
+        let index = (x + self.half_width as f64) * KERNEL_PHASES as f64;
+        if index < 0.0 { return 0.0; }
+        let whole = index.floor() as usize;
+        if whole + 1 >= self.kernel.len() { return 0.0; }
+        let fraction = (index - whole as f64) as f32;
+        return self.kernel[whole] + (self.kernel[whole + 1] - self.kernel[whole]) * fraction;
+    }
+
+    //Drop all buffered input, the next output starts from silence
+    pub fn reset(&mut self) {
+        self.history.clear();
+        for _ in 0..self.half_width {
+            self.history.push_back([0.0, 0.0]);
+        }
+        self.position = self.half_width as f64 - 1.0;
+    }
+
+    pub fn input_rate(&self) -> u32 {
+        self.input_rate
+    }
+
+    pub fn output_rate(&self) -> u32 {
+        self.output_rate
+    }
+
+    /*
+    Stretch or squeeze the output slightly, e.g. 1.001 produces 0.1% fewer output samples.
+    Used to steer a playback buffer's fill level without audible pitch changes.
+     */
+    pub fn set_step_scale(&mut self, scale: f64) {
+        self.step_scale = scale;
+    }
+
+    //Number of output samples produced per input sample at the current ratio
+    pub fn ratio(&self) -> f64 {
+        1.0 / (self.step * self.step_scale)
+    }
+
+    pub fn push(&mut self, sample: [f32; 2]) {
+        self.history.push_back(sample);
+    }
+
+    /*
+    Next output sample, or None until enough input has been pushed to fill the kernel around it.
+    Output at time t (in input samples) is the sum over the inputs within half_width of t, weighted by the kernel.
+    Weights are normalised so a constant input passes through at exactly the same level.
+     */
+    pub fn pull(&mut self) -> Option<[f32; 2]> {
+        let centre = self.position.floor() as usize;
+        if centre + self.half_width >= self.history.len() { return None; }
+        let mut left = 0.0;
+        let mut right = 0.0;
+        let mut total = 0.0;
+        for i in (centre + 1 - self.half_width)..=(centre + self.half_width) {
+            let weight = self.kernel_at(i as f64 - self.position);
+            left += self.history[i][0] * weight;
+            right += self.history[i][1] * weight;
+            total += weight;
+        }
+        self.position += self.step * self.step_scale;
+        //Discard inputs that no later output can reach
+        let consumed = (self.position.floor() as usize + 1).saturating_sub(self.half_width);
+        let consumed = std::cmp::min(consumed, self.history.len());
+        self.history.drain(..consumed);
+        self.position -= consumed as f64;
+        if total == 0.0 { return Some([0.0, 0.0]); }
+        return Some([left / total, right / total]);
+    }
+
+    //Push a block of input and return every output sample it completes
+    pub fn process(&mut self, input: &[[f32; 2]]) -> Vec<[f32; 2]> {
+        let mut output: Vec<[f32; 2]> = Vec::with_capacity((input.len() as f64 * self.ratio()) as usize + 1);
+        for sample in input {
+            self.push(*sample);
+            loop {
+                match self.pull() {
+                    Some(x) => output.push(x),
+                    None => break,
+                }
+            }
+        }
+        return output;
+    }
+}
+
+/*
+First-order high-pass: out = in - capacitor, capacitor = in - out * charge.
+The charge factor matches the console's output capacitor (0.999958 per 4 MHz clock) at the given sample rate.
+ */
+pub struct DcBlocker {
+    charge: f32,
+    capacitor: [f32; 2],
+}
+
+impl DcBlocker {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            charge: 0.999958f64.powf(constants::CLOCK_HZ as f64 / sample_rate as f64) as f32,
+            capacitor: [0.0, 0.0],
+        }
+    }
+
+    pub fn process(&mut self, sample: [f32; 2]) -> [f32; 2] {
+        let mut output = [0.0, 0.0];
+        for side in 0..2 {
+            output[side] = sample[side] - self.capacitor[side];
+            self.capacitor[side] = sample[side] - output[side] * self.charge;
+        }
+        return output;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT_RATE: u32 = constants::APU_SAMPLE_RATE;
+    const OUTPUT_RATE: u32 = 44100;
+
+    //One second of a stereo sine at `frequency` Hz, sampled at the APU rate
+    fn sine(frequency: f64, amplitude: f32) -> Vec<[f32; 2]> {
+        (0..INPUT_RATE).map(|i| {
+            let value = amplitude * (2.0 * std::f64::consts::PI * frequency * i as f64 / INPUT_RATE as f64).sin() as f32;
+            [value, -value]
+        }).collect()
+    }
+
+    //Largest absolute sample on the left side, ignoring the filter's start-up
+    fn peak(samples: &[[f32; 2]]) -> f32 {
+        samples[samples.len() / 4..].iter().fold(0.0, |peak, x| f32::max(peak, x[0].abs()))
+    }
+
+    #[test]
+    fn output_length_follows_the_rate_ratio() {
+        let mut resampler = Resampler::new(INPUT_RATE, OUTPUT_RATE);
+        let output = resampler.process(&vec![[0.0, 0.0]; INPUT_RATE as usize]);
+        //Everything but the last half kernel of input has been turned into output
+        let expected = OUTPUT_RATE as f64 * (INPUT_RATE as f64 - resampler.half_width as f64) / INPUT_RATE as f64;
+        assert!((output.len() as f64 - expected).abs() <= 2.0, "{} samples, expected about {}", output.len(), expected);
+    }
+
+    #[test]
+    fn step_scale_stretches_the_output() {
+        let mut resampler = Resampler::new(INPUT_RATE, OUTPUT_RATE);
+        resampler.set_step_scale(1.01);
+        let output = resampler.process(&vec![[0.0, 0.0]; INPUT_RATE as usize]);
+        assert!(output.len() < 44000 && output.len() > 43500, "{} samples", output.len());
+    }
+
+    #[test]
+    fn constant_input_passes_at_the_same_level() {
+        let mut resampler = Resampler::new(INPUT_RATE, OUTPUT_RATE);
+        let output = resampler.process(&vec![[0.5, -0.25]; INPUT_RATE as usize / 10]);
+        for sample in &output[output.len() / 2..] {
+            assert!((sample[0] - 0.5).abs() < 1e-4 && (sample[1] + 0.25).abs() < 1e-4, "{:?}", sample);
+        }
+    }
+
+    #[test]
+    fn passband_sine_keeps_its_amplitude() {
+        let mut resampler = Resampler::new(INPUT_RATE, OUTPUT_RATE);
+        let output = resampler.process(&sine(1000.0, 0.8));
+        let level = peak(&output);
+        assert!((level - 0.8).abs() < 0.02, "peak {}", level);
+    }
+
+    #[test]
+    fn sine_above_output_nyquist_is_removed() {
+        let mut resampler = Resampler::new(INPUT_RATE, OUTPUT_RATE);
+        let output = resampler.process(&sine(30000.0, 0.8));
+        let level = peak(&output);
+        assert!(level < 0.01, "peak {}", level);
+    }
+
+    #[test]
+    fn dc_blocker_removes_offset_but_keeps_signal() {
+        let mut blocker = DcBlocker::new(INPUT_RATE);
+        let input: Vec<[f32; 2]> = sine(1000.0, 0.5).iter().map(|x| [x[0] - 1.0, x[1] - 1.0]).collect();
+        let output: Vec<[f32; 2]> = input.iter().map(|x| blocker.process(*x)).collect();
+        let tail = &output[output.len() / 2..];
+        let mean = tail.iter().map(|x| x[0]).sum::<f32>() / tail.len() as f32;
+        assert!(mean.abs() < 0.01, "mean {}", mean);
+        assert!((peak(tail) - 0.5).abs() < 0.02, "peak {}", peak(tail));
+    }
+}
@@ -0,0 +1,219 @@
+/*
+Audio capture to 16-bit PCM .wav files: http://soundfile.sapp.org/doc/WaveFormat/
+
+WavWriter: writes the RIFF header with placeholder sizes up front and patches them in when the file is finished.
+Recorder: converts the APU's native-rate output to WAV_SAMPLE_RATE (DC blocker + band-limited resampler, the same
+path the speaker output takes) and writes the stereo mix. Optionally it also writes one mono file per channel
+(pulse 1, pulse 2, wave, noise) taken straight from each channel's DAC, before panning and master volume.
+ */
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::emulator::constants;
+use crate::emulator::apu::resampler::{DcBlocker, Resampler};
+
+const WAV_HEADER_SIZE: u32 = 44;
+const STEM_NAMES: [&str; 4] = ["pulse1", "pulse2", "wave", "noise"];
+
+pub struct WavWriter {
+    file: BufWriter<File>,
+    channels: u16,
+    frames: u32,
+}
+
+impl WavWriter {
+    pub fn new(path: &Path, channels: u16, sample_rate: u32) -> std::io::Result<Self> {
+        let mut writer = Self {
+            file: BufWriter::new(File::create(path)?),
+            channels,
+            frames: 0,
+        };
+        writer.write_header(sample_rate)?;
+        return Ok(writer);
+    }
+
+    fn write_header(&mut self, sample_rate: u32) -> std::io::Result<()> {
+        let block_align = self.channels * 2;
+        self.file.write_all(b"RIFF")?;
+        self.file.write_all(&(WAV_HEADER_SIZE - 8).to_le_bytes())?;
+        self.file.write_all(b"WAVE")?;
+        self.file.write_all(b"fmt ")?;
+        self.file.write_all(&16u32.to_le_bytes())?;
+        self.file.write_all(&1u16.to_le_bytes())?; //PCM
+        self.file.write_all(&self.channels.to_le_bytes())?;
+        self.file.write_all(&sample_rate.to_le_bytes())?;
+        self.file.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        self.file.write_all(&block_align.to_le_bytes())?;
+        self.file.write_all(&16u16.to_le_bytes())?; //bits per sample
+        self.file.write_all(b"data")?;
+        self.file.write_all(&0u32.to_le_bytes())?;
+        return Ok(());
+    }
+
+    //One sample per channel, each in -1.0..=1.0 (values outside are clipped)
+    pub fn write_frame(&mut self, frame: &[f32]) -> std::io::Result<()> {
+        for sample in frame.iter().take(self.channels as usize) {
+            let value = (sample.max(-1.0).min(1.0) * i16::MAX as f32) as i16;
+            self.file.write_all(&value.to_le_bytes())?;
+        }
+        self.frames += 1;
+        return Ok(());
+    }
+
+    //Fill in the RIFF and data chunk sizes now that the length is known
+    pub fn finish(mut self) -> std::io::Result<()> {
+        let data_size = self.frames * self.channels as u32 * 2;
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&(WAV_HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_all(&data_size.to_le_bytes())?;
+        self.file.flush()?;
+        return Ok(());
+    }
+}
+
+/*
+One output file together with the filters that convert APU samples for it.
+Mono stems run through the stereo filters with the same value on both sides.
+ */
+struct Track {
+    writer: WavWriter,
+    dc_blocker: DcBlocker,
+    resampler: Resampler,
+}
+
+impl Track {
+    fn new(path: &Path, channels: u16) -> std::io::Result<Self> {
+        Ok(Self {
+            writer: WavWriter::new(path, channels, constants::WAV_SAMPLE_RATE)?,
+            dc_blocker: DcBlocker::new(constants::APU_SAMPLE_RATE),
+            resampler: Resampler::new(constants::APU_SAMPLE_RATE, constants::WAV_SAMPLE_RATE),
+        })
+    }
+
+    fn write(&mut self, sample: [f32; 2]) -> std::io::Result<()> {
+        self.resampler.push(self.dc_blocker.process(sample));
+        loop {
+            match self.resampler.pull() {
+                Some(x) => self.writer.write_frame(&x)?,
+                None => return Ok(()),
+            }
+        }
+    }
+}
+
+pub struct Recorder {
+    mix: Track,
+    stems: Vec<Track>,
+}
+
+impl Recorder {
+    /*
+    Starts writing the mix to `path`. With `stems` set, each channel also goes to <name>_pulse1.wav, <name>_pulse2.wav,
+    <name>_wave.wav and <name>_noise.wav next to it.
+     */
+    pub fn new(path: &Path, stems: bool) -> std::io::Result<Self> {
+        let mut recorder = Self {
+            mix: Track::new(path, 2)?,
+            stems: Vec::new(),
+        };
+        if stems {
+            for name in STEM_NAMES.iter() {
+                recorder.stems.push(Track::new(&Recorder::stem_path(path, name), 1)?);
+            }
+        }
+        return Ok(recorder);
+    }
+
+    fn stem_path(path: &Path, name: &str) -> PathBuf {
+        let stem = match path.file_stem() {
+            Some(x) => x.to_string_lossy().to_string(),
+            None => String::from("recording"),
+        };
+        return path.with_file_name(format!("{}_{}.wav", stem, name));
+    }
+
+    //Called once per APU output sample with the stereo mix and the four channel DAC outputs
+    pub fn write(&mut self, mix: [f32; 2], channels: [f32; 4]) -> std::io::Result<()> {
+        self.mix.write(mix)?;
+        for (track, output) in self.stems.iter_mut().zip(channels.iter()) {
+            track.write([*output, *output])?;
+        }
+        return Ok(());
+    }
+
+    pub fn finish(self) -> std::io::Result<()> {
+        self.mix.writer.finish()?;
+        for track in self.stems {
+            track.writer.finish()?;
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("gameboyo_{}_{}.wav", name, std::process::id()))
+    }
+
+    fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+    }
+
+    #[test]
+    fn finish_patches_the_riff_and_data_sizes() {
+        let path = temp_path("sizes");
+        let mut writer = WavWriter::new(&path, 2, constants::WAV_SAMPLE_RATE).unwrap();
+        for _ in 0..5 {
+            writer.write_frame(&[0.5, -0.5]).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let data_size = 5 * 2 * 2;
+        assert_eq!(bytes.len() as u32, WAV_HEADER_SIZE + data_size);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(read_u32(&bytes, 4), WAV_HEADER_SIZE - 8 + data_size);
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(read_u32(&bytes, 40), data_size);
+        assert_eq!(i16::from_le_bytes([bytes[44], bytes[45]]), i16::MAX / 2);
+    }
+
+    #[test]
+    fn empty_recording_has_a_valid_header() {
+        let path = temp_path("empty");
+        WavWriter::new(&path, 1, constants::WAV_SAMPLE_RATE).unwrap().finish().unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(bytes.len() as u32, WAV_HEADER_SIZE);
+        assert_eq!(read_u32(&bytes, 4), WAV_HEADER_SIZE - 8);
+        assert_eq!(read_u32(&bytes, 40), 0);
+    }
+
+    #[test]
+    fn stems_are_written_next_to_the_mix() {
+        let path = temp_path("stems");
+        let mut recorder = Recorder::new(&path, true).unwrap();
+        for _ in 0..constants::APU_SAMPLE_RATE / 100 {
+            recorder.write([0.25, 0.25], [0.1, 0.2, 0.3, 0.4]).unwrap();
+        }
+        recorder.finish().unwrap();
+
+        let mix = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mix_frames = read_u32(&mix, 40) / 4;
+        assert!(mix_frames > 0);
+        for name in STEM_NAMES.iter() {
+            let stem_path = Recorder::stem_path(&path, name);
+            let stem = std::fs::read(&stem_path).unwrap();
+            std::fs::remove_file(&stem_path).unwrap();
+            assert_eq!(read_u32(&stem, 40), mix_frames * 2, "{}", name);
+        }
+    }
+}
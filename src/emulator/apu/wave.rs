@@ -0,0 +1,98 @@
+/*
+Wave channel 3: https://gbdev.io/pandocs/Audio_Registers.html#sound-channel-3--wave-output
+
+Registers (index relative to NR30):
+    0: DAC enable (bit 7)
+    1: initial length (0-255)
+    2: output level (bits 5-6): 0 = mute, 1 = 100%, 2 = 50%, 3 = 25%
+    3: period, low 8 bits
+    4: trigger (bit 7), length enable (bit 6), period high 3 bits (bits 0-2)
+
+Wave RAM (0xFF30-0xFF3F) holds 32 4-bit samples, upper nibble first. The channel steps to the next sample every
+(2048 - period) * 2 clocks.
+ */
+use crate::emulator::apu::channel::LengthCounter;
+
+pub const WAVE_RAM_SIZE: usize = 16;
+
+pub struct Wave {
+    pub enabled: bool,
+    dac_enabled: bool,
+    output_level: u8,
+    period: u16,
+    timer: u16,
+    position: usize,
+    sample: u8,
+    pub length: LengthCounter,
+    pub ram: [u8; WAVE_RAM_SIZE],
+}
+
+impl Wave {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            dac_enabled: false,
+            output_level: 0,
+            period: 0,
+            timer: 0,
+            position: 0,
+            sample: 0,
+            length: LengthCounter::new(256),
+            ram: [0; WAVE_RAM_SIZE],
+        }
+    }
+
+    pub fn write(&mut self, register: usize, data: u8) {
+        match register {
+            0 => {
+                self.dac_enabled = data & 0x80 > 0;
+                if !self.dac_enabled { self.enabled = false; }
+            },
+            1 => self.length.load(data),
+            2 => self.output_level = (data >> 5) & 0b11,
+            3 => self.period = (self.period & 0x700) | data as u16,
+            4 => {
+                self.period = (self.period & 0xFF) | (((data & 0b111) as u16) << 8);
+                self.length.enabled = data & 0x40 > 0;
+                if data & 0x80 > 0 { self.trigger(); }
+            },
+            _ => ()
+        }
+    }
+
+    //Playback restarts at sample 1, sample 0 is only played once the position wraps around
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.length.trigger();
+        self.timer = (2048 - self.period) * 2;
+        self.position = 0;
+    }
+
+    pub fn tick(&mut self) {
+        if self.timer > 0 { self.timer -= 1; }
+        if self.timer > 0 { return; }
+        self.timer = (2048 - self.period) * 2;
+        self.position = (self.position + 1) % (WAVE_RAM_SIZE * 2);
+        let byte = self.ram[self.position / 2];
+        self.sample = if self.position % 2 == 0 { byte >> 4 } else { byte & 0x0F };
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() { self.enabled = false; }
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    //Digital output, 0-15
+    pub fn output(&self) -> u8 {
+        if !self.enabled { return 0; }
+        match self.output_level {
+            1 => self.sample,
+            2 => self.sample >> 1,
+            3 => self.sample >> 2,
+            _ => 0
+        }
+    }
+}
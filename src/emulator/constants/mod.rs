@@ -1,19 +1,123 @@
-#[macro_use]
-extern crate lazy_static;
-
-use std::collections::HashMap;
-
 //Timing constants
 pub const CLOCK_HZ: f32 = 4_194_304.0;
-pub const UI_FPS: f32 = 59.7275;
-pub const FPS_MILLIS: f32 = 1.0/UI_FPS * 1000;
-pub const CYCLES_PER_FRAME: usize = ((1.0/UI_FPS) * CLOCK_HZ).floor() as usize;
-pub const CYCLE_DURATION_MILLIS: f32 = (1.0/UI_FPS)/CYCLES_PER_FRAME * 1000;
+pub const SAVE_FLUSH_CYCLES: u32 = 4_194_304 * 5; //flush dirty battery RAM to disk every ~5 seconds of emulated time
 
 //Screen constants
 pub const SCREEN_X_DIM: u32 = 160; //unit = pixels
 pub const SCREEN_Y_DIM: u32 = 144; //unit = pixels
 
+//PPU timing
+pub const DOTS_PER_LINE: u16 = 456;
+pub const OAM_SCAN_END_DOT: u16 = 80;
+pub const DRAWING_END_DOT: u16 = 252;
+pub const LINES_PER_FRAME: u8 = 154;
+pub const LAST_LINE_LY_RESET_DOT: u16 = 4; //LY reads 0 from this dot of line 153 on
+pub const DOTS_PER_FRAME: u32 = DOTS_PER_LINE as u32 * LINES_PER_FRAME as u32;
+
+//I/O registers
+pub const P1: usize = 0xFF00;
+pub const SB: usize = 0xFF01;
+pub const SC: usize = 0xFF02;
+pub const DIV: usize = 0xFF04;
+pub const TIMA: usize = 0xFF05;
+pub const TMA: usize = 0xFF06;
+pub const TAC: usize = 0xFF07;
+pub const IF: usize = 0xFF0F;
+
+//PPU registers
+pub const LCDC: usize = 0xFF40;
+pub const STAT: usize = 0xFF41;
+pub const SCY: usize = 0xFF42;
+pub const SCX: usize = 0xFF43;
+pub const LY: usize = 0xFF44;
+pub const LYC: usize = 0xFF45;
+pub const BGP: usize = 0xFF47;
+pub const OBP0: usize = 0xFF48;
+pub const OBP1: usize = 0xFF49;
+pub const WY: usize = 0xFF4A;
+pub const WX: usize = 0xFF4B;
+
+//LCDC bits
+pub const LCDC_BG_ENABLE: u8 = 0b00000001;
+pub const LCDC_OBJ_ENABLE: u8 = 0b00000010;
+pub const LCDC_OBJ_SIZE: u8 = 0b00000100;
+pub const LCDC_BG_MAP: u8 = 0b00001000;
+pub const LCDC_TILE_DATA: u8 = 0b00010000;
+pub const LCDC_WINDOW_ENABLE: u8 = 0b00100000;
+pub const LCDC_WINDOW_MAP: u8 = 0b01000000;
+pub const LCDC_ENABLE: u8 = 0b10000000;
+
+//OAM DMA
+pub const DMA: usize = 0xFF46;
+pub const OAM_DMA_LENGTH: usize = 160;
+pub const OAM_DMA_STARTUP_CYCLES: u32 = 4;
+pub const OAM_DMA_CYCLES_PER_BYTE: u32 = 4;
+
+//STAT bits
+pub const STAT_COINCIDENCE: u8 = 0b00000100;
+pub const STAT_HBLANK_SOURCE: u8 = 0b00001000;
+pub const STAT_VBLANK_SOURCE: u8 = 0b00010000;
+pub const STAT_OAM_SOURCE: u8 = 0b00100000;
+pub const STAT_LYC_SOURCE: u8 = 0b01000000;
+
+//OAM
+pub const OAM_SIZE: usize = 0xA0;
+pub const OBJECT_COUNT: usize = 40;
+pub const OBJECTS_PER_LINE: usize = 10;
+pub const OBJ_PALETTE: u8 = 0b00010000;
+pub const OBJ_X_FLIP: u8 = 0b00100000;
+pub const OBJ_Y_FLIP: u8 = 0b01000000;
+pub const OBJ_BG_PRIORITY: u8 = 0b10000000;
+pub const OBJ_CGB_PALETTE: u8 = 0b00000111;
+pub const OBJ_BANK: u8 = 0b00001000;
+
+//CGB BG map attributes (VRAM bank 1)
+pub const BG_ATTR_PALETTE: u8 = 0b00000111;
+pub const BG_ATTR_BANK: u8 = 0b00001000;
+pub const BG_ATTR_X_FLIP: u8 = 0b00100000;
+pub const BG_ATTR_Y_FLIP: u8 = 0b01000000;
+pub const BG_ATTR_PRIORITY: u8 = 0b10000000;
+
+//APU registers
+pub const NR10: usize = 0xFF10;
+pub const NR11: usize = 0xFF11;
+pub const NR12: usize = 0xFF12;
+pub const NR13: usize = 0xFF13;
+pub const NR14: usize = 0xFF14;
+pub const NR21: usize = 0xFF16;
+pub const NR22: usize = 0xFF17;
+pub const NR23: usize = 0xFF18;
+pub const NR24: usize = 0xFF19;
+pub const NR30: usize = 0xFF1A;
+pub const NR31: usize = 0xFF1B;
+pub const NR32: usize = 0xFF1C;
+pub const NR33: usize = 0xFF1D;
+pub const NR34: usize = 0xFF1E;
+pub const NR41: usize = 0xFF20;
+pub const NR42: usize = 0xFF21;
+pub const NR43: usize = 0xFF22;
+pub const NR44: usize = 0xFF23;
+pub const NR50: usize = 0xFF24;
+pub const NR51: usize = 0xFF25;
+pub const NR52: usize = 0xFF26;
+pub const WAVE_RAM_START: usize = 0xFF30;
+pub const WAVE_RAM_END: usize = 0xFF3F;
+
+//APU timing
+pub const FRAME_SEQUENCER_CYCLES: u32 = 8192; //512 Hz
+pub const APU_CYCLES_PER_SAMPLE: u32 = 32;
+pub const APU_SAMPLE_RATE: u32 = 4_194_304 / 32;
+pub const APU_SAMPLE_BUFFER_LIMIT: usize = 4_194_304 / 32; //~1 second of samples kept if nobody drains the buffer
+pub const WAV_SAMPLE_RATE: u32 = 44100;
+
+//CGB palettes
+pub const BCPS: usize = 0xFF68;
+pub const BCPD: usize = 0xFF69;
+pub const OCPS: usize = 0xFF6A;
+pub const OCPD: usize = 0xFF6B;
+pub const PALETTE_RAM_SIZE: usize = 64;
+pub const PALETTE_AUTO_INCREMENT: u8 = 0b10000000;
+
 //Bit masks
 pub const SET_ZERO_FLAG_MASK: u16 = 0b0000000010000000;
 pub const UNSET_ZERO_FLAG_MASK: u16 = 0b1111111101111111;
@@ -67,17 +171,58 @@ pub const ONBOARD_WRAM_END: usize = 0xCFFF;
 pub const SWITCHABLE_WRAM_START: usize = 0xD000;
 pub const SWITCHABLE_WRAM_END: usize = 0xDFFF;
 pub const ECHO_RAM_LOW_START: usize = 0xE000;
-pub const ECHO_RAM_LOW_END: usize = 0xFDFF;
+pub const ECHO_RAM_LOW_END: usize = 0xEFFF;
 pub const ECHO_RAM_HIGH_START: usize = 0xF000;
 pub const ECHO_RAM_HIGH_END: usize = 0xFDFF;
 pub const OAM_START: usize = 0xFE00;
 pub const OAM_END: usize = 0xFE9F;
+pub const UNUSABLE_START: usize = 0xFEA0;
+pub const UNUSABLE_END: usize = 0xFEFF;
 pub const IO_REG_START: usize = 0xFF00;
 pub const IO_REG_END: usize = 0xFF7F;
 pub const HRAM_START: usize = 0xFF80;
 pub const HRAM_END: usize = 0xFFFE;
 pub const IE_REGISTER: usize = 0xFFFF;
 
+//Boot ROM
+pub const DMG_BOOT_ROM_SIZE: usize = 0x0100;
+pub const CGB_BOOT_ROM_SIZE: usize = 0x0900;
+pub const BOOT_ROM_LOW_END: usize = 0x00FF;
+pub const BOOT_ROM_HIGH_START: usize = 0x0200;
+pub const BOOT_ROM_HIGH_END: usize = 0x08FF;
+pub const BOOT_ROM_DISABLE: usize = 0xFF50;
+
+//CGB VRAM DMA
+pub const HDMA1: usize = 0xFF51;
+pub const HDMA2: usize = 0xFF52;
+pub const HDMA3: usize = 0xFF53;
+pub const HDMA4: usize = 0xFF54;
+pub const HDMA5: usize = 0xFF55;
+pub const VRAM_DMA_BLOCK_SIZE: u16 = 16;
+pub const VRAM_DMA_CYCLES_PER_BLOCK: u32 = 32;
+
+//CGB mode select, written by the CGB boot ROM
+pub const KEY0: usize = 0xFF4C;
+pub const KEY0_DMG_MODE: u8 = 0b00000100;
+
+//CGB speed switch
+pub const KEY1: usize = 0xFF4D;
+
+//Serial port
+pub const SC_TRANSFER: u8 = 0b10000000;
+pub const SC_FAST_CLOCK: u8 = 0b00000010; //CGB only
+pub const SC_INTERNAL_CLOCK: u8 = 0b00000001;
+pub const SERIAL_CLOCKS_PER_BIT: u32 = 512; //8192 Hz internal clock
+pub const SERIAL_FAST_CLOCKS_PER_BIT: u32 = 16; //262144 Hz internal clock
+pub const LINK_SYNC_TICKS: u32 = 1024; //longest TCP link sync window, under the 2048 ticks of the shortest 8192 Hz transfer
+pub const LINK_FAST_SYNC_TICKS: u32 = 64; //sync window once fast clock transfers (64 ticks at double speed) are in use
+pub const LINK_TIMEOUT_MILLIS: u64 = 3000; //a peer that doesn't answer for this long is treated as disconnected
+pub const LINK_PROTOCOL_VERSION: u8 = 1;
+
+//CGB banking registers
+pub const VBK: usize = 0xFF4F;
+pub const SVBK: usize = 0xFF70;
+
 //Size Constant
 pub const FOUR_KB: usize = 4096;
 pub const EIGHT_KB: usize = 8192;
@@ -98,6 +243,16 @@ pub const GBC_HL: u16 = 0x000D;
 pub const GBC_SP: u16 = 0xFFFE;
 pub const GBC_PC: u16 = 0x0100;
 pub const GBC_DIV: u16 = 0x1EA0;
+pub const POST_BOOT_LCDC: u8 = 0x91;
+pub const POST_BOOT_BGP: u8 = 0xFC;
+//Sound registers as the boot ROM leaves them. NRx4 are written without the trigger bit (they read back as 0xBF).
+pub const POST_BOOT_APU: [(usize, u8); 21] = [
+    (NR52, 0xF1), (NR10, 0x80), (NR11, 0xBF), (NR12, 0xF3), (NR13, 0xFF), (NR14, 0x3F),
+    (NR21, 0x3F), (NR22, 0x00), (NR23, 0xFF), (NR24, 0x3F),
+    (NR30, 0x7F), (NR31, 0xFF), (NR32, 0x9F), (NR33, 0xFF), (NR34, 0x3F),
+    (NR41, 0xFF), (NR42, 0x00), (NR43, 0x00), (NR44, 0x3F),
+    (NR50, 0x77), (NR51, 0xF3),
+];
 
 //Interrupt Vectors
 pub const INT_VBL: u16 = 0x0040;
@@ -105,74 +260,3 @@ pub const INT_STAT: u16 = 0x0048;
 pub const INT_TIMER: u16 = 0x0050;
 pub const INT_SERIAL: u16 = 0x0058;
 pub const INT_JOYPAD: u16 = 0x0060;
-
-//OpCode Details
-lazy_static! {
-    static ref OPCODES: HashMap<u8, (u32, u32)> = {
-        let mut opcodes: HashMap<u8, u32> = HashMap::new();
-        opcodes.insert(0x00, (4, 1));
-        opcodes.insert(0x01, (12, 3));
-        opcodes.insert(0x02, (8, 1));
-        opcodes.insert(0x03, (8, 1));
-        opcodes.insert(0x04, (4, 1));
-        opcodes.insert(0x05, (4, 1));
-        opcodes.insert(0x06, (8, 2));
-        opcodes.insert(0x07, (4, 1));
-        opcodes.insert(0x08, (20, 3));
-        opcodes.insert(0x09, (8, 1));
-        opcodes.insert(0x0A, (8, 1));
-        opcodes.insert(0x0B, (8, 1));
-        opcodes.insert(0x0C, (4, 1));
-        opcodes.insert(0x0D, (4, 1));
-        opcodes.insert(0x0E, (8, 2));
-        opcodes.insert(0x0F, (4, 1));
-        opcodes.insert(0x10, (5, 1));
-        opcodes.insert(0x11, (12, 3));
-        opcodes.insert(0x12, (8, 1));
-        opcodes.insert(0x13, (8, 1));
-        opcodes.insert(0x14, (4 1));
-        opcodes.insert(0x15, (4, 1));
-        opcodes.insert(0x16, (8, 2));
-        opcodes.insert(0x17, (4, 1));
-        opcodes.insert(0x18, (12, 2));
-        opcodes.insert(0x19, (8, 1));
-        opcodes.insert(0x1A, (8, 1));
-        opcodes.insert(0x1B, (8, 1));
-        opcodes.insert(0x1C, (4, 1));
-        opcodes.insert(0x1D, (4, 1));
-        opcodes.insert(0x1E, (8, 2));
-        opcodes.insert(0x1F, (4, 1));
-        opcodes.insert(0x20, (8, 2));
-        opcodes.insert(0x21, (12, 3));
-        opcodes.insert(0x22, (8, 1));
-        opcodes.insert(0x23, (8, 1));
-        opcodes.insert(0x24, (4, 1));
-        opcodes.insert(0x25, (4, 1));
-        opcodes.insert(0x26, (8, 2));
-        opcodes.insert(0x27, (4, 1));
-        opcodes.insert(0x28, (8, 2));
-        opcodes.insert(0x29, (8, 1));
-        opcodes.insert(0x2A, (8, 1));
-        opcodes.insert(0x2B, (8, 1));
-        opcodes.insert(0x2C, (4, 1));
-        opcodes.insert(0x2D, (4, 1));
-        opcodes.insert(0x2E, (8, 2));
-        opcodes.insert(0x2F, (4, 1));
-        opcodes.insert(0x30, (8, 2));
-        opcodes.insert(0x31, (12, 3));
-        opcodes.insert(0x32, (8, 1));
-        opcodes.insert(0x33, (8, 1));
-        opcodes.insert(0x34, (12, 1));
-        opcodes.insert(0x35, (12, 1));
-        opcodes.insert(0x36, (12, 2));
-        opcodes.insert(0x37, (4, 1));
-        opcodes.inserT(0x38, (8, 2));
-        opcodes.insert(0x39, (8, 1));
-        opcodes.insert(0x3A, (8, 1));
-        opcodes.insert(0x3B, (8, 1));
-        opcodes.insert(0x3C, (4, 1));
-        opcodes.insert(0x3D, (4, 1));
-        opcodes.insert(0x3E, (8, 2));
-        opcodes.insert(0x3F, (4, 1));
-    };
-}
\ No newline at end of file
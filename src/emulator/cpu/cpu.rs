@@ -1,30 +1,27 @@
-use crate::emulator::cpu::registers::{Flags, FlagSettings, Registers, Register8, Register16};
-use crate::emulator::cpu::interrupts::{Interrupt, InterruptRegisters};
+use crate::emulator::cpu::registers::{Flags, Registers, Register8, Register16};
+use crate::emulator::cpu::interrupts::Interrupt;
 use crate::emulator::constants;
 use crate::emulator::emulator::Platform;
 use crate::emulator::memory::memory::Memory;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 pub enum CpuState {
     Ready,
-    Wait(u32),
-
+    Halted,
+    Stopped,
 }
 
 /*
 registers: contains registers A F B C D E HL plus 8- and 16-bit access functions
 sp: 16-bit stack pointer
 pc: 16-bit program counter
-IF: contains interrupt flags and access functions
-IE: contains interrupt enable register and access functions
-IME: master interrut enable register
+instr_state: progress through the instruction being executed, None between instructions
+IF / IE / IME live in Memory (memory.interrupts) so they can be reached through the I/O registers
  */
 pub struct CPU {
     registers: Registers,
     sp: u16,
     pc: u16,
-    interrupts: InterruptRegisters,
-    cycle: u32,
     pub state: CpuState,
     instr_state: Option<InstructionState>
 }
@@ -55,8 +52,6 @@ impl CPU {
                     registers: Registers::new(platform),
                     sp: constants::DMG_SP,
                     pc: constants::DMG_PC,
-                    interrupts: InterruptRegisters::new(),
-                    cycle: 0,
                     state: CpuState::Ready,
                     instr_state: None,
                 }
@@ -66,8 +61,6 @@ impl CPU {
                     registers: Registers::new(platform),
                     sp: constants::GBC_SP,
                     pc: constants::GBC_PC,
-                    interrupts: InterruptRegisters::new(),
-                    cycle: 0,
                     state: CpuState::Ready,
                     instr_state: None,
                 }
@@ -75,34 +68,44 @@ impl CPU {
         }
     }
 
+    /*
+    CPU state at power on, used when a boot ROM is loaded. Execution starts at 0x0000 and the boot ROM
+    sets up the post-boot register values itself.
+     */
+    pub fn power_on() -> Self {
+        Self {
+            registers: Registers::power_on(),
+            sp: 0,
+            pc: 0,
+            state: CpuState::Ready,
+            instr_state: None,
+        }
+    }
+
+    /*
+    Advance by one clock. Instructions run over several clocks: the opcode is fetched on the first one and each
+    handler acts on the clocks where the hardware would access memory, clearing instr_state on its last one.
+    A HALTed CPU wakes up once an enabled interrupt is requested, whether or not IME is set.
+     */
     pub fn tick(&mut self, memory: &mut Memory) {
-        //TODO check for requested interrupts
         match &self.state {
             CpuState::Ready => {
-                //let mut instr = memory.read(self.pc);             // fetch instruction at [PC]
-                let mut instr: u8 = 0;
-                let mut cycle: u32 = 1;
-                let mut prefix = false;
-                match &mut self.instr_state {
+                let (instr, cycle, prefix) = match &mut self.instr_state {
                     Some(x) => {
-                        if 1 <= *x.cycle && *x.cycle < 4 || (5 <= *x.cycle && *x.cycle < 8 && *x.prefix) {
-                            *x.cycle = *x.cycle + 1;
-                            return;
+                        x.cycle = x.cycle + 1;
+                        //The clock after a CB prefix fetches the opcode it applies to
+                        if x.prefix && x.cycle == 5 {
+                            x.instruction = memory.read(self.pc) as u16;
                         }
-                        if *x.cycle == 8 && *x.prefix {
-                            *x.instruction = memory.read(self.pc);
-                        }
-                        prefix = *x.prefix;
-                        instr = *x.instruction;
-                        *x.cycle = *x.cycle + 1;
-                        cycle = *x.cycle;
+                        (x.instruction as u8, x.cycle as u32, x.prefix)
                     },
                     None => {
-                        instr = memory.read(self.pc);
+                        memory.interrupts.check_ei();
+                        let instr = memory.read(self.pc);
                         self.instr_state = Some(InstructionState::new(instr as u16));
-                        self.interrupts.check_ei();
+                        (instr, 1, false)
                     }
-                }
+                };
                 if !prefix {
                     match instr {
                         0xCB => self.prefix(cycle),                                                                                     //PREFIX CB
@@ -122,9 +125,7 @@ impl CPU {
                         0x0D => self.dec_r(cycle, Register8::C),                                                                  //DEC C
                         0x0E => self.ld_r_u8(cycle, memory, Register8::C),                                                        //LD C,u8
                         0x0F => self.rrca(cycle),                                                                                    //RRCA
-                        0x10 => {                                                                                                    //TODO STOP
-                            self.state = CpuState::Wait(4);
-                        },
+                        0x10 => self.stop(cycle, memory),                                                                      //STOP
                         0x11 => self.ld_rr_u16(cycle, memory, Register8::D, Register8::E),                                 //LD DE,u16
                         0x12 => self.sti_rr_r(cycle, memory, Register16::DE, Register8::A),                            //LD (DE),A
                         0x13 => self.inc_rr(cycle, Register16::DE),                                                                //INC DE
@@ -153,7 +154,7 @@ impl CPU {
                         0x2A => self.ldi_r_rr_inc(cycle, memory, Register8::A, Register16::HL),                      //LD A, (HL+)
                         0x2B => self.dec_rr(cycle, Register16::HL),                                                         //DEC HL
                         0x2C => self.inc_r(cycle, Register8::L),                                                            //INC L
-                        0x2D => self.dec_r(cycle, Targets::L),                                                              //DEC L
+                        0x2D => self.dec_r(cycle, Register8::L),                                                              //DEC L
                         0x2E => self.ld_r_u8(cycle, memory, Register8::L),                                                 //LD L,u8
                         0x2F => self.cpl(cycle),                                                                                         //CPL
                         0x30 => self.jr_i8(cycle, memory, vec![Flags::N, Flags::C]),                                       //JR NC,e8
@@ -226,9 +227,7 @@ impl CPU {
                         0x73 => self.sti_rr_r(cycle, memory, Register16::HL, Register8::E),                                  //LD (HL), E
                         0x74 => self.sti_rr_r(cycle, memory, Register16::HL, Register8::H),                                  //LD (HL), H
                         0x75 => self.sti_rr_r(cycle, memory, Register16::HL, Register8::L),                                  //LD (HL), L
-                        0x76 => {                                                                                                   //HALT TODO
-                            CpuState::Wait(4)
-                        },
+                        0x76 => self.halt(cycle),                                                                                   //HALT
                         0x77 => self.sti_rr_r(cycle, memory, Register16::HL, Register8::A),                                  //LD (HL), A
                         0x78 => self.ld_r_r(cycle, Register8::A, Register8::B),                                                     //LD A, B
                         0x79 => self.ld_r_r(cycle, Register8::A, Register8::C),                                                     //LD A, C
@@ -315,16 +314,16 @@ impl CPU {
                         0xCA => self.jp_u16(cycle, memory, vec![Flags::Z]),                                                          //JP Z, u16
                         0xCC => self.call(cycle, memory, vec![Flags::Z]),                                                   //CALL Z, u16
                         0xCD => self.call(cycle, memory, vec![]),                                                             //CALL u16
-                        0xCE => self.adc_r_u8(cycle, memory, Targets::A),                                                           //ADC A, u8
+                        0xCE => self.adc_r_u8(cycle, memory, Register8::A),                                                           //ADC A, u8
                         0xCF => self.rst(cycle, memory, 0x08),                                                                  //RST 08h
                         0xD0 => self.ret(cycle, memory, vec![Flags::N, Flags::C]),                                        //RET NC
                         0xD1 => self.pop_rr(cycle, memory, Register16::DE),                                                   //POP DE
                         0xD2 => self.jp_u16(cycle, memory, vec![Flags::N, Flags::C]),                                      //JP NC, u16
                         0xD4 => self.call(cycle, memory, vec![Flags::N, Flags::C]),                                        //CALL NC,u16
                         0xD5 => self.push_rr(cycle, memory, Register16::DE),                                                 //PUSH DE
-                        0xD6 => self.sub_r_u8(cycle, memory, Targets::A),                                                           //SUB A, u8
+                        0xD6 => self.sub_r_u8(cycle, memory, Register8::A),                                                           //SUB A, u8
                         0xD7 => self.rst(cycle, memory, 0x10),                                                                  //RST 10h
-                        0xD8 => self.ret(cycle, memory, vec![Flags::C])                                                    //RET C
+                        0xD8 => self.ret(cycle, memory, vec![Flags::C]),                                                   //RET C
                         0xD9 => self.reti(cycle, memory),                                                                          //RETI
                         0xDA => self.jp_u16(cycle, memory, vec![Flags::C]),                                               //JP C, u16
                         0xDC => self.call(cycle, memory, vec![Flags::C]),                                                //CALL C, u16
@@ -344,14 +343,14 @@ impl CPU {
                         0xF0 => self.ld_extended_u8(cycle, memory, Register8::A),                                   //LD A, (FF00+u8)
                         0xF1 => self.pop_rr(cycle, memory, Register16::AF),                                                       //POP AF
                         0xF2 => self.ld_extended_r(cycle, memory, Register8::A),                                  //LD A, (FF00+C)
-                        0xF3 => self.di(cycle),                                                                         //DI (Disable interrupts)
+                        0xF3 => self.di(cycle, memory),                                                                         //DI (Disable interrupts)
                         0xF5 => self.push_rr(cycle, memory, Register16::AF),                                                      //PUSH AF
                         0xF6 => self.or_r_u8(cycle, memory, Register8::A),                                                       //OR A, u8
                         0xF7 => self.rst(cycle, memory, 0x30),                                                             //RST 30h
                         0xF8 => self.ld_rr_spi8(cycle, memory, Register16::HL),                                                    //LD HL, SP+i8
                         0xF9 => self.ld_sp_rr(cycle, Register16::HL),                                                   //LD SP, HL
                         0xFA => self.ldi_r_u16(cycle, memory, Register8::A),                                                       //LD A, (u16)
-                        0xFB => self.ei(cycle),                                                                           //EI (Enable interrupts)
+                        0xFB => self.ei(cycle, memory),                                                                           //EI (Enable interrupts)
                         0xFE => self.cp_r_u8(cycle, memory, Register8::A),                                                       //CP A, u8
                         0xFF => self.rst(cycle, memory, 0x38),                                                             //RST 38h
                         _ => panic!("Code reached unreachable state")
@@ -364,7 +363,7 @@ impl CPU {
                         0x03 => self.rlc_r(cycle, Register8::E),
                         0x04 => self.rlc_r(cycle, Register8::H),
                         0x05 => self.rlc_r(cycle, Register8::L),
-                        0x06 => self.rlci_rr(cycle, Register16::HL),
+                        0x06 => self.rlci_rr(cycle, memory, Register16::HL),
                         0x07 => self.rlc_r(cycle, Register8::A),
                         0x08 => self.rrc_r(cycle, Register8::B),
                         0x09 => self.rrc_r(cycle, Register8::C),
@@ -372,7 +371,7 @@ impl CPU {
                         0x0B => self.rrc_r(cycle, Register8::E),
                         0x0C => self.rrc_r(cycle, Register8::H),
                         0x0D => self.rrc_r(cycle, Register8::L),
-                        0x0E => self.rrci_rr(cycle, Register16::HL),
+                        0x0E => self.rrci_rr(cycle, memory, Register16::HL),
                         0x0F => self.rrc_r(cycle, Register8::A),
                         0x10 => self.rl_r(cycle, Register8::B),
                         0x11 => self.rl_r(cycle, Register8::C),
@@ -380,7 +379,7 @@ impl CPU {
                         0x13 => self.rl_r(cycle, Register8::E),
                         0x14 => self.rl_r(cycle, Register8::H),
                         0x15 => self.rl_r(cycle, Register8::L),
-                        0x16 => self.rli_rr(cycle, Register16::HL),
+                        0x16 => self.rli_rr(cycle, memory, Register16::HL),
                         0x17 => self.rl_r(cycle, Register8::A),
                         0x18 => self.rr_r(cycle, Register8::B),
                         0x19 => self.rr_r(cycle, Register8::C),
@@ -388,7 +387,7 @@ impl CPU {
                         0x1B => self.rr_r(cycle, Register8::E),
                         0x1C => self.rr_r(cycle, Register8::H),
                         0x1D => self.rr_r(cycle, Register8::L),
-                        0x1E => self.rri_rr(cycle, Register16::HL),
+                        0x1E => self.rri_rr(cycle, memory, Register16::HL),
                         0x1F => self.rr_r(cycle, Register8::A),
                         0x20 => self.sla_r(cycle, Register8::B),
                         0x21 => self.sla_r(cycle, Register8::C),
@@ -549,7 +548,7 @@ impl CPU {
                         0xBC => self.res_r(cycle, 7, Register8::H),
                         0xBD => self.res_r(cycle, 7, Register8::L),
                         0xBE => self.resi_rr(cycle, memory, 7,  Register16::HL),
-                        0xBF => self.res_r(cycle, 8, Register8::A),
+                        0xBF => self.res_r(cycle, 7, Register8::A),
                         0xC0 => self.set_r(cycle, 0, Register8::B),
                         0xC1 => self.set_r(cycle, 0, Register8::C),
                         0xC2 => self.set_r(cycle, 0, Register8::D),
@@ -614,40 +613,46 @@ impl CPU {
                         0xFD => self.set_r(cycle, 7, Register8::L),
                         0xFE => self.seti_rr(cycle, memory, 7,  Register16::HL),
                         0xFF => self.set_r(cycle, 7, Register8::A),
-                        _ => panic!("Code reached unreachable state")
                     }
                 }
             },
-            _ => {},
+            CpuState::Halted => {
+                if memory.interrupts.pending() { self.state = CpuState::Ready; }
+            },
+            CpuState::Stopped => (),
         }
-        return;
+    }
+
+    //True between instructions, when an interrupt can be dispatched
+    pub fn instruction_boundary(&self) -> bool {
+        self.instr_state.is_none()
     }
 
     pub fn set_pc(&mut self, val: u16) {
         self.pc = val;
     }
 
-    pub fn get_interrupt(&self) -> Option<Interrupt> {
-        if self.interrupts.check_interrupt(Interrupt::VerticalBlanking) {
+    pub fn get_interrupt(&self, memory: &Memory) -> Option<Interrupt> {
+        if memory.interrupts.check_interrupt(Interrupt::VerticalBlanking) {
             return Some(Interrupt::VerticalBlanking);
-        } else if self.interrupts.check_interrupt(Interrupt::LcdStat) {
+        } else if memory.interrupts.check_interrupt(Interrupt::LcdStat) {
             return Some(Interrupt::LcdStat);
-        } else if self.interrupts.check_interrupt(Interrupt::Timer) {
+        } else if memory.interrupts.check_interrupt(Interrupt::Timer) {
             return Some(Interrupt::Timer);
-        } else if self.interrupts.check_interrupt(Interrupt::Serial) {
+        } else if memory.interrupts.check_interrupt(Interrupt::Serial) {
             return Some(Interrupt::Serial);
-        } else if self.interrupts.check_interrupt(Interrupt::Joypad) {
+        } else if memory.interrupts.check_interrupt(Interrupt::Joypad) {
             return Some(Interrupt::Joypad);
         }
         return None;
     }
 
     pub fn push_pc(&mut self, memory: &mut Memory) {
-        let pc_high: u8 = (&self.PC & 0xFF00) >> 8 as u8;
-        let pc_low: u8 = (&self.PC & 0x00FF) as u8;
-        self.SP.write(self.SP.read() - 1);
+        let pc_high: u8 = (self.pc >> 8) as u8;
+        let pc_low: u8 = (self.pc & 0x00FF) as u8;
+        self.sp = self.sp.wrapping_sub(1);
         memory.write(self.sp, pc_high);
-        self.SP.write(self.SP.read() - 1);
+        self.sp = self.sp.wrapping_sub(1);
         memory.write(self.sp, pc_low);
     }
 
@@ -665,17 +670,35 @@ impl CPU {
         Reads byte at PC+1 and returns as u8
      */
     fn fetch_u8_immediate(&self, memory: &mut Memory) -> u8 {
-        return memory.read(self.pc + 1);
+        return memory.read(self.pc.wrapping_add(1));
+    }
+
+    //Value carried between the clocks of a multi-cycle instruction, e.g. an address being fetched
+    fn intermediate(&self) -> u16 {
+        match &self.instr_state {
+            Some(x) => x.intermediate,
+            None => panic!("Invalid state"),
+        }
+    }
+
+    fn set_intermediate(&mut self, val: u16) {
+        match &mut self.instr_state {
+            Some(x) => x.intermediate = val,
+            None => panic!("Invalid state"),
+        }
     }
 
     /*
-        Reads bytes PC+1 and PC+2 and returns them as u16
-        PC+1 is least-significant byte
+    Conditional jumps / calls / returns list the flag they test, a leading Flags::N negates it
+    (e.g. [N, Z] is NZ). An empty list always jumps.
      */
-    fn fetch_u16_immediate(&self, memory: &mut Memory) -> u16 {
-        let mut d16 = memory.read(self.pc + 1) as u16;
-        d16 = d16 + ((memory.read(self.pc + 2) as u16) << 8);
-        return d16;
+    fn condition_met(&self, conditions: &Vec<Flags>) -> bool {
+        match conditions.as_slice() {
+            [] => true,
+            [Flags::N, x] => !self.registers.get_flag(*x),
+            [x] => self.registers.get_flag(*x),
+            _ => panic!("Invalid jump condition"),
+        }
     }
 
 
@@ -695,7 +718,7 @@ impl CPU {
     //ld R, u8
     fn ld_r_u8(&mut self, cycle: u32, memory: &mut Memory, register: Register8) {
         if cycle == 8 {
-            let val = self.fetch_u8_immdiate(memory);
+            let val = self.fetch_u8_immediate(memory);
             self.registers.set8(register, val);
             self.instr_state = None;
             self.pc = self.pc + 2;
@@ -709,7 +732,7 @@ impl CPU {
             let val = memory.read(addr);
             self.registers.set8(register1, val);
             self.instr_state = None;
-            self.pc = self.pc + 2;
+            self.pc = self.pc + 1;
         }
     }
 
@@ -741,16 +764,12 @@ impl CPU {
 
     //ldi R, (u16)
     fn ldi_r_u16(&mut self, cycle: u32, memory: &mut Memory, register: Register8) {
-        let mut state = match &self.instr_state {
-            Some(x) => x,
-            None => panic!("Invalid state"),
-        };
         if cycle == 8 {
-            *state.intermediate = memory.read(self.pc + 1) as u16;
+            self.set_intermediate(memory.read(self.pc + 1) as u16);
         } else if cycle == 12 {
-            *state.intermediate = *state.intermediate + ((memory.read(self.pc + 2) as u16) << 8);
+            self.set_intermediate(self.intermediate() + ((memory.read(self.pc + 2) as u16) << 8));
         } else if cycle == 16 {
-            self.registers.set8(register, memory.read(*state.intermediate));
+            self.registers.set8(register, memory.read(self.intermediate()));
             self.instr_state = None;
             self.pc = self.pc + 3;
         }
@@ -758,16 +777,12 @@ impl CPU {
 
     //st (u16), R
     fn sti_u16_r(&mut self, cycle: u32, memory: &mut Memory, register: Register8) {
-        let mut state = match &self.instr_state {
-            Some(x) => x,
-            None => panic!("Invalid state"),
-        };
         if cycle == 8 {
-            *state.intermediate = memory.read(self.pc + 1) as u16;
+            self.set_intermediate(memory.read(self.pc + 1) as u16);
         } else if cycle == 12 {
-            *state.intermediate = *state.intermediate + ((memory.read(self.pc + 2) as u16) << 8);
+            self.set_intermediate(self.intermediate() + ((memory.read(self.pc + 2) as u16) << 8));
         } else if cycle == 16 {
-            memory.write(*state.intermediate, self.registers.get8(register));
+            memory.write(self.intermediate(), self.registers.get8(register));
             self.instr_state = None;
             self.pc = self.pc + 3;
         }
@@ -826,7 +841,7 @@ impl CPU {
             let val = self.registers.get8(register2);
             memory.write(addr, val);
             self.instr_state = None;
-            self.pc = self.pc + 2;
+            self.pc = self.pc + 1;
         }
     }
 
@@ -839,7 +854,7 @@ impl CPU {
             let res = addr.overflowing_add(1);
             self.registers.set16(register1, res.0);
             self.instr_state = None;
-            self.pc = self.pc + 2;
+            self.pc = self.pc + 1;
         }
     }
 
@@ -852,7 +867,7 @@ impl CPU {
             let res = addr.overflowing_sub(1);
             self.registers.set16(register1, res.0);
             self.instr_state = None;
-            self.pc = self.pc + 2;
+            self.pc = self.pc + 1;
         }
     }
 
@@ -902,7 +917,7 @@ impl CPU {
         if cycle == 8 {
             let val = memory.read(self.pc + 1);
             self.sp = self.sp & 0xFF00;
-            self.sp = self.sp + val;
+            self.sp = self.sp + val as u16;
         } else if cycle == 12 {
             let val = memory.read(self.pc + 2);
             self.sp = self.sp & 0x00FF;
@@ -915,12 +930,12 @@ impl CPU {
     //push RR
     fn push_rr(&mut self, cycle: u32, memory: &mut Memory, register: Register16) {
         if cycle == 12 {
-            let val = self.registers.get(register) >> 8;
-            self.sp = self.sp - 1;
+            let val = (self.registers.get16(register) >> 8) as u8;
+            self.sp = self.sp.wrapping_sub(1);
             memory.write(self.sp, val);
         } else if cycle == 16 {
-            let val = self.registers.get(register) >> 8;
-            self.sp = self.sp - 1;
+            let val = (self.registers.get16(register) & 0xFF) as u8;
+            self.sp = self.sp.wrapping_sub(1);
             memory.write(self.sp, val);
             self.instr_state = None;
             self.pc = self.pc + 1;
@@ -932,10 +947,10 @@ impl CPU {
         let rs = register.sub_registers();
         if cycle == 8 {
             self.registers.set8(rs.1,memory.read(self.sp));
-            self.sp = self.sp + 1;
+            self.sp = self.sp.wrapping_add(1);
         } else if cycle == 12 {
             self.registers.set8(rs.0,memory.read(self.sp));
-            self.sp = self.sp + 1;
+            self.sp = self.sp.wrapping_add(1);
             self.instr_state = None;
             self.pc = self.pc + 1;
         }
@@ -943,21 +958,14 @@ impl CPU {
 
     //store SP
     fn sti_u16_sp(&mut self, cycle: u32, memory: &mut Memory) {
-        let mut state = match &self.instr_state {
-            Some(x) => x,
-            None => panic!("Invalid state"),
-        };
-        if cycle == 4 {
-            *state.intermediate = self.fetch_u8_immdiate(memory);
-            self.pc = self.pc + 1;
-        } else if cycle == 8 {
-            *state.intermediate = *state.intermediate + ((self.fetch_u8_immdiate(memory) as u16) << 8);
-            self.pc = self.pc + 1;
+        if cycle == 8 {
+            self.set_intermediate(self.fetch_u8_immediate(memory) as u16);
         } else if cycle == 12 {
-            memory.write(*state.intermediate, (self.sp & 0xFF) as u8);
+            self.set_intermediate(self.intermediate() + ((memory.read(self.pc + 2) as u16) << 8));
         } else if cycle == 16 {
-            memory.write(*state.intermediate, (self.sp >> 8) as u8);
+            memory.write(self.intermediate(), (self.sp & 0xFF) as u8);
         } else if cycle == 20 {
+            memory.write(self.intermediate().wrapping_add(1), (self.sp >> 8) as u8);
             self.instr_state = None;
             self.pc = self.pc + 3;
         }
@@ -1026,7 +1034,7 @@ impl CPU {
 
     fn inci_rr(&mut self, cycle: u32, memory: &mut Memory, pair: Register16) {
         if cycle == 12 {
-            let mut addr = self.registers.get16(pair);
+            let addr = self.registers.get16(pair);
             let val = memory.read(addr);
             let res = val.overflowing_add(1);
             if res.0 == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
@@ -1044,7 +1052,7 @@ impl CPU {
             let val = self.registers.get8(register);
             let res = val.overflowing_sub(1);
             if res.0 == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            if (val & 0xF) - 1 > 0xF { self.registers.set_flag(Flags::H) } else { self.registers.unset_flag(Flags::H) }
+            if val & 0xF == 0 { self.registers.set_flag(Flags::H) } else { self.registers.unset_flag(Flags::H) }
             self.registers.set_flag(Flags::N);
             self.registers.set8(register, res.0);
         } else if cycle == 4 {
@@ -1055,11 +1063,11 @@ impl CPU {
 
     fn deci_rr(&mut self, cycle: u32, memory: &mut Memory, pair: Register16) {
         if cycle == 12 {
-            let mut addr = self.registers.get16(pair);
+            let addr = self.registers.get16(pair);
             let val = memory.read(addr);
             let res = val.overflowing_sub(1);
             if res.0 == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            if (val & 0xF) - 1 > 0xF { self.registers.set_flag(Flags::H) } else { self.registers.unset_flag(Flags::H) }
+            if val & 0xF == 0 { self.registers.set_flag(Flags::H) } else { self.registers.unset_flag(Flags::H) }
             self.registers.set_flag(Flags::N);
             memory.write(addr, res.0);
             self.instr_state = None;
@@ -1068,32 +1076,52 @@ impl CPU {
         }
     }
 
+    //Flags for 8-bit ADD / ADC, returns the result
+    fn add8(&mut self, v1: u8, v2: u8, carry_in: bool) -> u8 {
+        let c = if carry_in { 1 } else { 0 };
+        let res = v1.wrapping_add(v2).wrapping_add(c);
+        if res == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
+        if v1 as u16 + v2 as u16 + c as u16 > 0xFF { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
+        if (v1 & 0xF) + (v2 & 0xF) + c > 0xF { self.registers.set_flag(Flags::H) } else { self.registers.unset_flag(Flags::H) }
+        self.registers.unset_flag(Flags::N);
+        return res;
+    }
+
+    //Flags for 8-bit SUB / SBC / CP, returns the result
+    fn sub8(&mut self, v1: u8, v2: u8, carry_in: bool) -> u8 {
+        let c = if carry_in { 1 } else { 0 };
+        let res = v1.wrapping_sub(v2).wrapping_sub(c);
+        if res == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
+        if (v1 as u16) < v2 as u16 + c as u16 { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
+        if v1 & 0xF < (v2 & 0xF) + c { self.registers.set_flag(Flags::H) } else { self.registers.unset_flag(Flags::H) }
+        self.registers.set_flag(Flags::N);
+        return res;
+    }
+
+    //Flags for AND / XOR / OR, H is only set by AND
+    fn logic8(&mut self, res: u8, half_carry: bool) {
+        if res == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
+        if half_carry { self.registers.set_flag(Flags::H) } else { self.registers.unset_flag(Flags::H) }
+        self.registers.unset_flag(Flags::C);
+        self.registers.unset_flag(Flags::N);
+    }
+
     //add r1, r2
     fn add_r_r(&mut self, cycle: u32, register1: Register8, register2: Register8) {
         if cycle == 4 {
-            let v1 = self.registers.get8(register1);
-            let v2 = self.registers.get8(register2);
-            let res = v1.overflowing_add(v2);
-            if res.0 == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            if res.1 { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            if (v1 & 0xF) + (v2 & 0xF) > 0xF { self.registers.set_flag(Flags::H) } else { self.registers.unset_flag(Flags::H) }
-            self.registers.unset_flag(Flags::N);
-            self.registers.set8(register1, res.0);
+            let res = self.add8(self.registers.get8(register1), self.registers.get8(register2), false);
+            self.registers.set8(register1, res);
             self.instr_state = None;
             self.pc = self.pc + 1;
         }
     }
+
     //add r, u8
     fn add_r_u8(&mut self, cycle: u32, memory: &mut Memory, register: Register8) {
         if cycle == 8 {
-            let v1 = self.registers.get8(register1);
-            let v2 = self.fetch_u8_immdiate(memory);
-            let res = v1.overflowing_add(v2);
-            if res.0 == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            if res.1 { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            if (v1 & 0xF) + (v2 & 0xF) > 0xF { self.registers.set_flag(Flags::H) } else { self.registers.unset_flag(Flags::H) }
-            self.registers.unset_flag(Flags::N);
-            self.registers.set8(register1, res.0);
+            let v2 = self.fetch_u8_immediate(memory);
+            let res = self.add8(self.registers.get8(register), v2, false);
+            self.registers.set8(register, res);
             self.instr_state = None;
             self.pc = self.pc + 2;
         }
@@ -1102,411 +1130,261 @@ impl CPU {
     //add r, (rr)
     fn addi_r_rr(&mut self, cycle: u32, memory: &mut Memory, register: Register8, pair: Register16) {
         if cycle == 8 {
-            let v1 = self.registers.get8(register);
-            let addr = self.registers.get16(pair);
-            let v2 = memory.read(addr);
-            let res = v1.overflowing_add(v2);
-            if res.0 == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            if res.1 { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            if (v1 & 0xF) + (v2 & 0xF) > 0xF { self.registers.set_flag(Flags::H) } else { self.registers.unset_flag(Flags::H) }
-            self.registers.unset_flag(Flags::N);
-            self.registers.set8(register1, res.0);
+            let v2 = memory.read(self.registers.get16(pair));
+            let res = self.add8(self.registers.get8(register), v2, false);
+            self.registers.set8(register, res);
             self.instr_state = None;
             self.pc = self.pc + 1;
         }
     }
 
-    //add r1, r2
+    //sub r1, r2
     fn sub_r_r(&mut self, cycle: u32, register1: Register8, register2: Register8) {
         if cycle == 4 {
-            let v1 = self.registers.get8(register1);
-            let v2 = self.registers.get8(register2);
-            let res = v1.overflowing_sub(v2);
-            if res.0 == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            if res.1 { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            if (v1 & 0xF) - (v2 & 0xF) > 0xF { self.registers.set_flag(Flags::H) } else { self.registers.unset_flag(Flags::H) }
-            self.registers.set_flag(Flags::N);
-            self.registers.set8(register1, res.0);
+            let res = self.sub8(self.registers.get8(register1), self.registers.get8(register2), false);
+            self.registers.set8(register1, res);
             self.instr_state = None;
             self.pc = self.pc + 1;
         }
     }
-    //add r, u8
+
+    //sub r, u8
     fn sub_r_u8(&mut self, cycle: u32, memory: &mut Memory, register: Register8) {
         if cycle == 8 {
-            let v1 = self.registers.get8(register1);
-            let v2 = self.fetch_u8_immdiate(memory);
-            let res = v1.overflowing_sub(v2);
-            if res.0 == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            if res.1 { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            if (v1 & 0xF) - (v2 & 0xF) > 0xF { self.registers.set_flag(Flags::H) } else { self.registers.unset_flag(Flags::H) }
-            self.registers.set_flag(Flags::N);
-            self.registers.set8(register1, res.0);
+            let v2 = self.fetch_u8_immediate(memory);
+            let res = self.sub8(self.registers.get8(register), v2, false);
+            self.registers.set8(register, res);
             self.instr_state = None;
             self.pc = self.pc + 2;
         }
     }
-    //add r, (rr)
+
+    //sub r, (rr)
     fn sub_r_rr(&mut self, cycle: u32, memory: &mut Memory, register: Register8, pair: Register16) {
         if cycle == 8 {
-            let v1 = self.registers.get8(register);
-            let addr = self.registers.get16(pair);
-            let v2 = memory.read(addr);
-            let res = v1.overflowing_sub(v2);
-            if res.0 == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            if res.1 { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            if (v1 & 0xF) - (v2 & 0xF) > 0xF { self.registers.set_flag(Flags::H) } else { self.registers.unset_flag(Flags::H) }
-            self.registers.set_flag(Flags::N);
-            self.registers.set8(register1, res.0);
+            let v2 = memory.read(self.registers.get16(pair));
+            let res = self.sub8(self.registers.get8(register), v2, false);
+            self.registers.set8(register, res);
             self.instr_state = None;
             self.pc = self.pc + 1;
         }
     }
 
-    //add r1, r2
+    //adc r1, r2
     fn adc_r_r(&mut self, cycle: u32, register1: Register8, register2: Register8) {
         if cycle == 4 {
-            let v1 = self.registers.get8(register1);
-            let v2 = self.registers.get8(register2);
-            let mut res = v1.overflowing_add(v2);
-            let mut half_carry = (v1 & 0xF) + (v2 & 0xF) > 0xF;
-            let mut carry = res.1;
-            if self.registers.get_flag(Flags::C) {
-                if !half_carry { half_carry = (res.0 & 0xF) + 1 > 0xF }
-                res = res.0.overflowing_add(1);
-                if !carry { carry = res.1 }
-            }
-            if res.0 == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            if carry { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            if half_carry { self.registers.set_flag(Flags::H) } else { self.registers.unset_flag(Flags::H) }
-            self.registers.unset_flag(Flags::N);
-            self.registers.set8(register1, res.0);
+            let carry = self.registers.get_flag(Flags::C);
+            let res = self.add8(self.registers.get8(register1), self.registers.get8(register2), carry);
+            self.registers.set8(register1, res);
             self.instr_state = None;
             self.pc = self.pc + 1;
         }
     }
 
-    //add r, u8
+    //adc r, u8
     fn adc_r_u8(&mut self, cycle: u32, memory: &mut Memory, register: Register8) {
         if cycle == 8 {
-            let v1 = self.registers.get8(register1);
-            let v2 = self.fetch_u8_immdiate(memory);
-            let mut res = v1.overflowing_add(v2);
-            let mut half_carry = (v1 & 0xF) + (v2 & 0xF) > 0xF;
-            let mut carry = res.1;
-            if self.registers.get_flag(Flags::C) {
-                if !half_carry { half_carry = (res.0 & 0xF) + 1 > 0xF }
-                res = res.0.overflowing_add(1);
-                if !carry { carry = res.1 }
-            }
-            if res.0 == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            if carry { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            if half_carry { self.registers.set_flag(Flags::H) } else { self.registers.unset_flag(Flags::H) }
-            self.registers.unset_flag(Flags::N);
-            self.registers.set8(register1, res.0);
+            let carry = self.registers.get_flag(Flags::C);
+            let v2 = self.fetch_u8_immediate(memory);
+            let res = self.add8(self.registers.get8(register), v2, carry);
+            self.registers.set8(register, res);
             self.instr_state = None;
             self.pc = self.pc + 2;
         }
     }
 
-    //add r, (rr)
+    //adc r, (rr)
     fn adci_r_rr(&mut self, cycle: u32, memory: &mut Memory, register: Register8, pair: Register16) {
         if cycle == 8 {
-            let v1 = self.registers.get8(register1);
-            let addr = self.registers.get16(pair);
-            let v2 = memory.read(addr);
-            let mut res = v1.overflowing_add(v2);
-            let mut half_carry = (v1 & 0xF) + (v2 & 0xF) > 0xF;
-            let mut carry = res.1;
-            if self.registers.get_flag(Flags::C) {
-                if !half_carry { half_carry = (res.0 & 0xF) + 1 > 0xF }
-                res = res.0.overflowing_add(1);
-                if !carry { carry = res.1 }
-            }
-            if res.0 == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            if carry { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            if half_carry { self.registers.set_flag(Flags::H) } else { self.registers.unset_flag(Flags::H) }
-            self.registers.unset_flag(Flags::N);
-            self.registers.set8(register1, res.0);
+            let carry = self.registers.get_flag(Flags::C);
+            let v2 = memory.read(self.registers.get16(pair));
+            let res = self.add8(self.registers.get8(register), v2, carry);
+            self.registers.set8(register, res);
             self.instr_state = None;
             self.pc = self.pc + 1;
         }
     }
 
-    //add r1, r2
+    //sbc r1, r2
     fn sbc_r_r(&mut self, cycle: u32, register1: Register8, register2: Register8) {
         if cycle == 4 {
-            let v1 = self.registers.get8(register1);
-            let v2 = self.registers.get8(register2);
-            let mut res = v1.overflowing_sub(v2);
-            let mut half_carry = (v1 & 0xF) - (v2 & 0xF) > 0xF;
-            let mut carry = res.1;
-            if self.registers.get_flag(Flags::C) {
-                if !half_carry { half_carry = (res.0 & 0xF) - 1 > 0xF }
-                res = res.0.overflowing_sub(1);
-                if !carry { carry = res.1 }
-            }
-            if res.0 == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            if carry { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            if half_carry { self.registers.set_flag(Flags::H) } else { self.registers.unset_flag(Flags::H) }
-            self.registers.set_flag(Flags::N);
-            self.registers.set8(register1, res.0);
+            let carry = self.registers.get_flag(Flags::C);
+            let res = self.sub8(self.registers.get8(register1), self.registers.get8(register2), carry);
+            self.registers.set8(register1, res);
             self.instr_state = None;
             self.pc = self.pc + 1;
         }
     }
 
-    //add r, u8
+    //sbc r, u8
     fn sbc_r_u8(&mut self, cycle: u32, memory: &mut Memory, register: Register8) {
         if cycle == 8 {
-            let v1 = self.registers.get8(register1);
-            let v2 = self.fetch_u8_immdiate(memory);
-            let mut res = v1.overflowing_sub(v2);
-            let mut half_carry = (v1 & 0xF) - (v2 & 0xF) > 0xF;
-            let mut carry = res.1;
-            if self.registers.get_flag(Flags::C) {
-                if !half_carry { half_carry = (res.0 & 0xF) - 1 > 0xF }
-                res = res.0.overflowing_sub(1);
-                if !carry { carry = res.1 }
-            }
-            if res.0 == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            if carry { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            if half_carry { self.registers.set_flag(Flags::H) } else { self.registers.unset_flag(Flags::H) }
-            self.registers.set_flag(Flags::N);
-            self.registers.set8(register1, res.0);
+            let carry = self.registers.get_flag(Flags::C);
+            let v2 = self.fetch_u8_immediate(memory);
+            let res = self.sub8(self.registers.get8(register), v2, carry);
+            self.registers.set8(register, res);
             self.instr_state = None;
             self.pc = self.pc + 2;
         }
     }
 
-    //add r, (rr)
+    //sbc r, (rr)
     fn sbci_r_rr(&mut self, cycle: u32, memory: &mut Memory, register: Register8, pair: Register16) {
         if cycle == 8 {
-            let v1 = self.registers.get8(register1);
-            let addr = self.registers.get16(pair);
-            let v2 = memory.read(addr);
-            let mut res = v1.overflowing_sub(v2);
-            let mut half_carry = (v1 & 0xF) - (v2 & 0xF) > 0xF;
-            let mut carry = res.1;
-            if self.registers.get_flag(Flags::C) {
-                if !half_carry { half_carry = (res.0 & 0xF) - 1 > 0xF }
-                res = res.0.overflowing_sub(1);
-                if !carry { carry = res.1 }
-            }
-            if res.0 == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            if carry { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            if half_carry { self.registers.set_flag(Flags::H) } else { self.registers.unset_flag(Flags::H) }
-            self.registers.unset_flag(Flags::N);
-            self.registers.set8(register1, res.0);
+            let carry = self.registers.get_flag(Flags::C);
+            let v2 = memory.read(self.registers.get16(pair));
+            let res = self.sub8(self.registers.get8(register), v2, carry);
+            self.registers.set8(register, res);
             self.instr_state = None;
             self.pc = self.pc + 1;
         }
     }
 
-    //add r1, r2
+    //and r1, r2
     fn and_r_r(&mut self, cycle: u32, register1: Register8, register2: Register8) {
         if cycle == 4 {
-            let v1 = self.registers.get8(register1);
-            let v2 = self.registers.get8(register2);
-            let res = v1 & v2;
-            if res == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            self.registers.unset_flag(Flags::C);
-            self.registers.set_flag(Flags::H);
-            self.registers.unset_flag(Flags::N);
-            self.registers.set8(register1, res.0);
+            let res = self.registers.get8(register1) & self.registers.get8(register2);
+            self.logic8(res, true);
+            self.registers.set8(register1, res);
             self.instr_state = None;
             self.pc = self.pc + 1;
         }
     }
 
-    //add r, u8
+    //and r, u8
     fn and_r_u8(&mut self, cycle: u32, memory: &mut Memory, register: Register8) {
         if cycle == 8 {
-            let v1 = self.registers.get8(register1);
-            let v2 = self.fetch_u8_immdiate(memory);
-            let res = v1 & v2;
-            if res == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            self.registers.unset_flag(Flags::C);
-            self.registers.set_flag(Flags::H);
-            self.registers.unset_flag(Flags::N);
-            self.registers.set8(register1, res.0);
+            let res = self.registers.get8(register) & self.fetch_u8_immediate(memory);
+            self.logic8(res, true);
+            self.registers.set8(register, res);
             self.instr_state = None;
             self.pc = self.pc + 2;
         }
     }
 
-    //add r, (rr)
+    //and r, (rr)
     fn andi_r_rr(&mut self, cycle: u32, memory: &mut Memory, register: Register8, pair: Register16) {
         if cycle == 8 {
-            let v1 = self.registers.get8(register);
-            let addr = self.registers.get16(pair);
-            let v2 = memory.read(addr);
-            let res = v1 & v2;
-            if res == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            self.registers.unset_flag(Flags::C);
-            self.registers.set_flag(Flags::H);
-            self.registers.unset_flag(Flags::N);
-            self.registers.set8(register1, res.0);
+            let res = self.registers.get8(register) & memory.read(self.registers.get16(pair));
+            self.logic8(res, true);
+            self.registers.set8(register, res);
             self.instr_state = None;
             self.pc = self.pc + 1;
         }
     }
 
-    //add r1, r2
+    //xor r1, r2
     fn xor_r_r(&mut self, cycle: u32, register1: Register8, register2: Register8) {
         if cycle == 4 {
-            let v1 = self.registers.get8(register1);
-            let v2 = self.registers.get8(register2);
-            let res = v1 ^ v2;
-            if res == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            self.registers.unset_flag(Flags::C);
-            self.registers.unset_flag(Flags::H);
-            self.registers.unset_flag(Flags::N);
-            self.registers.set8(register1, res.0);
+            let res = self.registers.get8(register1) ^ self.registers.get8(register2);
+            self.logic8(res, false);
+            self.registers.set8(register1, res);
             self.instr_state = None;
             self.pc = self.pc + 1;
         }
     }
 
-    //add r, u8
+    //xor r, u8
     fn xor_r_u8(&mut self, cycle: u32, memory: &mut Memory, register: Register8) {
         if cycle == 8 {
-            let v1 = self.registers.get8(register1);
-            let v2 = self.fetch_u8_immdiate(memory);
-            let res = v1 ^ v2;
-            if res == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            self.registers.unset_flag(Flags::C);
-            self.registers.unset_flag(Flags::H);
-            self.registers.unset_flag(Flags::N);
-            self.registers.set8(register1, res.0);
+            let res = self.registers.get8(register) ^ self.fetch_u8_immediate(memory);
+            self.logic8(res, false);
+            self.registers.set8(register, res);
             self.instr_state = None;
             self.pc = self.pc + 2;
         }
     }
 
-    //add r, (rr)
+    //xor r, (rr)
     fn xori_r_rr(&mut self, cycle: u32, memory: &mut Memory, register: Register8, pair: Register16) {
-        if cycle == 4 {
-            let v1 = self.registers.get8(register);
-            let addr = self.registers.get16(pair);
-            let v2 = memory.read(addr);
-            let res = v1 ^ v2;
-            if res == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            self.registers.unset_flag(Flags::C);
-            self.registers.unset_flag(Flags::H);
-            self.registers.unset_flag(Flags::N);
-            self.registers.set8(register1, res.0);
+        if cycle == 8 {
+            let res = self.registers.get8(register) ^ memory.read(self.registers.get16(pair));
+            self.logic8(res, false);
+            self.registers.set8(register, res);
             self.instr_state = None;
             self.pc = self.pc + 1;
         }
     }
 
-    //add r1, r2
+    //or r1, r2
     fn or_r_r(&mut self, cycle: u32, register1: Register8, register2: Register8) {
         if cycle == 4 {
-            let v1 = self.registers.get8(register1);
-            let v2 = self.registers.get8(register2);
-            let res = v1 | v2;
-            if res == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            self.registers.unset_flag(Flags::C);
-            self.registers.unset_flag(Flags::H);
-            self.registers.unset_flag(Flags::N);
-            self.registers.set8(register1, res.0);
+            let res = self.registers.get8(register1) | self.registers.get8(register2);
+            self.logic8(res, false);
+            self.registers.set8(register1, res);
             self.instr_state = None;
             self.pc = self.pc + 1;
         }
     }
 
-    //add r, u8
+    //or r, u8
     fn or_r_u8(&mut self, cycle: u32, memory: &mut Memory, register: Register8) {
         if cycle == 8 {
-            let v1 = self.registers.get8(register1);
-            let v2 = self.fetch_u8_immdiate(memory);
-            let res = v1 | v2;
-            if res == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            self.registers.unset_flag(Flags::C);
-            self.registers.unset_flag(Flags::H);
-            self.registers.unset_flag(Flags::N);
-            self.registers.set8(register1, res.0);
+            let res = self.registers.get8(register) | self.fetch_u8_immediate(memory);
+            self.logic8(res, false);
+            self.registers.set8(register, res);
             self.instr_state = None;
             self.pc = self.pc + 2;
         }
     }
 
-    //add r, (rr)
+    //or r, (rr)
     fn ori_r_rr(&mut self, cycle: u32, memory: &mut Memory, register: Register8, pair: Register16) {
         if cycle == 8 {
-            let v1 = self.registers.get8(register);
-            let addr = self.registers.get16(pair);
-            let v2 = memory.read(addr);
-            let res = v1 | v2;
-            if res == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            self.registers.unset_flag(Flags::C);
-            self.registers.unset_flag(Flags::H);
-            self.registers.unset_flag(Flags::N);
-            self.registers.set8(register1, res.0);
+            let res = self.registers.get8(register) | memory.read(self.registers.get16(pair));
+            self.logic8(res, false);
+            self.registers.set8(register, res);
             self.instr_state = None;
             self.pc = self.pc + 1;
         }
     }
 
-    //add r1, r2
+    //cp r1, r2
     fn cp_r_r(&mut self, cycle: u32, register1: Register8, register2: Register8) {
         if cycle == 4 {
-            let v1 = self.registers.get8(register1);
-            let v2 = self.registers.get8(register2);
-            let res = v1.overflowing_sub(v2);
-            if res.0 == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            if res.1 { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            if (v1 & 0xF) - (v2 & 0xF) > 0xF { self.registers.set_flag(Flags::H) } else { self.registers.unset_flag(Flags::H) }
-            self.registers.set_flag(Flags::N);
+            self.sub8(self.registers.get8(register1), self.registers.get8(register2), false);
             self.instr_state = None;
             self.pc = self.pc + 1;
         }
     }
-    //add r, u8
+
+    //cp r, u8
     fn cp_r_u8(&mut self, cycle: u32, memory: &mut Memory, register: Register8) {
         if cycle == 8 {
-            let v1 = self.registers.get8(register1);
-            let v2 = self.fetch_u8_immdiate(memory);
-            let res = v1.overflowing_sub(v2);
-            if res.0 == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            if res.1 { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            if (v1 & 0xF) - (v2 & 0xF) > 0xF { self.registers.set_flag(Flags::H) } else { self.registers.unset_flag(Flags::H) }
-            self.registers.set_flag(Flags::N);
+            let v2 = self.fetch_u8_immediate(memory);
+            self.sub8(self.registers.get8(register), v2, false);
             self.instr_state = None;
             self.pc = self.pc + 2;
         }
     }
-    //add r, (rr)
+
+    //cp r, (rr)
     fn cpi_r_rr(&mut self, cycle: u32, memory: &mut Memory, register: Register8, pair: Register16) {
         if cycle == 8 {
-            let v1 = self.registers.get8(register);
-            let addr = self.registers.get16(pair);
-            let v2 = memory.read(addr);
-            let res = v1.overflowing_sub(v2);
-            if res.0 == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            if res.1 { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            if (v1 & 0xF) - (v2 & 0xF) > 0xF { self.registers.set_flag(Flags::H) } else { self.registers.unset_flag(Flags::H) }
-            self.registers.set_flag(Flags::N);
+            let v2 = memory.read(self.registers.get16(pair));
+            self.sub8(self.registers.get8(register), v2, false);
             self.instr_state = None;
             self.pc = self.pc + 1;
         }
     }
 
-    //DAA
+    //DAA, correct A to packed BCD after an addition (N clear) or subtraction (N set)
     fn daa(&mut self, cycle: u32) {
         if cycle == 4 {
+            let val = self.registers.get8(Register8::A);
             let mut adjustment = 0;
-            if self.registers.get_flag(Flags::H) == 1 || (self.registers.get_flag(Flags::N) == 0 && (self.registers.get8(Register8::A) > 9)) {
+            let mut carry = self.registers.get_flag(Flags::C);
+            if self.registers.get_flag(Flags::H) || (!self.registers.get_flag(Flags::N) && (val & 0xF) > 9) {
                 adjustment |= 0x6;
             }
-            if self.registers.get_flag(Flags::C) == 1 || (self.registers.get_flag(Flags::N) == 0 && (self.registers.get8(Register8::A) > 0x99)) {
+            if carry || (!self.registers.get_flag(Flags::N) && val > 0x99) {
                 adjustment |= 0x60;
-                self.registers.set_flag(Flags::C);
+                carry = true;
             }
-            if self.registers.get_flag(Flags::N) == 1 { self.registers.set8(Register8::A, (self.registers.get8(Register8::A) + adjustment) & 0xFF) } else { self.registers.set8(Register8::A, (self.registers.get8(Register8::A) - adjustment) & 0xFF) }
-            if self.registers.get8(Register8::A) == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
+            let res = if self.registers.get_flag(Flags::N) { val.wrapping_sub(adjustment) } else { val.wrapping_add(adjustment) };
+            self.registers.set8(Register8::A, res);
+            if res == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
+            if carry { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
             self.registers.unset_flag(Flags::H);
             self.instr_state = None;
             self.pc = self.pc + 1;
@@ -1516,7 +1394,9 @@ impl CPU {
     fn cpl(&mut self, cycle: u32) {
         if cycle == 4 {
             let val = self.registers.get8(Register8::A);
-            self.registers.set8(Register8::A, val ^ 0xFF)
+            self.registers.set8(Register8::A, val ^ 0xFF);
+            self.registers.set_flag(Flags::N);
+            self.registers.set_flag(Flags::H);
             self.instr_state = None;
             self.pc = self.pc + 1;
         }
@@ -1539,6 +1419,8 @@ impl CPU {
             } else {
                 self.registers.set_flag(Flags::C);
             }
+            self.registers.unset_flag(Flags::N);
+            self.registers.unset_flag(Flags::H);
             self.instr_state = None;
             self.pc = self.pc + 1;
         }
@@ -1546,334 +1428,166 @@ impl CPU {
 
     /*
         8-bit rotate/shift bits
-            * RLCA
+        The A-only forms (RLCA, RLA, RRCA, RRA) always clear Z, the CB-prefixed forms set it from the result.
+        rotate_shift8 does the work for each CB operation on a value from a register or (HL).
      */
+    fn rotate_shift8(&mut self, op: ShiftOp, val: u8) -> u8 {
+        let carry_in = if self.registers.get_flag(Flags::C) { 1 } else { 0 };
+        let (res, carry) = match op {
+            ShiftOp::Rlc => ((val << 1) | (val >> 7), val >> 7),
+            ShiftOp::Rl => ((val << 1) | carry_in, val >> 7),
+            ShiftOp::Rrc => ((val >> 1) | (val << 7), val & 0x01),
+            ShiftOp::Rr => ((val >> 1) | (carry_in << 7), val & 0x01),
+            ShiftOp::Sla => (val << 1, val >> 7),
+            ShiftOp::Sra => ((val >> 1) | (val & 0x80), val & 0x01),
+            ShiftOp::Swap => ((val << 4) | (val >> 4), 0),
+            ShiftOp::Srl => (val >> 1, val & 0x01),
+        };
+        if res == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
+        if carry == 1 { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
+        self.registers.unset_flag(Flags::N);
+        self.registers.unset_flag(Flags::H);
+        return res;
+    }
 
-    //Left circular shift register A
-    fn rlca(&mut self, cycle: u32) {
+    //op on register A without the CB prefix
+    fn rotate_a(&mut self, cycle: u32, op: ShiftOp) {
         if cycle == 4 {
-            let val = self.registers.get8(Targets::A);
-            let high = (val & 0xA0) >> 7;
-            let res = (val << 1) + high;
-            if high { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
+            let res = self.rotate_shift8(op, self.registers.get8(Register8::A));
             self.registers.set8(Register8::A, res);
             self.registers.unset_flag(Flags::Z);
-            self.registers.unset_flag(Flags::N);
-            self.registers.unset_flag(Flags::H);
             self.instr_state = None;
             self.pc = self.pc + 1;
         }
     }
 
-    //Left circular shift arbitrary 8-bit register
-    fn rlc_r(&mut self, cycle: u32, register: Register8) {
+    //CB op on an arbitrary 8-bit register
+    fn rotate_r(&mut self, cycle: u32, op: ShiftOp, register: Register8) {
         if cycle == 8 {
-            let val = self.registers.get8(register);
-            let high = (val & 0xA0) >> 7;
-            let res = (val << 1) + high;
-            if high { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            if res == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            self.registers.unset_flag(Flags::N);
-            self.registers.unset_flag(Flags::H);
+            let res = self.rotate_shift8(op, self.registers.get8(register));
             self.registers.set8(register, res);
             self.instr_state = None;
             self.pc = self.pc + 1;
         }
     }
 
-    //Left circular shift value stored in memory pointed to by RR
-    fn rlci_rr(&mut self, cycle: u32, pair: Register16) {
-        if  cycle == 16 {
+    //CB op on the value stored in memory pointed to by RR
+    fn rotate_rr(&mut self, cycle: u32, memory: &mut Memory, op: ShiftOp, pair: Register16) {
+        if cycle == 16 {
             let addr = self.registers.get16(pair);
-            let val = memory.read(addr);
-            let high = (val & 0xA0) >> 7;
-            let res = (val << 1) + high;
-            if high { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            if res == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            self.registers.unset_flag(Flags::N);
-            self.registers.unset_flag(Flags::H);
+            let res = self.rotate_shift8(op, memory.read(addr));
             memory.write(addr, res);
             self.instr_state = None;
             self.pc = self.pc + 1;
         }
     }
 
+    //Left circular shift register A
+    fn rlca(&mut self, cycle: u32) {
+        self.rotate_a(cycle, ShiftOp::Rlc);
+    }
+
+    //Left circular shift arbitrary 8-bit register
+    fn rlc_r(&mut self, cycle: u32, register: Register8) {
+        self.rotate_r(cycle, ShiftOp::Rlc, register);
+    }
+
+    //Left circular shift value stored in memory pointed to by RR
+    fn rlci_rr(&mut self, cycle: u32, memory: &mut Memory, pair: Register16) {
+        self.rotate_rr(cycle, memory, ShiftOp::Rlc, pair);
+    }
+
     //Left shift register A through carry
     fn rla(&mut self, cycle: u32) {
-        if cycle == 4 {
-            let val = self.registers.get8(Targets::A);
-            let high = (val & 0xA0) >> 7;
-            let new_low = if self.registers.get_flag(Flags::C) { 1 } else { 0 };
-            let res = (val << 1) + new_low;
-            if high { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            self.registers.set8(Register8::A, res);
-            self.registers.unset_flag(Flags::Z);
-            self.registers.unset_flag(Flags::N);
-            self.registers.unset_flag(Flags::H);
-            self.instr_state = None;
-            self.pc = self.pc + 1;
-        }
+        self.rotate_a(cycle, ShiftOp::Rl);
     }
 
     //Left shift arbitrary 8-bit register through carry
     fn rl_r(&mut self, cycle: u32, register: Register8) {
-        if cycle == 8 {
-            let val = self.registers.get8(register);
-            let high = (val & 0xA0) >> 7;
-            let new_low = if self.registers.get_flag(Flags::C) { 1 } else { 0 };
-            let res = (val << 1) + new_low;
-            if high { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            self.registers.set8(register, res);
-            if res == 0 { self.registers.unset_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            self.registers.unset_flag(Flags::N);
-            self.registers.unset_flag(Flags::H);
-            self.instr_state = None;
-            self.pc = self.pc + 1;
-        }
+        self.rotate_r(cycle, ShiftOp::Rl, register);
     }
 
     //Left shift value stored in memory pointed to by RR through carry
-    fn rli_rr(&mut self, cycle: u32, pair: Register16) {
-        if cycle == 16 {
-            let addr = self.registers.get16(pair);
-            let val = memory.read(addr);
-            let high = (val & 0xA0) >> 7;
-            let new_low = if self.registers.get_flag(Flags::C) { 1 } else { 0 };
-            let res = (val << 1) + new_low;
-            if high { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            if res == 0 { self.registers.unset_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            self.registers.unset_flag(Flags::N);
-            self.registers.unset_flag(Flags::H);
-            memory.write(addr, res);
-            self.instr_state = None;
-            self.pc = self.pc + 1;
-        }
+    fn rli_rr(&mut self, cycle: u32, memory: &mut Memory, pair: Register16) {
+        self.rotate_rr(cycle, memory, ShiftOp::Rl, pair);
     }
 
     //Right circular shift register A
     fn rrca(&mut self, cycle: u32) {
-        if cycle == 4 {
-            let val = self.registers.get8(Targets::A);
-            let low = val & 0x01;
-            let res = (val >> 1) + (low << 7);
-            if low { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            self.registers.unset_flag(Flags::Z);
-            self.registers.unset_flag(Flags::N);
-            self.registers.unset_flag(Flags::H);
-            self.registers.set8(Register8::A, res);
-            self.instr_state = None;
-            self.pc = self.pc + 1;
-        }
+        self.rotate_a(cycle, ShiftOp::Rrc);
     }
 
     //Right circular shift arbitrary 8-bit register
     fn rrc_r(&mut self, cycle: u32, register: Register8) {
-        if cycle == 8 {
-            let val = self.registers.get8(register);
-            let low = val & 0x01;
-            let res = (val >> 1) + (low << 7);
-            if low { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            if res == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            self.registers.unset_flag(Flags::N);
-            self.registers.unset_flag(Flags::H);
-            self.registers.set8(Register8::A, res);
-            self.instr_state = None;
-            self.pc = self.pc + 1;
-        }
+        self.rotate_r(cycle, ShiftOp::Rrc, register);
     }
 
     //Right circular shift value stored in memory pointed to by RR
-    fn rrci_rr(&mut self, cycle: u32, pair: Register16) {
-        if cycle == 16 {
-            let addr = self.registers.get16(pair);
-            let val = memory.read(addr);
-            let low = val & 0x01;
-            let res = (val >> 1) + (low << 7);
-            if low { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            if res == 0 { self.registers.set_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            self.registers.unset_flag(Flags::N);
-            self.registers.unset_flag(Flags::H);
-            memory.write(addr, res);
-            self.instr_state = None;
-            self.pc = self.pc + 1;
-        }
+    fn rrci_rr(&mut self, cycle: u32, memory: &mut Memory, pair: Register16) {
+        self.rotate_rr(cycle, memory, ShiftOp::Rrc, pair);
     }
 
-    //Left shift register A through carry
+    //Right shift register A through carry
     fn rra(&mut self, cycle: u32) {
-        if cycle == 4 {
-            let val = self.registers.get8(Targets::A);
-            let low = val & 0x0F;
-            let new_high = if self.registers.get_flag(Flags::C) { 1 } else { 0 };
-            let res = (val >> 1) + (new_high << 7);
-            if low { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            self.registers.set8(Register8::A, res);
-            self.registers.unset_flag(Flags::Z);
-            self.registers.unset_flag(Flags::N);
-            self.registers.unset_flag(Flags::H);
-            self.instr_state = None;
-            self.pc = self.pc + 1;
-        }
+        self.rotate_a(cycle, ShiftOp::Rr);
     }
 
-    //Left shift arbitrary 8-bit register through carry
+    //Right shift arbitrary 8-bit register through carry
     fn rr_r(&mut self, cycle: u32, register: Register8) {
-        if cycle == 8 {
-            let val = self.registers.get8(register);
-            let low = val & 0x0F;
-            let new_high = if self.registers.get_flag(Flags::C) { 1 } else { 0 };
-            let res = (val >> 1) + (new_high << 7);
-            if high { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            self.registers.set8(register, res);
-            if res == 0 { self.registers.unset_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            self.registers.unset_flag(Flags::N);
-            self.registers.unset_flag(Flags::H);
-            self.instr_state = None;
-            self.pc = self.pc + 1;
-        }
+        self.rotate_r(cycle, ShiftOp::Rr, register);
     }
 
-    //Left shift value stored in memory pointed to by RR through carry
-    fn rri_rr(&mut self, cycle: u32, pair: Register16) {
-        if cycle == 16 {
-            let addr = self.registers.get16(pair);
-            let val = memory.read(addr);
-            let low = val & 0x0F;
-            let new_high = if self.registers.get_flag(Flags::C) { 1 } else { 0 };
-            let res = (val >> 1) + (new_high << 7);
-            if high { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            if res == 0 { self.registers.unset_flag(Flags::Z) } else { self.registers.unset_flag(Flags::Z) }
-            self.registers.unset_flag(Flags::N);
-            self.registers.unset_flag(Flags::H);
-            memory.write(addr, res);
-            self.instr_state = None;
-            self.pc = self.pc + 1;
-        }
+    //Right shift value stored in memory pointed to by RR through carry
+    fn rri_rr(&mut self, cycle: u32, memory: &mut Memory, pair: Register16) {
+        self.rotate_rr(cycle, memory, ShiftOp::Rr, pair);
     }
 
     //Arithmetic shift left R
     fn sla_r(&mut self, cycle: u32, register: Register8) {
-        if cycle == 8 {
-            let val = self.registers.get8(register);
-            let carry = (val & 0xA0) >> 7;
-            let low = val & 0x1;
-            if carry == 1 { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            let mut res = val << 1;
-            if low == 1 { res = res | 0x1 } else { res = res & 0xF8 }
-            self.registers.set8(register, res);
-            self.instr_state = None;
-            self.pc = self.pc + 1;
-        }
+        self.rotate_r(cycle, ShiftOp::Sla, register);
     }
 
     //Arithmetic shift left (RR)
     fn slai_rr(&mut self, cycle: u32, memory: &mut Memory, pair: Register16) {
-        if cycle == 16 {
-            let addr = self.registers.get16(pair);
-            let val = memory.read(addr);
-            let carry = (val & 0xA0) >> 7;
-            let low = val & 0x1;
-            if carry == 1 { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            let mut res = val << 1;
-            if low == 1 { res = res | 0x1 } else { res = res & 0xF8 }
-            memory.write(addr, res);
-            self.instr_state = None;
-            self.pc = self.pc + 1;
-        }
+        self.rotate_rr(cycle, memory, ShiftOp::Sla, pair);
     }
 
-    //Arithmetic shift right R
+    //Arithmetic shift right R, bit 7 is kept
     fn sra_r(&mut self, cycle: u32, register: Register8) {
-        if cycle == 8 {
-            let val = self.registers.get8(register);
-            let carry = val & 0x01;
-            let high = val & 0xA0;
-            if carry == 1 { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            let mut res = val >> 1;
-            if high == 0xA0 { res = res | 0xA0 } else { res = res & 0x7F }
-            self.registers.set8(register, res);
-            self.instr_state = None;
-            self.pc = self.pc + 1;
-        }
+        self.rotate_r(cycle, ShiftOp::Sra, register);
     }
 
-    //Arithmetic shift right (RR)
+    //Arithmetic shift right (RR), bit 7 is kept
     fn srai_rr(&mut self, cycle: u32, memory: &mut Memory, pair: Register16) {
-        if cycle == 16 {
-            let addr = self.registers.get16(pair);
-            let val = memory.read(addr);
-            let carry = val & 0x01;
-            let high = val & 0xA0;
-            if carry == 1 { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            let mut res = val >> 1;
-            if high == 0xA0 { res = res | 0xA0 } else { res = res & 0x7F }
-            memory.write(addr, res);
-            self.instr_state = None;
-            self.pc = self.pc + 1;
-        }
+        self.rotate_rr(cycle, memory, ShiftOp::Sra, pair);
     }
 
     //Swap the upper 4 bits in R with the lower four
     fn swap_r(&mut self, cycle: u32, register: Register8) {
-        if cycle == 8 {
-            let val = self.registers.get8(register);
-            let low = (val & 0xF0) >> 4;
-            let high = (val & 0x0F) << 4;
-            let res = high + low;
-            self.registers.set8(register, res);
-            self.instr_state = None;
-            self.pc = self.pc + 1;
-        }
+        self.rotate_r(cycle, ShiftOp::Swap, register);
     }
 
     //Swap the upper 4 bits in (RR) with the lower four
     fn swapi_rr(&mut self, cycle: u32, memory: &mut Memory, pair: Register16) {
-        if cycle == 16 {
-            let addr = self.registers.get16(pair);
-            let val = memory.read(addr);
-            let low = (val & 0xF0) >> 4;
-            let high = (val & 0x0F) << 4;
-            let res = high + low;
-            memory.write(addr, res);
-            self.instr_state = None;
-            self.pc = self.pc + 1;
-        }
+        self.rotate_rr(cycle, memory, ShiftOp::Swap, pair);
     }
 
     //Logical shift right R
     fn srl_r(&mut self, cycle: u32, register: Register8) {
-        if cycle == 8 {
-            let val = self.registers.get8(register);
-            let carry = val & 0x01;
-            if carry == 1 { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            let mut res = val >> 1;
-            self.registers.set8(register, res);
-            self.instr_state = None;
-            self.pc = self.pc + 1;
-        }
+        self.rotate_r(cycle, ShiftOp::Srl, register);
     }
 
     //Logical shift right (RR)
     fn srli_rr(&mut self, cycle: u32, memory: &mut Memory, pair: Register16) {
-        if cycle == 16 {
-            let addr = self.registers.get16(pair);
-            let val = memory.read(addr);
-            let carry = val & 0x01;
-            if carry == 1 { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            let mut res = val >> 1;
-            memory.write(addr, res);
-            self.instr_state = None;
-            self.pc = self.pc + 1;
-        }
+        self.rotate_rr(cycle, memory, ShiftOp::Srl, pair);
     }
 
     //Test u3 in R, set Z if bit not set
     fn bit_r(&mut self, cycle: u32, bit: u8, register: Register8) {
         if cycle == 8 {
-            if bit > 7 { return }
             let val = self.registers.get8(register);
             let set = (val >> bit) & 0x1;
-            if set { self.registers.unset_flag(Flags::Z) } else { self.registers.set_flag(Flags::Z) }
+            if set == 1 { self.registers.unset_flag(Flags::Z) } else { self.registers.set_flag(Flags::Z) }
             self.registers.unset_flag(Flags::N);
             self.registers.set_flag(Flags::H);
             self.instr_state = None;
@@ -1884,11 +1598,10 @@ impl CPU {
     //Test u3 in (RR), set Z if bit not set
     fn biti_rr(&mut self, cycle: u32, memory: &mut Memory, bit: u8, pair: Register16) {
         if cycle == 12 {
-            if bit > 7 { return }
             let addr = self.registers.get16(pair);
             let val = memory.read(addr);
             let set = (val >> bit) & 0x1;
-            if set { self.registers.unset_flag(Flags::Z) } else { self.registers.set_flag(Flags::Z) }
+            if set == 1 { self.registers.unset_flag(Flags::Z) } else { self.registers.set_flag(Flags::Z) }
             self.registers.unset_flag(Flags::N);
             self.registers.set_flag(Flags::H);
             self.instr_state = None;
@@ -1899,8 +1612,7 @@ impl CPU {
     //Set bit position in R to 0
     fn res_r(&mut self, cycle: u32, bit: u8, register: Register8) {
         if cycle == 8 {
-            if bit > 7 { return }
-            let mut mask = 0xFF ^ (0x1 << bit);
+            let mask = 0xFF ^ (0x1 << bit);
             let val = self.registers.get8(register);
             let res = val & mask;
             self.registers.set8(register, res);
@@ -1912,9 +1624,8 @@ impl CPU {
     //Set bit position in (RR) to 0
     fn resi_rr(&mut self, cycle: u32, memory: &mut Memory, bit: u8, pair: Register16) {
         if cycle == 16 {
-            if bit > 7 { return }
             let addr = self.registers.get16(pair);
-            let mut mask = 0xFF ^ (0x1 << bit);
+            let mask = 0xFF ^ (0x1 << bit);
             let val = memory.read(addr);
             let res = val & mask;
             memory.write(addr, res);
@@ -1926,8 +1637,7 @@ impl CPU {
     //Set bit position in R to 1
     fn set_r(&mut self, cycle: u32, bit: u8, register: Register8) {
         if cycle == 8 {
-            if bit > 7 { return }
-            let mut mask = 0x1 << bit;
+            let mask = 0x1 << bit;
             let val = self.registers.get8(register);
             let res = val | mask;
             self.registers.set8(register, res);
@@ -1936,12 +1646,11 @@ impl CPU {
         }
     }
 
-    //Set bit position in  to 1
+    //Set bit position in (RR) to 1
     fn seti_rr(&mut self, cycle: u32, memory: &mut Memory, bit: u8, pair: Register16) {
         if cycle == 16 {
-            if bit > 7 { return }
             let addr = self.registers.get16(pair);
-            let mut mask = 0x1 << bit;
+            let mask = 0x1 << bit;
             let val = memory.read(addr);
             let res = val | mask;
             memory.write(addr, res);
@@ -1962,138 +1671,91 @@ impl CPU {
         * ADD SP, i8
         * LD HL, SP+i8
      */
-    fn inc_rr(&mut self, cycle: u32, rr: Register16) {
-        let rs = rr.sub_registers();
-        let mut state = match &self.instr_state {
-            Some(x) => x,
-            None => panic!("Invalid state"),
-        };
-        if cycle == 4 {
+    fn inc_rr(&mut self, cycle: u32, register: Register16) {
+        if cycle == 8 {
             let val = self.registers.get16(register);
-            let res = val.overflowing_add(1);
-            *state.intermediate = res.0;
-            self.registers.set8(rs.1, (res.0 & 0xFF) as u8);
-        } else if cycle == 8 {
-            self.registers.set8(rs.0, (*state.intermediate >> 8) as u8);
+            self.registers.set16(register, val.wrapping_add(1));
             self.instr_state = None;
             self.pc = self.pc + 1;
         }
     }
 
     fn inc_sp(&mut self, cycle: u32) {
-        let mut state = match &self.instr_state {
-            Some(x) => x,
-            None => panic!("Invalid state"),
-        };
-        if cycle == 4 {
-            let val = self.sp;
-            let res = val.overflowing_add(1);
-            *state.intermediate = res.0;
-            self.sp = self.sp & 0xFF00;
-            self.sp = self.sp + (res.0 & 0xFF);
-        } else if cycle == 8 {
-            self.sp = *state.intermediate;
+        if cycle == 8 {
+            self.sp = self.sp.wrapping_add(1);
             self.instr_state = None;
             self.pc = self.pc + 1;
         }
     }
 
     fn dec_rr(&mut self, cycle: u32, register: Register16) {
-        let rs = rr.sub_registers();
-        let mut state = match &self.instr_state {
-            Some(x) => x,
-            None => panic!("Invalid state"),
-        };
-        if cycle == 4 {
+        if cycle == 8 {
             let val = self.registers.get16(register);
-            let res = val.overflowing_sub(1);
-            *state.intermediate = res.0;
-            self.registers.set8(rs.1, (res.0 & 0xFF) as u8);
-        } else if cycle == 8 {
-            self.registers.set8(rs.0, (*state.intermediate >> 8) as u8);
+            self.registers.set16(register, val.wrapping_sub(1));
             self.instr_state = None;
             self.pc = self.pc + 1;
         }
     }
 
     fn dec_sp(&mut self, cycle: u32) {
-        let mut state = match &self.instr_state {
-            Some(x) => x,
-            None => panic!("Invalid state"),
-        };
-        if cycle == 4 {
-            let val = self.sp;
-            let res = val.overflowing_sub(1);
-            *state.intermediate = res.0;
-            self.sp = self.sp & 0xFF00;
-            self.sp = self.sp + (res.0 & 0xFF);
-        } else if cycle == 8 {
-            self.sp = *state.intermediate;
+        if cycle == 8 {
+            self.sp = self.sp.wrapping_sub(1);
             self.instr_state = None;
             self.pc = self.pc + 1;
         }
     }
 
+    //Flags for ADD HL, RR: H is the carry out of bit 11, Z is left alone
+    fn add16(&mut self, v1: u16, v2: u16) -> u16 {
+        let res = v1.overflowing_add(v2);
+        if (v1 & 0x0FFF) + (v2 & 0x0FFF) > 0x0FFF { self.registers.set_flag(Flags::H) } else { self.registers.unset_flag(Flags::H) }
+        if res.1 { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
+        self.registers.unset_flag(Flags::N);
+        return res.0;
+    }
+
     fn add_rr_rr(&mut self, cycle: u32, pair1: Register16, pair2: Register16) {
-        if cycle == 4 {
-            let v1 = self.registers.get16(pair1);
-            let v2 = self.registers.get16(pair2);
-            let res = v1.overflowing_add(v2);
-            if (v1 & 0x0F00) + (v2 & 0x0F00) > 0x0F00 { self.registers.set_flag(Flags::H) } else { self.registers.unset_flag(Flags::H) }
-            if res.1 { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            self.registers.unset_flag(Flags::N);
-            self.registers.set16(pair1, res.0);
-        } else if cycle == 8 {
+        if cycle == 8 {
+            let res = self.add16(self.registers.get16(pair1), self.registers.get16(pair2));
+            self.registers.set16(pair1, res);
             self.instr_state = None;
             self.pc = self.pc + 1;
         }
     }
 
     fn add_rr_sp(&mut self, cycle: u32, register: Register16) {
-        let mut state = match &self.instr_state {
-            Some(x) => x,
-            None => panic!("Invalid state"),
-        };
-        let rs = register.sub_registers();
-        if cycle == 4 {
-            let val = self.registers.get16(register);
-            let res = val.overflowing_add(self.sp);
-            if (val & 0x0F00) + (self.sp & 0x0F00) > 0x0F00 { self.registers.set_flag(Flags::H) } else { self.registers.unset_flag(Flags::H) }
-            if res.1 { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            self.registers.unset_flag(Flags::N);
-            *state.intermediate = res.0;
-            self.registers.set8(rs.1, (res.0 & 0xFF) as u8);
-        }  else if cycle == 8 {
-            self.registers.set8(rs.0, (*state.intermediate >> 8) as u8);
+        if cycle == 8 {
+            let res = self.add16(self.registers.get16(register), self.sp);
+            self.registers.set16(register, res);
             self.instr_state = None;
             self.pc = self.pc + 1;
         }
     }
 
-    fn add_sp_i8(&mut self, cycle: u32, memory: &mut Memory) {
-        let e8 = memory.read(self.pc + 1) as i8;
-        let val = e8.abs() as u8;
-        let res = if e8 >= 0 { self.sp.overflowing_add(val as u16) } else { self.sp.overflowing_sub(val as u16) };
-        if (self.sp & 0x0F00) + (e8 & 0x0F00) > 0x0F00 { self.registers.set_flag(Flags::H) } else { self.registers.unset_flag(Flags::H) }
-        if res.1 { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
+    //SP plus a signed offset. H and C come from the unsigned addition of the low byte, Z and N are cleared.
+    fn sp_plus_i8(&mut self, memory: &mut Memory) -> u16 {
+        let offset = self.fetch_u8_immediate(memory);
+        let res = self.sp.wrapping_add(offset as i8 as u16);
+        if (self.sp & 0x0F) + (offset as u16 & 0x0F) > 0x0F { self.registers.set_flag(Flags::H) } else { self.registers.unset_flag(Flags::H) }
+        if (self.sp & 0xFF) + offset as u16 > 0xFF { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
         self.registers.unset_flag(Flags::N);
         self.registers.unset_flag(Flags::Z);
-        self.sp = res.0;
-        self.instr_state = None;
-        self.pc = self.pc + 2;
+        return res;
+    }
+
+    fn add_sp_i8(&mut self, cycle: u32, memory: &mut Memory) {
+        if cycle == 16 {
+            self.sp = self.sp_plus_i8(memory);
+            self.instr_state = None;
+            self.pc = self.pc + 2;
+        }
     }
 
     //LD HL,SP+i8
     fn ld_rr_spi8(&mut self, cycle: u32, memory: &mut Memory, register: Register16) {
         if cycle == 12 {
-            let e8 = memory.read(self.pc + 1) as i8;
-            let val = e8.abs() as u8;
-            let res = if e8 >= 0 { self.sp.overflowing_add(val as u16) } else { self.sp.overflowing_sub(val as u16) };
-            if (self.sp & 0x0F00) + (e8 & 0x0F00) > 0x0F00 { self.registers.set_flag(Flags::H) } else { self.registers.unset_flag(Flags::H) }
-            if res.1 { self.registers.set_flag(Flags::C) } else { self.registers.unset_flag(Flags::C) }
-            self.registers.unset_flag(Flags::N);
-            self.registers.unset_flag(Flags::Z);
-            self.registers.set16(register, res.0);
+            let res = self.sp_plus_i8(memory);
+            self.registers.set16(register, res);
             self.instr_state = None;
             self.pc = self.pc + 2;
         }
@@ -2101,6 +1763,7 @@ impl CPU {
 
     /*
         CPU Control / Misc
+        Conditional instructions take fewer clocks when the condition fails, they finish early with PC past the operands.
     */
 
     fn nop(&mut self, cycle: u32) {
@@ -2110,25 +1773,31 @@ impl CPU {
         }
     }
 
+    //HALT: wait for an interrupt, see tick()
+    fn halt(&mut self, cycle: u32) {
+        if cycle == 4 {
+            self.state = CpuState::Halted;
+            self.instr_state = None;
+            self.pc = self.pc + 1;
+        }
+    }
+
+    //RET takes 16 clocks, RET cc takes 20 (8 if not taken)
     fn ret(&mut self, cycle: u32, memory: &mut Memory, conditions: Vec<Flags>) {
-        let mut state = match &self.instr_state {
-            Some(x) => x,
-            None => panic!("Invalid state"),
-        };
-        if cycle == 8 {
-            let mut jump = true;
-            for condition in conditions {
-                if !self.registers.get_flag(condition) { jump = false }
+        let start = if conditions.is_empty() { 4 } else { 8 };
+        if cycle == 8 && !conditions.is_empty() {
+            if !self.condition_met(&conditions) {
+                self.instr_state = None;
+                self.pc = self.pc + 1;
             }
-            if !jump { self.instr_state = None; }
-        } else if cycle == 12 {
-            *state.intermediate = memory.read(self.sp);
-            self.sp = self.sp + 1;
-        } else if cycle == 16 {
-            *state.intermediate = *state.intermediate + ((memory.read(self.sp) as u16) << 8);
-            self.sp = self.sp + 1;
-        } else if cycle == 20 {
-            self.pc = *state.intermediate;
+        } else if cycle == start + 4 {
+            self.set_intermediate(memory.read(self.sp) as u16);
+            self.sp = self.sp.wrapping_add(1);
+        } else if cycle == start + 8 {
+            self.set_intermediate(self.intermediate() + ((memory.read(self.sp) as u16) << 8));
+            self.sp = self.sp.wrapping_add(1);
+        } else if cycle == start + 12 {
+            self.pc = self.intermediate();
             self.instr_state = None;
         }
     }
@@ -2136,76 +1805,62 @@ impl CPU {
     fn rst(&mut self, cycle: u32, memory: &mut Memory, vec: u16) {
         if cycle == 12 {
             self.pc = self.pc + 1;
-            self.sp = self.sp - 1;
+            self.sp = self.sp.wrapping_sub(1);
             memory.write(self.sp, ((self.pc) >> 8) as u8);
         } else if cycle == 16 {
-            self.sp = self.sp - 1;
-            memory.write(self.sp - 1, ((self.pc) & 0xFF) as u8);
+            self.sp = self.sp.wrapping_sub(1);
+            memory.write(self.sp, ((self.pc) & 0xFF) as u8);
             self.pc = vec;
             self.instr_state = None;
         }
     }
 
+    //CALL takes 24 clocks, 12 if the condition fails
     fn call(&mut self, cycle: u32, memory: &mut Memory, conditions: Vec<Flags>){
-        let mut state = match &self.instr_state {
-            Some(x) => x,
-            None => panic!("Invalid state"),
-        };
         if cycle == 8 {
-            *state.intermediate = memory.read(self.pc + 1);
+            self.set_intermediate(memory.read(self.pc + 1) as u16);
         } else if cycle == 12 {
-            *state.intermediate  = *state.intermediate + ((memory.read(self.pc + 2) as u16) << 8);
+            self.set_intermediate(self.intermediate() + ((memory.read(self.pc + 2) as u16) << 8));
             self.pc = self.pc + 3;
-            let mut jump = true;
-            for condition in conditions {
-                if !self.registers.get_flag(condition) { jump = false }
-            }
-            if !jump { self.instr_state = None; }
+            if !self.condition_met(&conditions) { self.instr_state = None; }
         } else if cycle == 16 {
-            self.sp = self.sp -1 ;
+            self.sp = self.sp.wrapping_sub(1);
             memory.write(self.sp, ((self.pc) >> 8) as u8);
         } else if cycle == 20 {
-            self.sp = self.sp - 1;
-            memory.write(self.sp - 1, ((self.pc) & 0xFF) as u8);
+            self.sp = self.sp.wrapping_sub(1);
+            memory.write(self.sp, ((self.pc) & 0xFF) as u8);
         } else if cycle == 24 {
-            self.pc = *state.intermediate;
+            self.pc = self.intermediate();
             self.instr_state = None;
         }
     }
 
+    //JR takes 12 clocks, 8 if the condition fails. The offset is relative to the next instruction.
     fn jr_i8(&mut self, cycle: u32, memory: &mut Memory, conditions: Vec<Flags>) {
         if cycle == 8 {
-            let mut jump = true;
-            for condition in conditions {
-                if !self.registers.get_flag(condition) { jump = false }
+            if !self.condition_met(&conditions) {
+                self.instr_state = None;
+                self.pc = self.pc + 2;
             }
-            if !jump { self.instr_state = None; }
         } else if cycle == 12 {
-            let e8 = memory.read(self.pc + 1) as i8;
-            let val = e8.abs() as u8;
-            let res = if e8 >= 0 { self.pc.overflowing_add(val as u16) } else { self.pc.overflowing_sub(val as u16) };
-            self.pc = res.0;
+            let e8 = self.fetch_u8_immediate(memory) as i8;
+            self.pc = self.pc.wrapping_add(2).wrapping_add(e8 as u16);
             self.instr_state = None;
-            self.pc = self.pc + 2;
         }
     }
 
+    //JP takes 16 clocks, 12 if the condition fails
     fn jp_u16(&mut self, cycle: u32, memory: &mut Memory, conditions: Vec<Flags>) {
-        let mut state = match &self.instr_state {
-            Some(x) => x,
-            None => panic!("Invalid state"),
-        };
         if cycle == 8 {
-            *state.intermediate = memory.read(self.pc + 1);
+            self.set_intermediate(memory.read(self.pc + 1) as u16);
         } else if cycle == 12 {
-            *state.intermediate  = *state.intermediate + ((memory.read(self.pc + 2) as u16) << 8);
-            let mut jump = true;
-            for condition in conditions {
-                if !self.registers.get_flag(condition) { jump = false }
+            self.set_intermediate(self.intermediate() + ((memory.read(self.pc + 2) as u16) << 8));
+            if !self.condition_met(&conditions) {
+                self.instr_state = None;
+                self.pc = self.pc + 3;
             }
-            if !jump { self.instr_state = None; }
         } else if cycle == 16 {
-            self.pc = *state.intermediate;
+            self.pc = self.intermediate();
             self.instr_state = None;
         }
     }
@@ -2218,48 +1873,70 @@ impl CPU {
         }
     }
 
-    fn di(&mut self, cycle: u32) {
+    /*
+    STOP is encoded as 0x10 0x00. If a CGB speed switch was armed through KEY1 it is performed and execution carries on,
+    otherwise the CPU stops until a button is pressed.
+     */
+    fn stop(&mut self, cycle: u32, memory: &mut Memory) {
         if cycle == 4 {
-            self.interrupts.reset_ime();
+            if !memory.stop() {
+                self.state = CpuState::Stopped;
+            }
             self.instr_state = None;
-            self.pc + self.pc + 1;
+            self.pc = self.pc + 2;
         }
     }
 
-    fn ei(&mut self, cycle: u32) {
+    fn di(&mut self, cycle: u32, memory: &mut Memory) {
         if cycle == 4 {
-            self.interrupts.ei();
-            self.pc + self.pc + 1;
+            memory.interrupts.reset_ime();
+            self.instr_state = None;
+            self.pc = self.pc + 1;
+        }
+    }
+
+    fn ei(&mut self, cycle: u32, memory: &mut Memory) {
+        if cycle == 4 {
+            memory.interrupts.ei();
+            self.instr_state = None;
+            self.pc = self.pc + 1;
         }
     }
 
     fn reti(&mut self, cycle: u32, memory: &mut Memory) {
-        let mut state = match &self.instr_state {
-            Some(x) => x,
-            None => panic!("Invalid state"),
-        };
         if cycle == 8 {
-            *state.intermediate = memory.read(self.sp);
-            self.sp = self.sp + 1;
+            self.set_intermediate(memory.read(self.sp) as u16);
+            self.sp = self.sp.wrapping_add(1);
         } else if cycle == 12 {
-            *state.intermediate = *state.intermediate + ((memory.read(self.sp) as u16) << 8);
-            self.sp = self.sp + 1;
+            self.set_intermediate(self.intermediate() + ((memory.read(self.sp) as u16) << 8));
+            self.sp = self.sp.wrapping_add(1);
         } else if cycle == 16 {
-            self.interrupts.set_ime();
-            self.pc = *state.intermediate;
+            memory.interrupts.set_ime();
+            self.pc = self.intermediate();
             self.instr_state = None;
-            self.pc = self.pc + 1;
         }
     }
 
     fn prefix(&mut self, cycle: u32) {
         if cycle == 4 {
-            let mut state = match &self.instr_state {
-                Some(x) => x,
+            match &mut self.instr_state {
+                Some(x) => x.prefix = true,
                 None => panic!("Invalid state"),
-            };
-            *state.prefix = true;
+            }
             self.pc = self.pc + 1;
         }
     }
-}
\ No newline at end of file
+}
+
+//CB-prefixed rotate / shift operations, see rotate_shift8
+#[derive(Clone, Copy)]
+enum ShiftOp {
+    Rlc,
+    Rl,
+    Rrc,
+    Rr,
+    Sla,
+    Sra,
+    Swap,
+    Srl,
+}
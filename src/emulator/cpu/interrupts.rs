@@ -75,7 +75,7 @@ impl InterruptRegisters {
             Interrupt::Serial => mask = 0b00001000,
             Interrupt::Joypad => mask = 0b00010000,
         }
-        return (self.bits & mask) > 0;
+        return (self.flags & mask) > 0;
     }
 
     pub fn set_flag(&mut self, interrupt: Interrupt) {
@@ -87,7 +87,7 @@ impl InterruptRegisters {
             Interrupt::Serial => mask = 0b00001000,
             Interrupt::Joypad => mask = 0b00010000,
         }
-        self.bits = self.bits | mask;
+        self.flags = self.flags | mask;
     }
 
     pub fn reset_flag(&mut self, interrupt: Interrupt) {
@@ -99,11 +99,34 @@ impl InterruptRegisters {
             Interrupt::Serial => mask = 0b11110111,
             Interrupt::Joypad => mask = 0b11101111,
         }
-        self.bits = self.bits & mask;
+        self.flags = self.flags & mask;
+    }
+
+    //IF register, bits 5-7 are unused and read as 1
+    pub fn read_flags(&self) -> u8 {
+        0b11100000 | self.flags
+    }
+
+    pub fn write_flags(&mut self, data: u8) {
+        self.flags = data & 0b00011111;
+    }
+
+    //IE register, all 8 bits are readable and writable
+    pub fn read_enable(&self) -> u8 {
+        self.enable
+    }
+
+    pub fn write_enable(&mut self, data: u8) {
+        self.enable = data;
     }
 
     pub fn enabled_any(&self) -> bool {
-        return self.bits & 0b00011111 > 0;
+        return self.flags & 0b00011111 > 0;
+    }
+
+    //An enabled interrupt is requested, wakes the CPU from HALT regardless of IME
+    pub fn pending(&self) -> bool {
+        return self.enable & self.flags & 0b00011111 > 0;
     }
 
     pub fn get_ime(&self) -> bool {
@@ -130,13 +153,14 @@ impl InterruptRegisters {
 
     pub fn check_ei(&mut self) {
         match &self.state {
-            InterruptStatePending(x) => {
+            InterruptState::Pending(x) => {
                 if *x == 0 {
                     self.state = InterruptState::Pending(1);
                 } else {
-                    self.set_ime;
+                    self.set_ime();
                 }
-            }
+            },
+            _ => ()
         }
     }
 }
\ No newline at end of file
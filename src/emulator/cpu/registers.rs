@@ -70,6 +70,20 @@ impl Registers {
         }
     }
 
+    //All registers cleared, as they are when the boot ROM starts executing
+    pub fn power_on() -> Self {
+        Self {
+            a: 0,
+            b: 0,
+            c: 0,
+            d: 0,
+            e: 0,
+            f: 0,
+            h: 0,
+            l: 0,
+        }
+    }
+
     pub fn get8(&self, r: Register8) -> u8 {
         match r {
             Register8::A => self.a,
@@ -90,7 +104,7 @@ impl Registers {
             Register8::C => self.c = val,
             Register8::D => self.d = val,
             Register8::E => self.e = val,
-            Register8::F => self.f = val,
+            Register8::F => self.f = val & 0xF0,
             Register8::H => self.h = val,
             Register8::L => self.l = val,
         }
@@ -98,10 +112,10 @@ impl Registers {
 
     pub fn get16(&self, rr: Register16) -> u16 {
         match rr {
-            Register16::AF => (self.a as u16) << 8 + self.f,
-            Register16::BC => (self.b as u16) << 8 + self.c,
-            Register16::DE => (self.d as u16) << 8 + self.e,
-            Register16::HL => (self.h as u16) << 8 + self.l,
+            Register16::AF => ((self.a as u16) << 8) + self.f as u16,
+            Register16::BC => ((self.b as u16) << 8) + self.c as u16,
+            Register16::DE => ((self.d as u16) << 8) + self.e as u16,
+            Register16::HL => ((self.h as u16) << 8) + self.l as u16,
         }
     }
 
@@ -109,7 +123,7 @@ impl Registers {
         match rr {
             Register16::AF => {
                 self.a = (val >> 8) as u8;
-                self.f = (val & 0x00FF) as u8;
+                self.f = (val & 0x00F0) as u8;
             },
             Register16::BC => {
                 self.b = (val >> 8) as u8;
@@ -126,216 +140,6 @@ impl Registers {
         }
     }
 
-    pub fn rotate_left_circular8(&mut self, r: Register8) {
-        match r {
-            Register8::A => {
-                let high = (self.a & 0xA0) >> 7;
-                self.set_c_flag(high == 1);
-                self.a = self.a << 1;
-                self.a += high;
-            },
-            Register8::B => {
-                let high = (self.b & 0xA0) >> 7;
-                self.set_c_flag(self.b & 0xA0 == 0xA0);
-                self.b = self.b << 1;
-                self.b += high;
-            },
-            Register8::C => {
-                let high = (self.c & 0xA0) >> 7;
-                self.set_c_flag(self.c & 0xA0 == 0xA0);
-                self.c = self.c << 1;
-                self.c += high;
-            },
-            Register8::D => {
-                let high = (self.d & 0xA0) >> 7;
-                self.set_c_flag(self.d & 0xA0 == 0xA0);
-                self.d = self.d << 1;
-                self.d += high;
-            },
-            Register8::E => {
-                let high = (self.e & 0xA0) >> 7;
-                self.set_c_flag(self.e & 0xA0 == 0xA0);
-                self.e = self.e << 1;
-                self.e += high;
-            },
-            Register8::F => {
-                let high = (self.f & 0xA0) >> 7;
-                self.set_c_flag(self.f & 0xA0 == 0xA0);
-                self.f = self.f << 1;
-                self.f += high;
-            },
-            Register8::H => {
-                let high = (self.h & 0xA0) >> 7;
-                self.set_c_flag(self.h & 0xA0 == 0xA0);
-                self.h = self.h << 1;
-                self.h += high;
-            },
-            Register8::L => {
-                let high = (self.l & 0xA0) >> 7;
-                self.set_c_flag(self.l & 0xA0 == 0xA0);
-                self.l = self.l << 1;
-                self.l += high;
-            }
-        }
-        self.set_z_flag(false);
-        self.set_n_flag(false);
-        self.set_h_flag(false);
-    }
-
-    pub fn rotate_left8(&mut self, r: Register8) {
-        let cur_c = self.get_c_flag();
-        match r {
-            Register8::A => {
-                self.set_c_flag(self.a & 0xA0 == 0xA0);
-                self.a = self.a << 1;
-                self.a += cur_c;
-            },
-            Register8::B => {
-                self.set_c_flag(self.b & 0xA0 == 0xA0);
-                self.b = self.b << 1;
-                self.b += cur_c;
-            },
-            Register8::C => {
-                self.set_c_flag(self.c & 0xA0 == 0xA0);
-                self.c = self.c << 1;
-                self.c += cur_c;
-            },
-            Register8::D => {
-                self.set_c_flag(self.d & 0xA0 == 0xA0);
-                self.d = self.d << 1;
-                self.d += cur_c;
-            },
-            Register8::E => {
-                self.set_c_flag(self.e & 0xA0 == 0xA0);
-                self.e = self.e << 1;
-                self.e += cur_c;
-            },
-            Register8::F => {
-                self.set_c_flag(self.f & 0xA0 == 0xA0);
-                self.f = self.f << 1;
-                self.f += cur_c;
-            },
-            Register8::H => {
-                self.set_c_flag(self.h & 0xA0 == 0xA0);
-                self.h = self.h << 1;
-                self.h += cur_c;
-            },
-            Register8::L => {
-                self.set_c_flag(self.l & 0xA0 == 0xA0);
-                self.l = self.l << 1;
-                self.l += cur_c;
-            }
-        }
-        self.set_z_flag(false);
-        self.set_n_flag(false);
-        self.set_h_flag(false);
-    }
-
-    pub fn rotate_right_circular8(&mut self, r: Register8) {
-        match r {
-            Register8::A => {
-                let low = self.a & 0x01;
-                self.set_c_flag(self.a & 0x01 == 0x01);
-                self.a = self.a >> 1;
-                self.a += low << 7;
-            },
-            Register8::B => {
-                let low = self.b & 0x01;
-                self.set_c_flag(self.b & 0x01 == 0x01);
-                self.b = self.b >> 1;
-                self.b += low << 7;
-            },
-            Register8::C => {
-                let low = self.c & 0x01;
-                self.set_c_flag(self.c & 0x01 == 0x01);
-                self.c = self.c >> 1;
-                self.c += low << 7;
-            },
-            Register8::D => {
-                let low = self.d & 0x01;
-                self.set_c_flag(self.d & 0x01 == 0x01);
-                self.d = self.d >> 1;
-                self.d += low << 7;
-            },
-            Register8::E => {
-                let low = self.e & 0x01;
-                self.set_c_flag(self.e & 0x01 == 0x01);
-                self.e = self.e >> 1;
-                self.e += low << 7;
-            },
-            Register8::F => {
-                let low = self.f & 0x01;
-                self.set_c_flag(self.f & 0x01 == 0x01);
-                self.f = self.f >> 1;
-                self.f += low << 7;
-            },
-            Register8::H => {
-                let low = self.h & 0x01;
-                self.set_c_flag(self.h & 0x01 == 0x01);
-                self.h = self.h >> 1;
-                self.h += low << 7;
-            },
-            Register8::L => {
-                let low = self.l & 0x01;
-                self.set_c_flag(self.l & 0x01 == 0x01);
-                self.l = self.l >> 1;
-                self.l += low << 7;
-            }
-        }
-        self.set_z_flag(false);
-        self.set_n_flag(false);
-        self.set_h_flag(false);
-    }
-
-    pub fn rotate_right8(&mut self, r: Register8) {
-        let cur_c = self.get_c_flag();
-        match r {
-            Register8::A => {
-                self.set_c_flag(self.a & 0x01 == 0x01);
-                self.a = self.a >> 1;
-                self.a += cur_c << 7;
-            },
-            Register8::B => {
-                self.set_c_flag(self.b & 0x01 == 0x01);
-                self.b = self.b >> 1;
-                self.b += cur_c << 7;
-            },
-            Register8::C => {
-                self.set_c_flag(self.c & 0x01 == 0x01);
-                self.c = self.c >> 1;
-                self.c += cur_c << 7;
-            },
-            Register8::D => {
-                self.set_c_flag(self.d & 0x01 == 0x01);
-                self.d = self.d >> 1;
-                self.d += cur_c << 7;
-            },
-            Register8::E => {
-                self.set_c_flag(self.e & 0x01 == 0x01);
-                self.e = self.e >> 1;
-                self.e += cur_c << 7;
-            },
-            Register8::F => {
-                self.set_c_flag(self.f & 0x01 == 0x01);
-                self.f = self.f >> 1;
-                self.f += cur_c << 7;
-            },
-            Register8::H => {
-                self.set_c_flag(self.h & 0x01 == 0x01);
-                self.h = self.h >> 1;
-                self.h += cur_c << 7;
-            },
-            Register8::L => {
-                self.set_c_flag(self.l & 0x01 == 0x01);
-                self.l = self.l >> 1;
-                self.l += cur_c << 7;
-            }
-        }
-        self.set_z_flag(false);
-        self.set_n_flag(false);
-        self.set_h_flag(false);
-    }
-
     //Set individual flag bit
     pub fn set_flag(&mut self, flag: Flags) {
         match flag {
@@ -370,10 +174,10 @@ impl Registers {
 impl Register16 {
     pub fn sub_registers(&self) -> (Register8, Register8) {
         match *self {
-            AF => (Register8::A, Register8::F),
-            BC => (Register8::B, Register8::C),
-            DE => (Register8::D, Register8::E),
-            HL => (Register8::H, Register8::L),
+            Register16::AF => (Register8::A, Register8::F),
+            Register16::BC => (Register8::B, Register8::C),
+            Register16::DE => (Register8::D, Register8::E),
+            Register16::HL => (Register8::H, Register8::L),
         }
     }
 }
\ No newline at end of file
@@ -1,26 +1,37 @@
+use std::fmt;
+
 use crate::emulator::constants;
-use crate::emulator::memory::memory::Memory;
+use crate::emulator::memory::memory::{BootRomError, Memory};
+use crate::emulator::memory::header::{CartridgeHeader, CgbSupport, HeaderError};
+use crate::emulator::memory::gbs::{GbsError, GbsFile, GbsHeader};
 use crate::emulator::cpu::cpu::CPU;
 use crate::emulator::timer::timer::Timer;
-use crate::emulator::joypad::joypad::Joypad;
-use crate::emulator::ppu::video::VideoController;
-use crate::emulator::cpu::registers::Interrupt;
+use crate::emulator::ppu::ppu;
+use crate::emulator::joypad::joypad::Button;
+use crate::emulator::serial::tcp::TcpLink;
+use crate::emulator::cpu::interrupts::Interrupt;
 use crate::emulator::cpu::cpu::CpuState;
 
 
 /*
 Main Emulator struct
-Contains references to Memory, CPU, PPU, APU, Timer, and Joypad subsystems
+Contains the CPU and Memory. Memory owns the memory-mapped subsystems (PPU, APU, Timer, Joypad, interrupt registers)
 Accessed from application UI code to tick the configure and tick the emulator backend
  */
 pub struct Emulator {
     memory: Memory,
     cpu: CPU,
-    timer: Timer,
-    joypad: Joypad,
-    video: VideoController,
     timer_state: TimerState,
     interrupt_state: InterruptState,
+    rumble: bool,
+    rumble_callback: Option<Box<dyn FnMut(bool) + Send>>,
+    save_flush_counter: u32,
+    options: EmulatorOptions,
+    gbs: Option<GbsPlayer>,
+    link: Option<TcpLink>,
+    link_window: u32,
+    link_error: Option<std::io::Error>,
+    boot_rom_error: Option<BootRomError>,
 }
 
 enum TimerState {
@@ -36,39 +47,222 @@ enum InterruptState {
     LoadVector(Interrupt),
 }
 
+/*
+GBS playback state
+file: the parsed rip, rebuilt into a fresh cartridge image whenever the track changes
+track: current track, 0-based
+ */
+struct GbsPlayer {
+    file: GbsFile,
+    track: u8,
+}
+
+//Reasons a cartridge or GBS file can't be loaded
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::io::Error),
+    Header(HeaderError),
+    Gbs(GbsError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "Unable to read ROM: {}", e),
+            LoadError::Header(e) => write!(f, "{}", e),
+            LoadError::Gbs(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<std::io::Error> for LoadError {
+    fn from(e: std::io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+impl From<HeaderError> for LoadError {
+    fn from(e: HeaderError) -> Self {
+        LoadError::Header(e)
+    }
+}
+
+impl From<GbsError> for LoadError {
+    fn from(e: GbsError) -> Self {
+        LoadError::Gbs(e)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Platform {
     DMG,
     GBC
 }
 
+/*
+Startup configuration for the emulator
+model: force DMG or GBC hardware instead of picking it from the cartridge header
+boot_rom: path to a DMG (256 byte) or CGB (2304 byte) boot ROM to run before the cartridge
+color_correction: mimic the CGB LCD's colour response in frame_rgb()
+ */
+#[derive(Clone, Default)]
+pub struct EmulatorOptions {
+    pub model: Option<Platform>,
+    pub boot_rom: Option<String>,
+    pub color_correction: bool,
+}
+
 impl Emulator {
-    pub fn new(path: String) -> Self {
-        let platform: Platform;
-        if path.ends_with(".gb") {
-            platform = Platform::DMG;
-        } else if path.ends_with((".gbc")) {
-            platform = Platform::GBC;
-        } else {
-            panic!("Unrecognized file type, please provide .gb or .gbc file");
-        }
-        let memory = Memory::new(path);
-        let cpu = CPU::new(&platform);
-        let timer = Timer::new(&platform);
-        let joypad = Joypad::new();
-        let video = VideoController::new();
+    pub fn new(path: String) -> Result<Self, LoadError> {
+        Emulator::with_options(path, EmulatorOptions::default())
+    }
+
+    /*
+    Loads a cartridge, or a .gbs rip (detected from its "GBS" signature) which starts playing its first track.
+    Fails if the file can't be read, or if the image has no complete header or uses an unsupported mapper.
+     */
+    pub fn with_options(path: String, options: EmulatorOptions) -> Result<Self, LoadError> {
+        let rom_data = std::fs::read(&path)?;
+        return Emulator::load(rom_data, Some(&path), options);
+    }
+
+    /*
+    Same as with_options for an image that is already in memory. Battery RAM is never written to disk, so several
+    instances can run the same game (e.g. in a LinkedPair) without sharing a save file.
+     */
+    pub fn from_rom(rom_data: Vec<u8>, options: EmulatorOptions) -> Result<Self, LoadError> {
+        return Emulator::load(rom_data, None, options);
+    }
+
+    fn load(rom_data: Vec<u8>, path: Option<&str>, options: EmulatorOptions) -> Result<Self, LoadError> {
+        if GbsFile::is_gbs(&rom_data) {
+            let gbs = GbsFile::parse(&rom_data)?;
+            let track = std::cmp::min(gbs.header.first_song.saturating_sub(1), gbs.header.song_count - 1);
+            let memory = Memory::from_rom(gbs.build_rom(track), None)?;
+            let mut emulator = Emulator::from_memory(memory, options);
+            emulator.gbs = Some(GbsPlayer { file: gbs, track });
+            return Ok(emulator);
+        }
+        let memory = Memory::from_rom(rom_data, path)?;
+        return Ok(Emulator::from_memory(memory, options));
+    }
+
+    fn from_memory(memory: Memory, options: EmulatorOptions) -> Self {
+        let (memory, cpu, boot_rom_error) = Emulator::power_up(memory, &options);
         Self {
             memory,
             cpu,
-            timer,
-            joypad,
-            video,
-            timer_state: TimerState::Nil,
-            interrupt_state: InterruptState::Nil,
+            timer_state: TimerState::Normal,
+            interrupt_state: InterruptState::Ready,
+            rumble: false,
+            rumble_callback: None,
+            save_flush_counter: 0,
+            options,
+            gbs: None,
+            link: None,
+            link_window: 0,
+            link_error: None,
+            boot_rom_error,
+        }
+    }
+
+    /*
+    Map the boot ROM (if any), pick the hardware model and set up the CPU / timer state it starts from.
+    A boot ROM that can't be read or has the wrong size is returned as an error and the emulator starts without it.
+     */
+    fn power_up(mut memory: Memory, options: &EmulatorOptions) -> (Memory, CPU, Option<BootRomError>) {
+        let boot_rom = match &options.boot_rom {
+            Some(x) => Emulator::load_boot_rom(&mut memory, x),
+            None => Ok(None),
+        };
+        let (boot_rom_size, boot_rom_error) = match boot_rom {
+            Ok(x) => (x, None),
+            Err(e) => (None, Some(e)),
+        };
+        //A boot ROM only runs on the hardware it was dumped from, so it decides the model unless one is forced
+        let platform = match (options.model, boot_rom_size) {
+            (Some(x), _) => x,
+            (None, Some(x)) => if x == constants::CGB_BOOT_ROM_SIZE { Platform::GBC } else { Platform::DMG },
+            (None, None) => Emulator::detect_platform(memory.header()),
+        };
+        //Without a boot ROM, start at 0x0100 with the register values the boot ROM would have left behind
+        let booting = boot_rom_size.is_some();
+        let (cpu, timer) = if booting { (CPU::power_on(), Timer::power_on()) } else { (CPU::new(&platform), Timer::new(&platform)) };
+        memory.timer = timer;
+        memory.set_platform(platform);
+        if !booting { memory.load_post_boot_state(); }
+        //The CGB boot ROM would have switched a DMG game to compatibility mode, here it gets plain grey palettes
+        if !booting && platform == Platform::GBC && memory.header().cgb_support() == CgbSupport::DmgOnly {
+            memory.enter_compatibility_mode();
+            memory.ppu.load_dmg_palettes();
+        }
+        return (memory, cpu, boot_rom_error);
+    }
+
+    //Returns the size of the mapped boot ROM
+    fn load_boot_rom(memory: &mut Memory, path: &str) -> Result<Option<usize>, BootRomError> {
+        let data = match std::fs::read(path) {
+            Ok(x) => x,
+            Err(e) => return Err(BootRomError::Io(e)),
+        };
+        let size = data.len();
+        memory.load_boot_rom(data)?;
+        return Ok(Some(size));
+    }
+
+    //Why the configured boot ROM isn't running, if it couldn't be used
+    pub fn boot_rom_error(&self) -> Option<&BootRomError> {
+        self.boot_rom_error.as_ref()
+    }
+
+    /*
+    Hardware mode comes from the CGB flag at 0x0143 rather than the file extension.
+    CGB-enhanced games run on GBC hardware, as they would on a real Game Boy Color.
+     */
+    pub fn detect_platform(header: &CartridgeHeader) -> Platform {
+        match header.cgb_support() {
+            CgbSupport::CgbOnly | CgbSupport::Enhanced => Platform::GBC,
+            CgbSupport::DmgOnly => Platform::DMG,
+        }
+    }
+
+    //Write battery-backed RAM to <rom>.sav
+    pub fn save(&mut self) -> std::io::Result<()> {
+        self.save_flush_counter = 0;
+        return self.memory.save_battery();
+    }
+
+    /*
+    Periodically flush battery RAM so a crash doesn't lose progress. Meant to be called once per frame, it only touches
+    the disk once SAVE_FLUSH_CYCLES ticks have passed and external RAM was written since the last save.
+     */
+    pub fn flush_save(&mut self) -> std::io::Result<()> {
+        if self.save_flush_counter < constants::SAVE_FLUSH_CYCLES { return Ok(()); }
+        self.save_flush_counter = 0;
+        return self.memory.flush_battery();
+    }
+
+    /*
+    Registers a callback fired whenever a rumble cartridge switches its motor on (true) or off (false)
+     */
+    pub fn on_rumble<F: FnMut(bool) + Send + 'static>(&mut self, callback: F) {
+        self.rumble_callback = Some(Box::new(callback));
+    }
+
+    fn check_rumble(&mut self) {
+        let rumble = self.memory.rumble();
+        if rumble == self.rumble { return; }
+        self.rumble = rumble;
+        match &mut self.rumble_callback {
+            Some(callback) => callback(rumble),
+            None => ()
         }
     }
 
     /*
-        CPU needs reference to Memory, Timer, Video Controller to read/write values
+        CPU needs reference to Memory, Timer, PPU to read/write values
 
         First tick the timer module. If TIMA overflows, set the timer state so an interrupt will be set on the NEXT machine cycle.
         Next, check if any interrupt flags are set for interrupts that are enabled. If so:
@@ -78,36 +272,138 @@ impl Emulator {
                 - (2 machine cycles) Push current PC to stack
                 - (1 machine cycle) Set PC to Interrupt vector
         Next, fetch / decode / execute from memory[PC]
+
+        In CGB double-speed mode the CPU, timer, serial clock and OAM DMA run twice per tick while the PPU and cartridge keep their normal rate.
+        In STOP mode everything is frozen until a button is pressed.
      */
     pub fn tick(&mut self) {
-        //Tick the system internal timer (and thereby DIV). If TIMA overflows, set IF for timer overflow
+        //Counts every tick, including ones where the CPU is stopped or stalled, so both ends of a link stay in step
+        self.tick_link();
+        if self.cpu.state == CpuState::Stopped {
+            if !self.memory.joypad.input_low() { return; }
+            self.cpu.state = CpuState::Ready;
+        }
+        let cpu_ticks = if self.memory.double_speed() { 2 } else { 1 };
+        for _ in 0..cpu_ticks {
+            self.tick_timer();
+            //Copy the next byte of an active OAM DMA transfer
+            self.memory.tick_dma();
+            //Shift an internally clocked serial transfer
+            self.memory.tick_serial();
+        }
+        //Advance the PPU by one dot, raising VBlank / STAT interrupts
+        self.memory.tick_ppu();
+        //Advance the sound channels, producing a stereo sample every APU_CYCLES_PER_SAMPLE ticks
+        self.memory.apu.tick();
+        //Tick cartridge hardware (MBC3 real-time clock)
+        self.memory.tick_mbc();
+        self.check_rumble();
+        self.save_flush_counter = self.save_flush_counter.saturating_add(1);
+        //A CGB VRAM DMA transfer keeps the CPU off the bus until it completes
+        if self.memory.tick_vram_dma_stall() { return; }
+        for _ in 0..cpu_ticks {
+            self.tick_cpu();
+        }
+    }
+
+    /*
+    Connect the serial port to another instance, see serial::tcp. From now on tick() waits for the peer to catch up
+    at the end of every sync window (at most LINK_SYNC_TICKS ticks). If the connection fails or the peer stops
+    answering the cable is unplugged, the reason is kept for link_error() and emulation carries on.
+     */
+    pub fn attach_link(&mut self, link: TcpLink) {
+        self.link = Some(link);
+        self.link_window = 0;
+        self.link_error = None;
+        self.memory.set_serial_connected(true);
+    }
+
+    pub fn detach_link(&mut self) {
+        self.link = None;
+        self.memory.set_serial_connected(false);
+    }
+
+    pub fn linked(&self) -> bool {
+        self.link.is_some()
+    }
+
+    //Why the link cable was unplugged by itself, cleared when a new one is attached
+    pub fn link_error(&self) -> Option<&std::io::Error> {
+        self.link_error.as_ref()
+    }
+
+    /*
+    Serial port access for a link cable driven from outside the emulator, see linked::LinkedPair.
+    serial_transfer returns the byte of an internally clocked transfer once it has been shifted out, the peer's byte
+    is then passed back with serial_complete. serial_receive clocks a byte in from the peer and returns the reply.
+     */
+    pub fn serial_transfer(&mut self) -> Option<u8> {
+        self.memory.serial_transfer()
+    }
+
+    pub fn serial_complete(&mut self, data: u8) {
+        self.memory.serial_complete(data);
+    }
+
+    pub fn serial_receive(&mut self, data: u8) -> u8 {
+        self.memory.serial_receive(data)
+    }
+
+    pub fn set_serial_connected(&mut self, connected: bool) {
+        self.memory.set_serial_connected(connected);
+    }
+
+    fn tick_link(&mut self) {
+        let result = match &mut self.link {
+            Some(link) => {
+                if self.link_window > 0 {
+                    self.link_window -= 1;
+                    return;
+                }
+                link.sync(&mut self.memory)
+            },
+            None => return,
+        };
+        match result {
+            //This tick is the first of the new window
+            Ok(window) => self.link_window = window - 1,
+            Err(e) => {
+                self.detach_link();
+                self.link_error = Some(e);
+            }
+        }
+    }
+
+    //Tick the system internal timer (and thereby DIV). If TIMA overflows, set IF for timer overflow
+    fn tick_timer(&mut self) {
         match &self.timer_state {
             TimerState::InterruptReady => {
-                self.timer.set_tima();
+                self.memory.timer.set_tima();
                 self.timer_state = TimerState::Normal;
-                if self.timer.read_tima() == 0 { self.cpu.enable_interrupt(Interrupt::Timer); }
+                if self.memory.timer.read_tima() == 0 { self.memory.interrupts.set_flag(Interrupt::Timer); }
             },
             TimerState::Normal => {
                 for _ in 0..4 {
-                    if self.timer.tick() {
+                    if self.memory.timer.tick() {
                         self.timer_state = TimerState::InterruptReady;
-                        self.timer.reset_tima();
+                        self.memory.timer.reset_tima();
                         break;
                     }
                 }
             }
         }
-        //TODO -- Check Joypad. State to be passed in from Iced
+    }
 
+    fn tick_cpu(&mut self) {
         //check interrupts, transfer control via ISR if necessary
-        if self.cpu.state == CpuState::Ready {
+        if self.cpu.state == CpuState::Ready && self.cpu.instruction_boundary() {
             match &self.interrupt_state {
                 InterruptState::Ready => {
-                    match self.cpu.get_interrupt() {
+                    match self.cpu.get_interrupt(&self.memory) {
                         Some(x) => {
                             self.interrupt_state = InterruptState::Nop(x);
-                            self.cpu.reset_interrupt_flag(x);
-                            self.cpu.reset_ime();
+                            self.memory.interrupts.reset_flag(x);
+                            self.memory.interrupts.reset_ime();
                             return;
                         },
                         None => ()
@@ -127,28 +423,255 @@ impl Emulator {
                     return;
                 },
                 InterruptState::LoadVector(i) => {
-                    self.cpu.load_vector(*x);
+                    self.cpu.load_vector(*i);
                     self.interrupt_state = InterruptState::Ready;
                     return;
                 }
             }
         }
-        //Tick the CPU on each clock, pass exact cycle position into CPU
+        //Tick the CPU on each clock, it tracks its own position within the current instruction
         self.cpu.tick(&mut self.memory);
-        //increment cycle position (4 clock ticks per machine cycle)
-        self.cycle = if self.cycle == 3 { 0 } else { self.cycle + 1};
     }
 
     /*
-    pub fn validate_logo(&self) -> bool {
-        let mut valid = true;
-        for i in constants::LOGO_START..=constants::LOGO_END {
-            if self.memory.read(i as u16) != constants::NINTENDO_LOGO[i - constants::LOGO_START] {
-                valid = false;
-            }
+    Press (true) or release (false) a button. Called by the frontend on key events, a press also wakes the CPU from STOP.
+     */
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.memory.set_button(button, pressed);
+    }
+
+    //Stereo samples at APU_SAMPLE_RATE produced since the last call, each side in -1.0..=1.0
+    pub fn take_samples(&mut self) -> Vec<[f32; 2]> {
+        self.memory.apu.take_samples()
+    }
+
+    /*
+    Record the audio output to a .wav file until stop_recording is called. With `stems` set, each channel
+    (pulse 1, pulse 2, wave, noise) is also written to <name>_<channel>.wav. Needs no audio device.
+     */
+    pub fn start_recording(&mut self, path: &str, stems: bool) -> std::io::Result<()> {
+        self.memory.apu.start_recording(std::path::Path::new(path), stems)
+    }
+
+    pub fn stop_recording(&mut self) -> std::io::Result<()> {
+        self.memory.apu.stop_recording()
+    }
+
+    pub fn recording(&self) -> bool {
+        self.memory.apu.recording()
+    }
+
+    //Set when a recording ended because its files could not be written, cleared by the next start_recording
+    pub fn recording_error(&self) -> Option<&std::io::Error> {
+        self.memory.apu.recording_error()
+    }
+
+    //Read a byte the way the CPU sees it, without ticking anything. For debugging and tests.
+    pub fn read(&self, addr: u16) -> u8 {
+        self.memory.read(addr)
+    }
+
+    //Current 160x144 frame as 15-bit colours (bits 0-4 red, 5-9 green, 10-14 blue)
+    pub fn frame(&self) -> &[u16] {
+        self.memory.ppu.framebuffer()
+    }
+
+    //Current frame as packed 8-bit RGB triples, row-major
+    pub fn frame_rgb(&self) -> Vec<u8> {
+        let mut rgb: Vec<u8> = Vec::with_capacity(self.frame().len() * 3);
+        for color in self.frame() {
+            rgb.extend_from_slice(&ppu::to_rgb(*color, self.options.color_correction));
         }
-        println!("Logo validation = {}", valid);
-        return valid;
+        return rgb;
+    }
+
+    pub fn set_color_correction(&mut self, enabled: bool) {
+        self.options.color_correction = enabled;
     }
+
+    /*
+    GBS player controls. Changing track restarts the machine with the new track number passed to the rip's init routine,
+    a recording in progress is finished first. These do nothing when a cartridge is loaded.
      */
-}
\ No newline at end of file
+    pub fn gbs_header(&self) -> Option<&GbsHeader> {
+        match &self.gbs {
+            Some(x) => Some(&x.file.header),
+            None => None,
+        }
+    }
+
+    //Current track, 0-based
+    pub fn track(&self) -> Option<u8> {
+        match &self.gbs {
+            Some(x) => Some(x.track),
+            None => None,
+        }
+    }
+
+    pub fn select_track(&mut self, track: u8) -> Result<(), LoadError> {
+        let rom = match &self.gbs {
+            Some(x) => {
+                if track >= x.file.header.song_count {
+                    return Err(LoadError::Gbs(GbsError::TrackOutOfRange(track, x.file.header.song_count)));
+                }
+                x.file.build_rom(track)
+            },
+            None => return Ok(()),
+        };
+        let memory = Memory::from_rom(rom, None)?;
+        //The new APU starts without a recording, keep the reason the old one ended for recording_error()
+        let recording_error = match self.stop_recording() {
+            Ok(_) => self.memory.apu.take_recording_error(),
+            Err(e) => Some(e),
+        };
+        let (memory, cpu, boot_rom_error) = Emulator::power_up(memory, &self.options);
+        self.memory = memory;
+        self.memory.apu.set_recording_error(recording_error);
+        self.boot_rom_error = boot_rom_error;
+        self.memory.set_serial_connected(self.link.is_some());
+        self.cpu = cpu;
+        self.timer_state = TimerState::Normal;
+        self.interrupt_state = InterruptState::Ready;
+        match &mut self.gbs {
+            Some(x) => x.track = track,
+            None => ()
+        }
+        return Ok(());
+    }
+
+    //Wraps around to the first track
+    pub fn next_track(&mut self) -> Result<(), LoadError> {
+        let (track, count) = match &self.gbs {
+            Some(x) => (x.track, x.file.header.song_count),
+            None => return Ok(()),
+        };
+        return self.select_track(if track + 1 >= count { 0 } else { track + 1 });
+    }
+
+    //Wraps around to the last track
+    pub fn previous_track(&mut self) -> Result<(), LoadError> {
+        let (track, count) = match &self.gbs {
+            Some(x) => (x.track, x.file.header.song_count),
+            None => return Ok(()),
+        };
+        return self.select_track(if track == 0 { count - 1 } else { track - 1 });
+    }
+
+    //Decoded cartridge header, including the logo/checksum validation report
+    pub fn header(&self) -> &CartridgeHeader {
+        self.memory.header()
+    }
+}
+
+impl Drop for Emulator {
+    fn drop(&mut self) {
+        match self.stop_recording() {
+            Ok(_) => (),
+            Err(e) => println!("Unable to finish audio recording: {}", e),
+        }
+        //Last chance to keep progress if the frontend didn't save, there is nowhere else to report a failure here
+        match self.save() {
+            Ok(_) => (),
+            Err(e) => println!("Unable to write save file: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    //32 KiB ROM-only image whose first byte is 0xAA, so a mapped boot ROM can be told apart
+    fn plain_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0000] = 0xAA;
+        return rom;
+    }
+
+    fn boot_rom_options(name: &str, data: Option<Vec<u8>>) -> EmulatorOptions {
+        let path = std::env::temp_dir().join(format!("gameboyo_{}_{}.bin", name, std::process::id()));
+        match data {
+            Some(x) => std::fs::write(&path, x).unwrap(),
+            None => (),
+        }
+        return EmulatorOptions { boot_rom: Some(path.to_string_lossy().to_string()), ..EmulatorOptions::default() };
+    }
+
+    #[test]
+    fn boot_rom_is_mapped_over_the_cartridge() {
+        let options = boot_rom_options("boot_valid", Some(vec![0x31; constants::DMG_BOOT_ROM_SIZE]));
+        let emulator = Emulator::from_rom(plain_rom(), options.clone()).unwrap();
+        assert!(emulator.boot_rom_error().is_none());
+        assert_eq!(emulator.read(0x0000), 0x31);
+        std::fs::remove_file(options.boot_rom.unwrap()).unwrap();
+    }
+
+    #[test]
+    fn missing_boot_rom_falls_back_to_the_cartridge() {
+        let emulator = Emulator::from_rom(plain_rom(), boot_rom_options("boot_missing", None)).unwrap();
+        match emulator.boot_rom_error() {
+            Some(BootRomError::Io(_)) => (),
+            x => panic!("Expected a read error, got {:?}", x),
+        }
+        assert_eq!(emulator.read(0x0000), 0xAA);
+    }
+
+    #[test]
+    fn wrong_size_boot_rom_falls_back_to_the_cartridge() {
+        let options = boot_rom_options("boot_size", Some(vec![0x31; 0x200]));
+        let emulator = Emulator::from_rom(plain_rom(), options.clone()).unwrap();
+        match emulator.boot_rom_error() {
+            Some(BootRomError::InvalidSize(0x200)) => (),
+            x => panic!("Expected a size error, got {:?}", x),
+        }
+        assert_eq!(emulator.read(0x0000), 0xAA);
+        std::fs::remove_file(options.boot_rom.unwrap()).unwrap();
+    }
+
+    #[test]
+    fn rumble_motor_changes_reach_the_callback() {
+        //32 KiB MBC5+RUMBLE image that switches the motor on and off again, then spins
+        let mut rom = vec![0; 0x8000];
+        rom[constants::CARTRIDGE_TYPE] = 0x1C;
+        let program = [
+            0x3E, 0x08,         //LD A, 0x08
+            0xEA, 0x00, 0x40,   //LD (0x4000), A
+            0x3E, 0x00,         //LD A, 0x00
+            0xEA, 0x00, 0x40,   //LD (0x4000), A
+            0x18, 0xFE,         //JR -2
+        ];
+        rom[0x0100..0x0104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        rom[0x0150..0x0150 + program.len()].copy_from_slice(&program);
+
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let mut emulator = Emulator::from_rom(rom, EmulatorOptions::default()).unwrap();
+        let recorded = changes.clone();
+        emulator.on_rumble(move |on| recorded.lock().unwrap().push(on));
+        for _ in 0..1000 {
+            emulator.tick();
+        }
+        assert_eq!(*changes.lock().unwrap(), vec![true, false]);
+    }
+
+    #[test]
+    fn closed_link_is_reported_as_disconnected() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        //The peer completes the handshake and hangs up straight away
+        let peer = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            TcpLink::from_stream(stream).unwrap();
+        });
+        let link = TcpLink::connect(addr).unwrap();
+        peer.join().unwrap();
+
+        let mut emulator = Emulator::from_rom(plain_rom(), EmulatorOptions::default()).unwrap();
+        emulator.attach_link(link);
+        assert!(emulator.linked());
+        assert!(emulator.link_error().is_none());
+        emulator.tick();
+        assert!(!emulator.linked());
+        assert!(emulator.link_error().is_some());
+    }
+}
@@ -1,17 +1,157 @@
+/*
+Joypad input: https://gbdev.io/pandocs/Joypad_Input.html
+
+The 8 buttons are wired as a 2x4 matrix. Writing 0 to P1 bit 4 (P14) selects the d-pad, bit 5 (P15) the buttons,
+and bits 0-3 then read the selected keys, active low. If both lines are selected the keys are ORed together.
+
+select: select lines as last written (bits 4-5)
+pressed: held buttons, one bit per Button (1 = held)
+lines: input lines (bits 0-3) as last seen, used to detect high-to-low transitions
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
 pub struct Joypad {
-    p1: u8,
+    select: u8,
+    pressed: u8,
+    lines: u8,
 }
 
 impl Joypad {
     pub fn new() -> Self {
-        Self { p1: 0b11000000 }
+        Self {
+            select: 0b00110000,
+            pressed: 0,
+            lines: 0b00001111,
+        }
     }
 
-    pub fn set_bit(&mut self, bit: u8) {
-        self.p1 = 0b11000000 | (0b11111111 & (0b00000001 << bit));
+    //Bit in `pressed`: the d-pad uses the low nibble and the buttons the high nibble, in input line order
+    fn mask(button: Button) -> u8 {
+        match button {
+            Button::Right => 0b00000001,
+            Button::Left => 0b00000010,
+            Button::Up => 0b00000100,
+            Button::Down => 0b00001000,
+            Button::A => 0b00010000,
+            Button::B => 0b00100000,
+            Button::Select => 0b01000000,
+            Button::Start => 0b10000000,
+        }
     }
 
-    pub fn get_bit(&self, bit: u8) -> u8 {
-        return (self.p1 & (0b00000001 << bit)) >> bit;
+    //Current state of input lines 0-3 given the selected rows
+    fn read_lines(&self) -> u8 {
+        let mut lines = 0b00001111;
+        if self.select & 0b00010000 == 0 {
+            lines = lines & !(self.pressed & 0b00001111);
+        }
+        if self.select & 0b00100000 == 0 {
+            lines = lines & !(self.pressed >> 4);
+        }
+        return lines;
     }
-}
\ No newline at end of file
+
+    /*
+    Recompute the input lines, returning true if any went from high to low (the joypad interrupt condition).
+    This can happen from a button press or from selecting a row that has a button held.
+     */
+    fn update_lines(&mut self) -> bool {
+        let lines = self.read_lines();
+        let falling = self.lines & !lines > 0;
+        self.lines = lines;
+        return falling;
+    }
+
+    //Press or release a button. Returns true if the joypad interrupt should be requested.
+    pub fn set_button(&mut self, button: Button, pressed: bool) -> bool {
+        if pressed {
+            self.pressed = self.pressed | Joypad::mask(button);
+        } else {
+            self.pressed = self.pressed & !Joypad::mask(button);
+        }
+        return self.update_lines();
+    }
+
+    pub fn pressed(&self, button: Button) -> bool {
+        self.pressed & Joypad::mask(button) > 0
+    }
+
+    //True if any button on a selected line is held (input bits 0-3 are active low)
+    pub fn input_low(&self) -> bool {
+        self.read_lines() != 0b00001111
+    }
+
+    //Bits 6-7 are unused and read as 1
+    pub fn read_register(&self) -> u8 {
+        0b11000000 | self.select | self.read_lines()
+    }
+
+    //Only the select lines (bits 4-5) are writable. Returns true if the joypad interrupt should be requested.
+    pub fn write_register(&mut self, data: u8) -> bool {
+        self.select = data & 0b00110000;
+        return self.update_lines();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_lines_choose_the_nibble() {
+        let mut joypad = Joypad::new();
+        joypad.set_button(Button::Right, true);
+        joypad.set_button(Button::Start, true);
+        //Neither row selected
+        assert_eq!(joypad.read_register(), 0xFF);
+        //P14 low: d-pad
+        joypad.write_register(0b00100000);
+        assert_eq!(joypad.read_register(), 0b11101110);
+        //P15 low: buttons
+        joypad.write_register(0b00010000);
+        assert_eq!(joypad.read_register(), 0b11010111);
+        //Both rows are ORed together
+        joypad.write_register(0b00000000);
+        assert_eq!(joypad.read_register(), 0b11000110);
+        joypad.set_button(Button::Right, false);
+        joypad.set_button(Button::Start, false);
+        assert_eq!(joypad.read_register(), 0b11001111);
+    }
+
+    #[test]
+    fn only_falling_lines_request_the_interrupt() {
+        let mut joypad = Joypad::new();
+        //Nothing selected, the press doesn't reach the lines
+        assert!(!joypad.set_button(Button::A, true));
+        joypad.write_register(0b00100000);
+        assert!(!joypad.set_button(Button::Left, false));
+        assert!(joypad.set_button(Button::Left, true));
+        //Already low
+        assert!(!joypad.set_button(Button::Left, true));
+        assert!(!joypad.set_button(Button::Left, false));
+        //Selecting the row with A held pulls line 0 low
+        assert!(joypad.write_register(0b00010000));
+        assert!(!joypad.write_register(0b00010000));
+        assert!(!joypad.write_register(0b00110000));
+    }
+
+    #[test]
+    fn pressed_keys_are_tracked_without_a_selected_row() {
+        let mut joypad = Joypad::new();
+        joypad.set_button(Button::Down, true);
+        assert!(joypad.pressed(Button::Down));
+        assert!(!joypad.input_low());
+        joypad.write_register(0b00100000);
+        assert!(joypad.input_low());
+    }
+}
@@ -0,0 +1,152 @@
+use crate::emulator::constants;
+use crate::emulator::emulator::Emulator;
+
+/*
+Two emulators joined by a link cable in the same process, e.g. for testing trades and battles.
+
+Both are stepped one tick at a time in a fixed order and bytes cross the cable on the tick the sending transfer
+finishes, so a run depends only on the two ROMs and their inputs. There are no sockets or threads involved.
+ */
+pub struct LinkedPair {
+    first: Emulator,
+    second: Emulator,
+}
+
+impl LinkedPair {
+    pub fn new(mut first: Emulator, mut second: Emulator) -> Self {
+        first.set_serial_connected(true);
+        second.set_serial_connected(true);
+        Self {
+            first,
+            second,
+        }
+    }
+
+    //Unplug the cable and hand both emulators back
+    pub fn into_inner(mut self) -> (Emulator, Emulator) {
+        self.first.set_serial_connected(false);
+        self.second.set_serial_connected(false);
+        return (self.first, self.second);
+    }
+
+    pub fn tick(&mut self) {
+        self.first.tick();
+        self.second.tick();
+        self.exchange();
+    }
+
+    //One frame's worth of ticks on both emulators
+    pub fn run_frame(&mut self) {
+        for _ in 0..constants::DOTS_PER_FRAME {
+            self.tick();
+        }
+    }
+
+    /*
+    Deliver a byte whose transfer finished this tick. The side clocking the transfer gets the other side's SB back,
+    or 0xFF if the other side isn't waiting for a transfer. If both sides clock a transfer at once each gets the other's byte.
+     */
+    fn exchange(&mut self) {
+        match (self.first.serial_transfer(), self.second.serial_transfer()) {
+            (Some(x), Some(y)) => {
+                self.first.serial_complete(y);
+                self.second.serial_complete(x);
+            },
+            (Some(x), None) => {
+                let reply = self.second.serial_receive(x);
+                self.first.serial_complete(reply);
+            },
+            (None, Some(y)) => {
+                let reply = self.first.serial_receive(y);
+                self.second.serial_complete(reply);
+            },
+            (None, None) => ()
+        }
+    }
+
+    pub fn first(&self) -> &Emulator {
+        &self.first
+    }
+
+    pub fn second(&self) -> &Emulator {
+        &self.second
+    }
+
+    //For input, recording and the other per-instance controls
+    pub fn first_mut(&mut self) -> &mut Emulator {
+        &mut self.first
+    }
+
+    pub fn second_mut(&mut self) -> &mut Emulator {
+        &mut self.second
+    }
+
+    //Current frames of both emulators (see Emulator::frame), first then second
+    pub fn frames(&self) -> (&[u16], &[u16]) {
+        (self.first.frame(), self.second.frame())
+    }
+
+    //Both frames as packed RGB rows, placed side by side in one 320x144 image
+    pub fn frames_rgb(&self) -> Vec<u8> {
+        let first = self.first.frame_rgb();
+        let second = self.second.frame_rgb();
+        let row = constants::SCREEN_X_DIM as usize * 3;
+        let mut rgb: Vec<u8> = Vec::with_capacity(first.len() + second.len());
+        for (left, right) in first.chunks(row).zip(second.chunks(row)) {
+            rgb.extend_from_slice(left);
+            rgb.extend_from_slice(right);
+        }
+        return rgb;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::emulator::EmulatorOptions;
+
+    //32 KiB ROM-only image that loads data into SB, starts a transfer with the given SC value and then spins
+    fn serial_rom(data: u8, control: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        let program = [
+            0x3E, data,     //LD A, data
+            0xE0, 0x01,     //LDH (SB), A
+            0x3E, control,  //LD A, control
+            0xE0, 0x02,     //LDH (SC), A
+            0x18, 0xFE,     //JR -2
+        ];
+        //The header lives at 0x0104-0x014F, so jump over it from the entry point
+        rom[0x0100..0x0104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        rom[0x0150..0x0150 + program.len()].copy_from_slice(&program);
+        return rom;
+    }
+
+    fn emulator(data: u8, control: u8) -> Emulator {
+        return Emulator::from_rom(serial_rom(data, control), EmulatorOptions::default()).unwrap();
+    }
+
+    #[test]
+    fn bytes_cross_the_cable_both_ways() {
+        //The first side drives the clock, the second waits for it
+        let mut pair = LinkedPair::new(emulator(0x12, 0x81), emulator(0x34, 0x80));
+        for _ in 0..2 {
+            pair.run_frame();
+        }
+        let (first, second) = pair.into_inner();
+        assert_eq!(first.read(constants::SB as u16), 0x34);
+        assert_eq!(second.read(constants::SB as u16), 0x12);
+        assert_eq!(first.read(constants::SC as u16) & constants::SC_TRANSFER, 0);
+        assert_eq!(second.read(constants::SC as u16) & constants::SC_TRANSFER, 0);
+    }
+
+    #[test]
+    fn unanswered_transfer_shifts_in_ones() {
+        //Neither side drives the clock for the second, so only the first side's transfer completes
+        let mut pair = LinkedPair::new(emulator(0x12, 0x81), emulator(0x34, 0x00));
+        for _ in 0..2 {
+            pair.run_frame();
+        }
+        assert_eq!(pair.first().read(constants::SB as u16), 0xFF);
+        assert_eq!(pair.second().read(constants::SB as u16), 0x34);
+    }
+}
@@ -0,0 +1,311 @@
+/*
+GBS (Game Boy Sound) rip files: https://ocremix.org/info/GBS_Format_Specification
+
+A GBS file is a 0x70 byte header followed by the music code and data, which is loaded at `load_address`.
+To play one, the data is wrapped in a generated MBC5 cartridge image together with a small driver:
+    * 0x0000-0x0038: RST vectors, redirected to load_address + vector as the format requires
+    * 0x0040 / 0x0050: VBlank / timer interrupt, call the play routine and return
+    * 0x0100: entry point, jumps to the driver
+    * 0x0104-0x014F: cartridge header (logo, title, MBC5+RAM, valid checksum) so boot ROMs accept the image
+    * 0x0150: driver, sets up the hardware, calls init with the track number in A, then idles while interrupts call play
+Bank switching works like MBC5, writes to 0x2000-0x3FFF select the bank at 0x4000-0x7FFF.
+ */
+use std::fmt;
+
+use crate::emulator::constants;
+
+const GBS_MAGIC: &[u8; 3] = b"GBS";
+const GBS_HEADER_SIZE: usize = 0x70;
+//The format requires the data to start above the driver area and inside the cartridge ROM
+const GBS_MIN_LOAD_ADDRESS: u16 = 0x0400;
+const GBS_MAX_LOAD_ADDRESS: u16 = 0x7FFF;
+const GBS_DRIVER_START: u16 = 0x0150;
+
+//Timer control bits in the GBS header
+const GBS_TIMER_INTERRUPT: u8 = 0b00000100;
+const GBS_DOUBLE_SPEED: u8 = 0b10000000;
+
+//Reasons a GBS file can't be played
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum GbsError {
+    //Missing the "GBS" signature
+    NotGbs,
+    //File length in bytes, no data after the 0x70 byte header
+    Truncated(usize),
+    //Data would be loaded over the driver or outside the cartridge ROM
+    LoadAddress(u16),
+    NoSongs,
+    //Requested track (0-based) and the number of tracks in the file
+    TrackOutOfRange(u8, u8),
+}
+
+impl fmt::Display for GbsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GbsError::NotGbs => write!(f, "Not a GBS file"),
+            GbsError::Truncated(x) => write!(f, "GBS file is truncated ({} bytes)", x),
+            GbsError::LoadAddress(x) => write!(f, "Unsupported GBS load address {:#06x}, expected {:#06x}-{:#06x}",
+                                               x, GBS_MIN_LOAD_ADDRESS, GBS_MAX_LOAD_ADDRESS),
+            GbsError::NoSongs => write!(f, "GBS file has no songs"),
+            GbsError::TrackOutOfRange(x, count) => write!(f, "Track {} out of range, the file has {} tracks", x + 1, count),
+        }
+    }
+}
+
+impl std::error::Error for GbsError {}
+
+#[derive(Clone, Debug)]
+pub struct GbsHeader {
+    pub version: u8,
+    pub song_count: u8,
+    pub first_song: u8,
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    pub stack_pointer: u16,
+    pub timer_modulo: u8,
+    pub timer_control: u8,
+    pub title: String,
+    pub author: String,
+    pub copyright: String,
+}
+
+impl GbsHeader {
+    //Play is driven by the timer interrupt (at the rate set by TMA/TAC) instead of VBlank
+    pub fn uses_timer(&self) -> bool {
+        self.timer_control & GBS_TIMER_INTERRUPT > 0
+    }
+
+    pub fn double_speed(&self) -> bool {
+        self.timer_control & GBS_DOUBLE_SPEED > 0
+    }
+}
+
+#[derive(Clone)]
+pub struct GbsFile {
+    pub header: GbsHeader,
+    data: Vec<u8>,
+}
+
+impl GbsFile {
+    pub fn is_gbs(data: &[u8]) -> bool {
+        data.len() >= GBS_MAGIC.len() && &data[..GBS_MAGIC.len()] == GBS_MAGIC
+    }
+
+    //Fails if the file isn't a GBS file, is truncated, has no songs or loads its data over the driver or outside ROM
+    pub fn parse(data: &[u8]) -> Result<Self, GbsError> {
+        if !GbsFile::is_gbs(data) { return Err(GbsError::NotGbs); }
+        if data.len() <= GBS_HEADER_SIZE { return Err(GbsError::Truncated(data.len())); }
+        let word = |index: usize| -> u16 { ((data[index + 1] as u16) << 8) + data[index] as u16 };
+        let text = |start: usize| -> String {
+            let bytes = &data[start..start + 32];
+            let end = bytes.iter().position(|x| *x == 0).unwrap_or(bytes.len());
+            String::from_utf8_lossy(&bytes[..end]).trim_end().to_string()
+        };
+        let header = GbsHeader {
+            version: data[0x03],
+            song_count: data[0x04],
+            first_song: data[0x05],
+            load_address: word(0x06),
+            init_address: word(0x08),
+            play_address: word(0x0A),
+            stack_pointer: word(0x0C),
+            timer_modulo: data[0x0E],
+            timer_control: data[0x0F],
+            title: text(0x10),
+            author: text(0x30),
+            copyright: text(0x50),
+        };
+        if header.load_address < GBS_MIN_LOAD_ADDRESS || header.load_address > GBS_MAX_LOAD_ADDRESS {
+            return Err(GbsError::LoadAddress(header.load_address));
+        }
+        if header.song_count == 0 { return Err(GbsError::NoSongs); }
+        Ok(Self {
+            header,
+            data: data[GBS_HEADER_SIZE..].to_vec(),
+        })
+    }
+
+    /*
+    Build the cartridge image that plays `song` (0-based). The ROM is padded to a power-of-two number of banks.
+     */
+    pub fn build_rom(&self, song: u8) -> Vec<u8> {
+        let end = self.header.load_address as usize + self.data.len();
+        let mut banks = 2;
+        while banks * constants::ROM_BANK_SIZE < end {
+            banks = banks * 2;
+        }
+        let mut rom = vec![0xFF; banks * constants::ROM_BANK_SIZE];
+        let load = self.header.load_address as usize;
+        rom[load..end].copy_from_slice(&self.data);
+        //RST vectors
+        for vector in (0x00..=0x38).step_by(8) {
+            GbsFile::emit(&mut rom, vector, &GbsFile::jp(self.header.load_address + vector as u16));
+        }
+        //Interrupt handlers: CALL play; RETI
+        let mut handler = GbsFile::call(self.header.play_address);
+        handler.push(0xD9);
+        GbsFile::emit(&mut rom, constants::INT_VBL as usize, &handler);
+        GbsFile::emit(&mut rom, constants::INT_TIMER as usize, &handler);
+        //Entry point: NOP; JP driver
+        let mut entry = vec![0x00];
+        entry.extend(GbsFile::jp(GBS_DRIVER_START));
+        GbsFile::emit(&mut rom, constants::ENTRY_POINT as usize, &entry);
+        self.write_header(&mut rom, banks);
+        GbsFile::emit(&mut rom, GBS_DRIVER_START as usize, &self.driver(song));
+        return rom;
+    }
+
+    fn write_header(&self, rom: &mut Vec<u8>, banks: usize) {
+        rom[constants::LOGO_START..=constants::LOGO_END].copy_from_slice(&constants::NINTENDO_LOGO);
+        for i in constants::TITLE_START..=constants::HEADER_CHECKSUM {
+            rom[i] = 0x00;
+        }
+        for (i, byte) in self.header.title.bytes().filter(|x| x.is_ascii()).take(15).enumerate() {
+            rom[constants::TITLE_START + i] = byte.to_ascii_uppercase();
+        }
+        //Double speed needs CGB hardware
+        rom[constants::CGB_FLAG] = if self.header.double_speed() { 0x80 } else { 0x00 };
+        rom[constants::CARTRIDGE_TYPE] = 0x1A; //MBC5+RAM, GBS code may use 0xA000-0xBFFF as work RAM
+        rom[constants::ROM_SIZE] = (banks.trailing_zeros() - 1) as u8;
+        rom[constants::RAM_SIZE] = 0x02;
+        let mut checksum: u8 = 0;
+        for i in constants::TITLE_START..constants::HEADER_CHECKSUM {
+            checksum = checksum.wrapping_sub(rom[i]).wrapping_sub(1);
+        }
+        rom[constants::HEADER_CHECKSUM] = checksum;
+    }
+
+    /*
+    Driver program:
+        DI; LD SP,stack
+        enable cartridge RAM, map bank 1
+        (double speed only) LD A,1; LDH (KEY1),A; STOP
+        set TMA / TAC, turn the LCD on so VBlank fires
+        LD A,song; CALL init
+        enable the VBlank or timer interrupt, clear IF, EI
+        JR -2 (idle forever, play runs from the interrupt handler)
+     */
+    fn driver(&self, song: u8) -> Vec<u8> {
+        let header = &self.header;
+        let mut code: Vec<u8> = vec![0xF3];
+        code.extend(&[0x31, header.stack_pointer as u8, (header.stack_pointer >> 8) as u8]);
+        code.extend(&[0x3E, 0x0A, 0xEA, 0x00, 0x00]);
+        code.extend(&[0x3E, 0x01, 0xEA, 0x00, 0x20]);
+        if header.double_speed() {
+            code.extend(&[0x3E, 0x01, 0xE0, constants::KEY1 as u8, 0x10, 0x00]);
+        }
+        code.extend(&[0x3E, header.timer_modulo, 0xE0, constants::TMA as u8]);
+        code.extend(&[0x3E, header.timer_control & 0b111, 0xE0, constants::TAC as u8]);
+        code.extend(&[0x3E, constants::LCDC_ENABLE, 0xE0, constants::LCDC as u8]);
+        code.extend(&[0x3E, song]);
+        code.extend(GbsFile::call(header.init_address));
+        let interrupt = if header.uses_timer() { 0b00000100 } else { 0b00000001 };
+        code.extend(&[0x3E, interrupt, 0xE0, constants::IE_REGISTER as u8]);
+        code.extend(&[0xAF, 0xE0, constants::IF as u8]);
+        code.extend(&[0xFB, 0x18, 0xFE]);
+        return code;
+    }
+
+    fn emit(rom: &mut Vec<u8>, addr: usize, code: &[u8]) {
+        rom[addr..addr + code.len()].copy_from_slice(code);
+    }
+
+    fn jp(addr: u16) -> Vec<u8> {
+        vec![0xC3, addr as u8, (addr >> 8) as u8]
+    }
+
+    fn call(addr: u16) -> Vec<u8> {
+        vec![0xCD, addr as u8, (addr >> 8) as u8]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::memory::header::{CartridgeHeader, Mapper};
+
+    //Header with 3 songs starting at song 2, loading `data_size` bytes of 0x00 at `load_address`
+    fn gbs_file(load_address: u16, data_size: usize) -> Vec<u8> {
+        let mut data = vec![0; GBS_HEADER_SIZE + data_size];
+        data[..3].copy_from_slice(GBS_MAGIC);
+        data[0x03] = 1;
+        data[0x04] = 3;
+        data[0x05] = 2;
+        data[0x06..0x08].copy_from_slice(&load_address.to_le_bytes());
+        data[0x08..0x0A].copy_from_slice(&0x0410u16.to_le_bytes());
+        data[0x0A..0x0C].copy_from_slice(&0x0420u16.to_le_bytes());
+        data[0x0C..0x0E].copy_from_slice(&0xDFFEu16.to_le_bytes());
+        data[0x0E] = 0xC0;
+        data[0x0F] = 0x04;
+        data[0x10..0x15].copy_from_slice(b"Title");
+        data[0x30..0x36].copy_from_slice(b"Author");
+        data[0x50..0x54].copy_from_slice(b"2024");
+        return data;
+    }
+
+    #[test]
+    fn header_fields_are_decoded() {
+        let gbs = GbsFile::parse(&gbs_file(0x0400, 0x10)).unwrap();
+        let header = &gbs.header;
+        assert_eq!(header.version, 1);
+        assert_eq!(header.song_count, 3);
+        assert_eq!(header.first_song, 2);
+        assert_eq!(header.load_address, 0x0400);
+        assert_eq!(header.init_address, 0x0410);
+        assert_eq!(header.play_address, 0x0420);
+        assert_eq!(header.stack_pointer, 0xDFFE);
+        assert_eq!(header.timer_modulo, 0xC0);
+        assert!(header.uses_timer());
+        assert!(!header.double_speed());
+        assert_eq!(header.title, "Title");
+        assert_eq!(header.author, "Author");
+        assert_eq!(header.copyright, "2024");
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        assert_eq!(GbsFile::parse(b"GBX").err(), Some(GbsError::NotGbs));
+        assert_eq!(GbsFile::parse(&gbs_file(0x0400, 0)).err(), Some(GbsError::Truncated(GBS_HEADER_SIZE)));
+        let mut no_songs = gbs_file(0x0400, 0x10);
+        no_songs[0x04] = 0;
+        assert_eq!(GbsFile::parse(&no_songs).err(), Some(GbsError::NoSongs));
+    }
+
+    #[test]
+    fn load_address_must_be_inside_rom_above_the_driver() {
+        assert_eq!(GbsFile::parse(&gbs_file(0x03FF, 0x10)).err(), Some(GbsError::LoadAddress(0x03FF)));
+        assert_eq!(GbsFile::parse(&gbs_file(0x8000, 0x10)).err(), Some(GbsError::LoadAddress(0x8000)));
+        assert_eq!(GbsFile::parse(&gbs_file(0xA000, 0x10)).err(), Some(GbsError::LoadAddress(0xA000)));
+        assert!(GbsFile::parse(&gbs_file(0x0400, 0x10)).is_ok());
+        assert!(GbsFile::parse(&gbs_file(0x7FFF, 0x10)).is_ok());
+    }
+
+    #[test]
+    fn built_rom_wraps_data_in_a_bootable_cartridge() {
+        let mut file = gbs_file(0x0400, 0x10);
+        file[GBS_HEADER_SIZE] = 0x12;
+        let rom = GbsFile::parse(&file).unwrap().build_rom(1);
+        assert_eq!(rom.len(), 2 * constants::ROM_BANK_SIZE);
+        assert_eq!(rom[0x0400], 0x12);
+        //RST 08 jumps into the rip, the timer interrupt calls play and returns
+        assert_eq!(rom[0x0008..0x000B], [0xC3, 0x08, 0x04]);
+        assert_eq!(rom[constants::INT_TIMER as usize..constants::INT_TIMER as usize + 4], [0xCD, 0x20, 0x04, 0xD9]);
+        assert_eq!(rom[constants::ENTRY_POINT as usize..constants::ENTRY_POINT as usize + 4], [0x00, 0xC3, 0x50, 0x01]);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(header.report.bootable());
+        assert_eq!(header.mapper(), Mapper::MBC5);
+        assert_eq!(header.rom_size, rom.len());
+        assert_eq!(header.title, "TITLE");
+        //The driver calls init with the track number in A
+        let driver = &rom[GBS_DRIVER_START as usize..];
+        assert!(driver.windows(5).any(|x| x == [0x3E, 0x01, 0xCD, 0x10, 0x04]));
+    }
+
+    #[test]
+    fn built_rom_grows_to_fit_the_data() {
+        let rom = GbsFile::parse(&gbs_file(0x0400, 0x8000)).unwrap().build_rom(0);
+        assert_eq!(rom.len(), 4 * constants::ROM_BANK_SIZE);
+        assert_eq!(CartridgeHeader::parse(&rom).unwrap().rom_size, rom.len());
+    }
+}
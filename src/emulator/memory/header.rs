@@ -0,0 +1,326 @@
+/*
+Cartridge header decoding and validation: https://gbdev.io/pandocs/The_Cartridge_Header.html
+ */
+use std::fmt;
+
+use crate::emulator::constants;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CgbSupport {
+    DmgOnly,
+    Enhanced,
+    CgbOnly,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Destination {
+    Japan,
+    Overseas,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mapper {
+    None,
+    MBC1,
+    MBC2,
+    MBC3,
+    MBC5,
+    Unsupported(u8),
+}
+
+//Reasons a ROM image can't be loaded as a cartridge
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HeaderError {
+    //Image length in bytes, too short to contain the header at 0x0100-0x014F
+    TooSmall(usize),
+    //Cartridge type byte at 0x0147 for a mapper that isn't implemented
+    UnsupportedCartridge(u8),
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeaderError::TooSmall(x) => write!(f, "ROM is too small to contain a cartridge header ({} bytes)", x),
+            HeaderError::UnsupportedCartridge(x) => write!(f, "Unsupported cartridge type {:#04x}", x),
+        }
+    }
+}
+
+impl std::error::Error for HeaderError {}
+
+/*
+Result of checking the logo and both checksums against the ROM contents.
+Real hardware refuses to boot on a bad logo or header checksum, the global checksum is never verified.
+ */
+#[derive(Clone, Debug)]
+pub struct HeaderReport {
+    pub logo_valid: bool,
+    pub header_checksum: u8,
+    pub computed_header_checksum: u8,
+    pub global_checksum: u16,
+    pub computed_global_checksum: u16,
+}
+
+impl HeaderReport {
+    pub fn header_checksum_valid(&self) -> bool {
+        self.header_checksum == self.computed_header_checksum
+    }
+
+    pub fn global_checksum_valid(&self) -> bool {
+        self.global_checksum == self.computed_global_checksum
+    }
+
+    //True if the cartridge would pass the boot ROM checks
+    pub fn bootable(&self) -> bool {
+        self.logo_valid && self.header_checksum_valid()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CartridgeHeader {
+    pub title: String,
+    pub manufacturer_code: Option<String>,
+    pub cgb_flag: u8,
+    pub sgb_flag: u8,
+    pub old_licensee_code: u8,
+    pub new_licensee_code: String,
+    pub cartridge_type: u8,
+    pub rom_size: usize,
+    pub ram_size: usize,
+    pub destination: Destination,
+    pub version: u8,
+    pub report: HeaderReport,
+}
+
+impl CartridgeHeader {
+    /*
+    Decodes the header from a full ROM image. Fails if the image is too small to contain a header.
+     */
+    pub fn parse(rom: &[u8]) -> Result<Self, HeaderError> {
+        if rom.len() <= constants::GLOBAL_CHECKSUM_END { return Err(HeaderError::TooSmall(rom.len())); }
+        let cgb_flag = rom[constants::CGB_FLAG];
+        let manufacturer_code = CartridgeHeader::parse_manufacturer(rom, cgb_flag);
+        //The title was shortened over time, first by the CGB flag and then by the manufacturer code
+        let title_end = match (&manufacturer_code, cgb_flag & 0x80 > 0) {
+            (Some(_), _) => constants::MANUFACTURER_START - 1,
+            (None, true) => constants::TITLE_END - 1,
+            (None, false) => constants::TITLE_END,
+        };
+        Ok(Self {
+            title: CartridgeHeader::parse_title(&rom[constants::TITLE_START..=title_end]),
+            manufacturer_code,
+            cgb_flag,
+            sgb_flag: rom[constants::SGB_FLAG],
+            old_licensee_code: rom[constants::OLD_LICENSEE_CODE],
+            new_licensee_code: String::from_utf8_lossy(&rom[constants::LICENSEE_START..=constants::LICENSEE_END]).to_string(),
+            cartridge_type: rom[constants::CARTRIDGE_TYPE],
+            rom_size: CartridgeHeader::decode_rom_size(rom[constants::ROM_SIZE]),
+            ram_size: CartridgeHeader::decode_ram_size(rom[constants::CARTRIDGE_TYPE], rom[constants::RAM_SIZE]),
+            destination: if rom[constants::DESTINATION_CODE] == 0x00 { Destination::Japan } else { Destination::Overseas },
+            version: rom[constants::MASK_ROM_VERSION],
+            report: CartridgeHeader::validate(rom),
+        })
+    }
+
+    fn parse_title(bytes: &[u8]) -> String {
+        let mut title = String::new();
+        for byte in bytes {
+            if *byte == 0x00 || !byte.is_ascii() { break; }
+            title.push(*byte as char);
+        }
+        return title.trim_end().to_string();
+    }
+
+    //Only present on later CGB cartridges, detected by four uppercase ASCII characters in 0x013F-0x0142
+    fn parse_manufacturer(rom: &[u8], cgb_flag: u8) -> Option<String> {
+        if cgb_flag & 0x80 == 0 { return None; }
+        let code = &rom[constants::MANUFACTURER_START..=constants::MANUFACTURER_END];
+        if !code.iter().all(|x| x.is_ascii_uppercase() || x.is_ascii_digit()) { return None; }
+        return Some(String::from_utf8_lossy(code).to_string());
+    }
+
+    fn decode_rom_size(code: u8) -> usize {
+        match code {
+            0x00..=0x08 => (constants::ROM_BANK_SIZE * 2) << code,
+            0x52 => constants::ROM_BANK_SIZE * 72,
+            0x53 => constants::ROM_BANK_SIZE * 80,
+            0x54 => constants::ROM_BANK_SIZE * 96,
+            _ => 0
+        }
+    }
+
+    //MBC2 carts report no RAM in the header, their 512 x 4-bit RAM is built into the controller
+    fn decode_ram_size(cartridge_type: u8, code: u8) -> usize {
+        if cartridge_type == 0x05 || cartridge_type == 0x06 { return 512; }
+        match code {
+            0x01 => 0x800,
+            0x02 => constants::EXTERNAL_RAM_SIZE,
+            0x03 => constants::EXTERNAL_RAM_SIZE * 4,
+            0x04 => constants::EXTERNAL_RAM_SIZE * 16,
+            0x05 => constants::EXTERNAL_RAM_SIZE * 8,
+            _ => 0
+        }
+    }
+
+    fn validate(rom: &[u8]) -> HeaderReport {
+        let mut logo_valid = true;
+        for i in constants::LOGO_START..=constants::LOGO_END {
+            if rom[i] != constants::NINTENDO_LOGO[i - constants::LOGO_START] {
+                logo_valid = false;
+            }
+        }
+        let mut computed_header_checksum: u8 = 0;
+        for i in constants::TITLE_START..=constants::MASK_ROM_VERSION {
+            computed_header_checksum = computed_header_checksum.wrapping_sub(rom[i]).wrapping_sub(1);
+        }
+        let mut computed_global_checksum: u16 = 0;
+        for (i, byte) in rom.iter().enumerate() {
+            if i == constants::GLOBAL_CHECKSUM_START || i == constants::GLOBAL_CHECKSUM_END { continue; }
+            computed_global_checksum = computed_global_checksum.wrapping_add(*byte as u16);
+        }
+        HeaderReport {
+            logo_valid,
+            header_checksum: rom[constants::HEADER_CHECKSUM],
+            computed_header_checksum,
+            global_checksum: ((rom[constants::GLOBAL_CHECKSUM_START] as u16) << 8) + rom[constants::GLOBAL_CHECKSUM_END] as u16,
+            computed_global_checksum,
+        }
+    }
+
+    pub fn cgb_support(&self) -> CgbSupport {
+        match self.cgb_flag {
+            0xC0 => CgbSupport::CgbOnly,
+            0x80 => CgbSupport::Enhanced,
+            _ => CgbSupport::DmgOnly,
+        }
+    }
+
+    //SGB functions are only usable if the flag is 0x03 and the old licensee code is 0x33
+    pub fn sgb_support(&self) -> bool {
+        self.sgb_flag == 0x03 && self.old_licensee_code == 0x33
+    }
+
+    //Old licensee code 0x33 means the publisher is given by the two-character new licensee code instead
+    pub fn licensee(&self) -> String {
+        if self.old_licensee_code == 0x33 {
+            return self.new_licensee_code.clone();
+        }
+        return format!("{:02X}", self.old_licensee_code);
+    }
+
+    pub fn mapper(&self) -> Mapper {
+        match self.cartridge_type {
+            0x00 | 0x08..=0x09 => Mapper::None,
+            0x01..=0x03 => Mapper::MBC1,
+            0x05..=0x06 => Mapper::MBC2,
+            0x0F..=0x13 => Mapper::MBC3,
+            0x19..=0x1E => Mapper::MBC5,
+            x => Mapper::Unsupported(x),
+        }
+    }
+
+    pub fn has_battery(&self) -> bool {
+        match self.cartridge_type {
+            0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x22 | 0xFF => true,
+            _ => false
+        }
+    }
+
+    pub fn has_rtc(&self) -> bool {
+        self.cartridge_type == 0x0F || self.cartridge_type == 0x10
+    }
+
+    pub fn has_rumble(&self) -> bool {
+        match self.cartridge_type {
+            0x1C..=0x1E => true,
+            _ => false
+        }
+    }
+}
+
+impl fmt::Display for CartridgeHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let pass = |valid: bool| if valid { "OK" } else { "BAD" };
+        writeln!(f, "Title: {}", self.title)?;
+        writeln!(f, "Licensee: {}", self.licensee())?;
+        writeln!(f, "Cartridge type: {:#04x} ({:?})", self.cartridge_type, self.mapper())?;
+        writeln!(f, "ROM: {} KiB, RAM: {} KiB", self.rom_size / 1024, self.ram_size / 1024)?;
+        writeln!(f, "CGB: {:?}, SGB: {}", self.cgb_support(), self.sgb_support())?;
+        writeln!(f, "Destination: {:?}, Version: {}", self.destination, self.version)?;
+        writeln!(f, "Logo: {}", pass(self.report.logo_valid))?;
+        writeln!(f, "Header checksum: {} ({:#04x}, expected {:#04x})", pass(self.report.header_checksum_valid()), self.report.header_checksum, self.report.computed_header_checksum)?;
+        write!(f, "Global checksum: {} ({:#06x}, expected {:#06x})", pass(self.report.global_checksum_valid()), self.report.global_checksum, self.report.computed_global_checksum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //32 KiB image with the logo, an all-zero header and correct checksums
+    fn valid_rom() -> Vec<u8> {
+        let mut rom = vec![0; 2 * constants::ROM_BANK_SIZE];
+        rom[constants::LOGO_START..=constants::LOGO_END].copy_from_slice(&constants::NINTENDO_LOGO);
+        //0 - 1 for each of the 25 bytes 0x0134-0x014C
+        rom[constants::HEADER_CHECKSUM] = 0xE7;
+        let sum: u16 = rom.iter().fold(0, |sum, x| sum.wrapping_add(*x as u16));
+        rom[constants::GLOBAL_CHECKSUM_START] = (sum >> 8) as u8;
+        rom[constants::GLOBAL_CHECKSUM_END] = (sum & 0xFF) as u8;
+        return rom;
+    }
+
+    #[test]
+    fn valid_header_passes_all_checks() {
+        let header = CartridgeHeader::parse(&valid_rom()).unwrap();
+        assert!(header.report.logo_valid);
+        assert!(header.report.header_checksum_valid());
+        assert!(header.report.global_checksum_valid());
+        assert!(header.report.bootable());
+    }
+
+    #[test]
+    fn header_checksum_covers_title_to_version() {
+        let mut rom = valid_rom();
+        rom[constants::MASK_ROM_VERSION] = 0x01;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.report.computed_header_checksum, 0xE6);
+        assert!(!header.report.header_checksum_valid());
+        assert!(!header.report.bootable());
+    }
+
+    #[test]
+    fn global_checksum_mismatch_still_boots() {
+        let mut rom = valid_rom();
+        rom[0x4000] = 0x01;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(!header.report.global_checksum_valid());
+        assert_eq!(header.report.computed_global_checksum, header.report.global_checksum.wrapping_add(1));
+        assert!(header.report.bootable());
+    }
+
+    #[test]
+    fn global_checksum_skips_its_own_bytes() {
+        let mut rom = valid_rom();
+        let expected = CartridgeHeader::parse(&rom).unwrap().report.computed_global_checksum;
+        rom[constants::GLOBAL_CHECKSUM_START] = 0x12;
+        rom[constants::GLOBAL_CHECKSUM_END] = 0x34;
+        let report = CartridgeHeader::parse(&rom).unwrap().report;
+        assert_eq!(report.computed_global_checksum, expected);
+        assert_eq!(report.global_checksum, 0x1234);
+    }
+
+    #[test]
+    fn corrupt_logo_is_not_bootable() {
+        let mut rom = valid_rom();
+        rom[constants::LOGO_START] = 0x00;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(!header.report.logo_valid);
+        assert!(!header.report.bootable());
+    }
+
+    #[test]
+    fn short_image_is_rejected() {
+        assert_eq!(CartridgeHeader::parse(&[0; 0x14F]).unwrap_err(), HeaderError::TooSmall(0x14F));
+        assert!(CartridgeHeader::parse(&[0; 0x150]).is_ok());
+    }
+}
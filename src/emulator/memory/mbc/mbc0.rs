@@ -5,14 +5,14 @@ use crate::emulator::constants;
 use crate::emulator::memory::mbc::MemoryBankController;
 
 pub struct MBC0 {
-    rom: [u8; constants::SIXTEEN_KB],
+    rom: Vec<u8>,
     ram: [u8; constants::EXTERNAL_RAM_SIZE],
 }
 
 impl MBC0 {
-    pub fn new() -> Self {
+    pub fn new(rom: Vec<u8>) -> Self {
         Self {
-            rom: [0; constants::SIXTEEN_KB],
+            rom,
             ram: [0; constants::EXTERNAL_RAM_SIZE],
         }
     }
@@ -21,49 +21,48 @@ impl MBC0 {
 impl MemoryBankController for MBC0 {
     fn read(&self, addr: u16) -> u8 {
         match addr as usize {
-            constants::SWITCHABLE_ROM_START..=constants::SWITCHABLE_ROM_END => self.rom[addr as usize - constants::SIXTEEN_KB],
-            constants::EXTERNAL_RAM_START..=constants::EXTERNAL_RAM_END => self.ram[addr as usize],
+            constants::ONBOARD_ROM_START..=constants::SWITCHABLE_ROM_END => {
+                if (addr as usize) < self.rom.len() { self.rom[addr as usize] } else { 0xFF }
+            },
+            constants::EXTERNAL_RAM_START..=constants::EXTERNAL_RAM_END => self.ram[addr as usize - constants::EXTERNAL_RAM_START],
             _ => panic!("Unreachable memory")
         }
     }
 
     //little-endian (least significant byte first)
     fn read_double(&self, addr: u16) -> u16 {
-        match addr as usize {
-            constants::SWITCHABLE_ROM_START..=constants::SWITCHABLE_ROM_END => {
-                let low = self.rom[addr as usize - constants::SIXTEEN_KB];
-                let high = self.rom[(addr + 1) as usize - constants::SIXTEEN_KB];
-                return ((high as u16) << 8) + low as u16;
-            },
-            constants::EXTERNAL_RAM_START..=constants::EXTERNAL_RAM_END => {
-                let low = self.rom[addr as usize];
-                let high = self.rom[(addr + 1) as usize];
-                return ((high as u16) << 8) + low as u16;
-            },
-            _ => panic!("Unreachable memory")
-        }
+        let low = self.read(addr);
+        let high = self.read(addr + 1);
+        return ((high as u16) << 8) + low as u16;
     }
 
+    //ROM is read-only without an MBC, writes to it are ignored
     fn write(&mut self, addr: u16, data: u8) {
         match addr as usize {
-            constants::SWITCHABLE_ROM_START..=constants::SWITCHABLE_ROM_END => self.rom[addr as usize - constants::SIXTEEN_KB] = data,
-            constants::EXTERNAL_RAM_START..=constants::EXTERNAL_RAM_END => self.ram[addr as usize] = data,
+            constants::ONBOARD_ROM_START..=constants::SWITCHABLE_ROM_END => (),
+            constants::EXTERNAL_RAM_START..=constants::EXTERNAL_RAM_END => self.ram[addr as usize - constants::EXTERNAL_RAM_START] = data,
             _ => panic!("Unreachable memory")
         }
     }
 
     //little-endian (least significant byte first)
     fn write_double(&mut self, addr: u16, data: u16) {
-        match addr as usize {
-            constants::SWITCHABLE_ROM_START..=constants::SWITCHABLE_ROM_END => {
-                self.rom[addr as usize - constants::SIXTEEN_KB] = (data & 0x00FF) as u8;
-                self.rom[(addr + 1) as usize - constants::SIXTEEN_KB] = (data >> 8) as u8;
-            },
-            constants::EXTERNAL_RAM_START..=constants::EXTERNAL_RAM_END => {
-                self.rom[addr as usize] = (data & 0x00FF) as u8;
-                self.rom[(addr + 1) as usize] = (data >> 8) as u8;
-            },
-            _ => panic!("Unreachable memory")
-        }
+        self.write(addr, (data & 0x00FF) as u8);
+        self.write(addr + 1, (data >> 8) as u8);
     }
-}
\ No newline at end of file
+
+    fn tick(&mut self) {}
+
+    fn save_data(&self) -> Vec<u8> {
+        return self.ram.to_vec();
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        let len = std::cmp::min(self.ram.len(), data.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+    }
+
+    fn rumble(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,235 @@
+/*
+Implementation for MBC1 cartridges: https://gbdev.io/pandocs/MBC1.html
+
+bank1: 5-bit ROM bank register (0x2000-0x3FFF), a value of 0 is treated as 1
+bank2: 2-bit register (0x4000-0x5FFF), upper ROM bank bits or RAM bank number depending on mode
+mode: banking mode select (0x6000-0x7FFF), false = simple, true = advanced
+multicart: MBC1M wiring, where bank2 is shifted in above 4 bits of bank1 instead of 5
+*/
+use crate::emulator::constants;
+use crate::emulator::memory::mbc::MemoryBankController;
+
+pub struct MBC1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    bank1: u8,
+    bank2: u8,
+    mode: bool,
+    multicart: bool,
+}
+
+impl MBC1 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+        let multicart = MBC1::detect_multicart(&rom);
+        Self {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            bank1: 1,
+            bank2: 0,
+            mode: false,
+            multicart,
+        }
+    }
+
+    /*
+    MBC1M carts are 8 Mbit and contain several games, each starting with its own header.
+    The header of the second game sits at the start of bank 0x10, so look for the Nintendo logo there.
+     */
+    fn detect_multicart(rom: &Vec<u8>) -> bool {
+        if rom.len() != constants::ROM_BANK_SIZE * 64 {
+            return false;
+        }
+        let base = constants::ROM_BANK_SIZE * 0x10;
+        for i in constants::LOGO_START..=constants::LOGO_END {
+            if rom[base + i] != constants::NINTENDO_LOGO[i - constants::LOGO_START] {
+                return false;
+            }
+        }
+        return true;
+    }
+
+    fn bank_shift(&self) -> u8 {
+        if self.multicart { 4 } else { 5 }
+    }
+
+    fn bank1_mask(&self) -> u8 {
+        if self.multicart { 0x0F } else { 0x1F }
+    }
+
+    //ROM bank visible at 0x0000-0x3FFF, only affected by bank2 in advanced mode
+    fn zero_bank(&self) -> usize {
+        if self.mode { (self.bank2 << self.bank_shift()) as usize } else { 0 }
+    }
+
+    //ROM bank visible at 0x4000-0x7FFF
+    fn high_bank(&self) -> usize {
+        ((self.bank2 << self.bank_shift()) | (self.bank1 & self.bank1_mask())) as usize
+    }
+
+    fn rom_offset(&self, bank: usize, addr: u16) -> usize {
+        let banks = std::cmp::max(self.rom.len() / constants::ROM_BANK_SIZE, 1);
+        return (bank % banks) * constants::ROM_BANK_SIZE + (addr as usize & (constants::ROM_BANK_SIZE - 1));
+    }
+
+    fn ram_offset(&self, addr: u16) -> usize {
+        let bank = if self.mode { self.bank2 as usize } else { 0 };
+        let offset = bank * constants::EXTERNAL_RAM_SIZE + (addr as usize - constants::EXTERNAL_RAM_START);
+        return offset % self.ram.len();
+    }
+}
+
+impl MemoryBankController for MBC1 {
+    fn read(&self, addr: u16) -> u8 {
+        match addr as usize {
+            constants::ONBOARD_ROM_START..=constants::ONBOARD_ROM_END => self.rom[self.rom_offset(self.zero_bank(), addr)],
+            constants::SWITCHABLE_ROM_START..=constants::SWITCHABLE_ROM_END => self.rom[self.rom_offset(self.high_bank(), addr)],
+            constants::EXTERNAL_RAM_START..=constants::EXTERNAL_RAM_END => {
+                if !self.ram_enabled || self.ram.is_empty() { return 0xFF; }
+                self.ram[self.ram_offset(addr)]
+            },
+            _ => panic!("Unreachable memory")
+        }
+    }
+
+    //little-endian (least significant byte first)
+    fn read_double(&self, addr: u16) -> u16 {
+        let low = self.read(addr);
+        let high = self.read(addr + 1);
+        return ((high as u16) << 8) + low as u16;
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr as usize {
+            0x0000..=0x1FFF => self.ram_enabled = data & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                self.bank1 = data & 0x1F;
+                if self.bank1 == 0 { self.bank1 = 1; }
+            },
+            0x4000..=0x5FFF => self.bank2 = data & 0b11,
+            0x6000..=0x7FFF => self.mode = data & 0b1 == 1,
+            constants::EXTERNAL_RAM_START..=constants::EXTERNAL_RAM_END => {
+                if !self.ram_enabled || self.ram.is_empty() { return; }
+                let offset = self.ram_offset(addr);
+                self.ram[offset] = data;
+            },
+            _ => panic!("Unreachable memory")
+        }
+    }
+
+    //little-endian (least significant byte first)
+    fn write_double(&mut self, addr: u16, data: u16) {
+        self.write(addr, (data & 0x00FF) as u8);
+        self.write(addr + 1, (data >> 8) as u8);
+    }
+
+    fn tick(&mut self) {}
+
+    fn save_data(&self) -> Vec<u8> {
+        return self.ram.clone();
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        let len = std::cmp::min(self.ram.len(), data.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+    }
+
+    fn rumble(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //ROM where the first byte of every bank holds its bank number
+    fn banked_rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * constants::ROM_BANK_SIZE];
+        for bank in 0..banks {
+            rom[bank * constants::ROM_BANK_SIZE] = bank as u8;
+        }
+        return rom;
+    }
+
+    //8 Mbit image with a second game header at bank 0x10
+    fn multicart_rom() -> Vec<u8> {
+        let mut rom = banked_rom(64);
+        let base = constants::ROM_BANK_SIZE * 0x10 + constants::LOGO_START;
+        rom[base..base + constants::NINTENDO_LOGO.len()].copy_from_slice(&constants::NINTENDO_LOGO);
+        return rom;
+    }
+
+    #[test]
+    fn bank_0_maps_to_bank_1() {
+        let mut mbc = MBC1::new(banked_rom(4), 0);
+        assert_eq!(mbc.read(0x4000), 1);
+        mbc.write(0x2000, 0x00);
+        assert_eq!(mbc.read(0x4000), 1);
+        mbc.write(0x2000, 0x02);
+        assert_eq!(mbc.read(0x4000), 2);
+        //Only the 5 bits of the register are checked, so 0x20 also selects bank 1
+        mbc.write(0x2000, 0x20);
+        assert_eq!(mbc.read(0x4000), 1);
+    }
+
+    #[test]
+    fn advanced_mode_applies_upper_bits_to_low_rom_area() {
+        let mut mbc = MBC1::new(banked_rom(64), 0);
+        mbc.write(0x4000, 0x01);
+        mbc.write(0x2000, 0x01);
+        assert_eq!(mbc.read(0x0000), 0);
+        assert_eq!(mbc.read(0x4000), 0x21);
+        mbc.write(0x6000, 0x01);
+        assert_eq!(mbc.read(0x0000), 0x20);
+        assert_eq!(mbc.read(0x4000), 0x21);
+        mbc.write(0x6000, 0x00);
+        assert_eq!(mbc.read(0x0000), 0);
+    }
+
+    #[test]
+    fn ram_is_only_accessible_while_enabled() {
+        let mut mbc = MBC1::new(banked_rom(4), constants::EXTERNAL_RAM_SIZE);
+        mbc.write(0xA000, 0x12);
+        assert_eq!(mbc.read(0xA000), 0xFF);
+        mbc.write(0x0000, 0x0A);
+        assert_eq!(mbc.read(0xA000), 0x00);
+        mbc.write(0xA000, 0x12);
+        assert_eq!(mbc.read(0xA000), 0x12);
+        mbc.write(0x0000, 0x00);
+        assert_eq!(mbc.read(0xA000), 0xFF);
+        //Only the lower nibble is decoded
+        mbc.write(0x0000, 0x1A);
+        assert_eq!(mbc.read(0xA000), 0x12);
+    }
+
+    #[test]
+    fn advanced_mode_switches_ram_banks() {
+        let mut mbc = MBC1::new(banked_rom(4), 4 * constants::EXTERNAL_RAM_SIZE);
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0xA000, 0x01);
+        mbc.write(0x4000, 0x02);
+        assert_eq!(mbc.read(0xA000), 0x01);
+        mbc.write(0x6000, 0x01);
+        assert_eq!(mbc.read(0xA000), 0x00);
+        mbc.write(0xA000, 0x03);
+        mbc.write(0x6000, 0x00);
+        assert_eq!(mbc.read(0xA000), 0x01);
+    }
+
+    #[test]
+    fn multicart_uses_4_bit_bank1() {
+        let mut mbc = MBC1::new(multicart_rom(), 0);
+        assert!(mbc.multicart);
+        mbc.write(0x4000, 0x01);
+        mbc.write(0x2000, 0x02);
+        assert_eq!(mbc.read(0x4000), 0x12);
+        mbc.write(0x6000, 0x01);
+        assert_eq!(mbc.read(0x0000), 0x10);
+        //Bit 4 of bank1 isn't wired, so 0x10 reads as bank 0 of the selected game
+        mbc.write(0x2000, 0x10);
+        assert_eq!(mbc.read(0x4000), 0x10);
+        assert!(!MBC1::new(banked_rom(64), 0).multicart);
+    }
+}
@@ -0,0 +1,148 @@
+/*
+Implementation for MBC2 cartridges: https://gbdev.io/pandocs/MBC2.html
+
+Writes to 0x0000-0x3FFF are decoded using address bit 8: clear = RAM enable, set = ROM bank select.
+The built-in RAM is 512 x 4 bits, only the lower nibble is stored and the upper nibble reads back as 1s.
+It only decodes the bottom 9 address bits, so it repeats across all of 0xA000-0xBFFF.
+*/
+use crate::emulator::constants;
+use crate::emulator::memory::mbc::MemoryBankController;
+
+const MBC2_RAM_SIZE: usize = 512;
+
+pub struct MBC2 {
+    rom: Vec<u8>,
+    ram: [u8; MBC2_RAM_SIZE],
+    ram_enabled: bool,
+    rom_bank: u8,
+}
+
+impl MBC2 {
+    pub fn new(rom: Vec<u8>) -> Self {
+        Self {
+            rom,
+            ram: [0; MBC2_RAM_SIZE],
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+
+    fn rom_offset(&self, bank: usize, addr: u16) -> usize {
+        let banks = std::cmp::max(self.rom.len() / constants::ROM_BANK_SIZE, 1);
+        return (bank % banks) * constants::ROM_BANK_SIZE + (addr as usize & (constants::ROM_BANK_SIZE - 1));
+    }
+}
+
+impl MemoryBankController for MBC2 {
+    fn read(&self, addr: u16) -> u8 {
+        match addr as usize {
+            constants::ONBOARD_ROM_START..=constants::ONBOARD_ROM_END => self.rom[self.rom_offset(0, addr)],
+            constants::SWITCHABLE_ROM_START..=constants::SWITCHABLE_ROM_END => self.rom[self.rom_offset(self.rom_bank as usize, addr)],
+            constants::EXTERNAL_RAM_START..=constants::EXTERNAL_RAM_END => {
+                if !self.ram_enabled { return 0xFF; }
+                0xF0 | self.ram[addr as usize & (MBC2_RAM_SIZE - 1)]
+            },
+            _ => panic!("Unreachable memory")
+        }
+    }
+
+    //little-endian (least significant byte first)
+    fn read_double(&self, addr: u16) -> u16 {
+        let low = self.read(addr);
+        let high = self.read(addr + 1);
+        return ((high as u16) << 8) + low as u16;
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr as usize {
+            constants::ONBOARD_ROM_START..=constants::ONBOARD_ROM_END => {
+                if addr & 0x0100 == 0 {
+                    self.ram_enabled = data & 0x0F == 0x0A;
+                } else {
+                    self.rom_bank = data & 0x0F;
+                    if self.rom_bank == 0 { self.rom_bank = 1; }
+                }
+            },
+            constants::SWITCHABLE_ROM_START..=constants::SWITCHABLE_ROM_END => (),
+            constants::EXTERNAL_RAM_START..=constants::EXTERNAL_RAM_END => {
+                if !self.ram_enabled { return; }
+                self.ram[addr as usize & (MBC2_RAM_SIZE - 1)] = data & 0x0F;
+            },
+            _ => panic!("Unreachable memory")
+        }
+    }
+
+    //little-endian (least significant byte first)
+    fn write_double(&mut self, addr: u16, data: u16) {
+        self.write(addr, (data & 0x00FF) as u8);
+        self.write(addr + 1, (data >> 8) as u8);
+    }
+
+    fn tick(&mut self) {}
+
+    //One byte per 4-bit cell, 512 bytes total
+    fn save_data(&self) -> Vec<u8> {
+        return self.ram.to_vec();
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        let len = std::cmp::min(MBC2_RAM_SIZE, data.len());
+        for i in 0..len {
+            self.ram[i] = data[i] & 0x0F;
+        }
+    }
+
+    fn rumble(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //ROM where the first byte of every bank holds its bank number
+    fn banked_rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * constants::ROM_BANK_SIZE];
+        for bank in 0..banks {
+            rom[bank * constants::ROM_BANK_SIZE] = bank as u8;
+        }
+        return rom;
+    }
+
+    #[test]
+    fn address_bit_8_selects_register() {
+        let mut mbc = MBC2::new(banked_rom(16));
+        //Bit 8 clear: RAM enable, the ROM bank is untouched
+        mbc.write(0x0000, 0x0A);
+        assert_eq!(mbc.read(0x4000), 1);
+        assert_eq!(mbc.read(0xA000), 0xF0);
+        //Bit 8 set: ROM bank, RAM stays enabled
+        mbc.write(0x0100, 0x05);
+        assert_eq!(mbc.read(0x4000), 5);
+        assert_eq!(mbc.read(0xA000), 0xF0);
+        mbc.write(0x3FFF, 0x0A);
+        assert_eq!(mbc.read(0x4000), 0x0A);
+        mbc.write(0x3EFF, 0x00);
+        assert_eq!(mbc.read(0xA000), 0xFF);
+        assert_eq!(mbc.read(0x4000), 0x0A);
+        mbc.write(0x2100, 0x00);
+        assert_eq!(mbc.read(0x4000), 1);
+    }
+
+    #[test]
+    fn ram_stores_lower_nibble_and_mirrors() {
+        let mut mbc = MBC2::new(banked_rom(2));
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0xA000, 0xAB);
+        mbc.write(0xA1FF, 0x07);
+        assert_eq!(mbc.read(0xA000), 0xFB);
+        assert_eq!(mbc.read(0xA1FF), 0xF7);
+        //Only 9 address bits are decoded
+        assert_eq!(mbc.read(0xA200), 0xFB);
+        assert_eq!(mbc.read(0xBE00), 0xFB);
+        assert_eq!(mbc.read(0xBFFF), 0xF7);
+        mbc.write(0xB005, 0x03);
+        assert_eq!(mbc.read(0xA005), 0xF3);
+    }
+}
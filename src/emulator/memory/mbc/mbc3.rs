@@ -0,0 +1,515 @@
+/*
+Implementation for MBC3 cartridges, including the real-time clock: https://gbdev.io/pandocs/MBC3.html
+
+rom_bank: 7-bit ROM bank register (0x2000-0x3FFF), a value of 0 is treated as 1
+ram_select: RAM bank (0x00-0x07) or RTC register (0x08-0x0C) mapped to 0xA000-0xBFFF
+latch_armed: set when 0x00 is written to 0x6000-0x7FFF, a following write of 0x01 latches the clock
+*/
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::emulator::constants;
+use crate::emulator::memory::mbc::MemoryBankController;
+
+const RTC_SECONDS: u8 = 0x08;
+const RTC_MINUTES: u8 = 0x09;
+const RTC_HOURS: u8 = 0x0A;
+const RTC_DAY_LOW: u8 = 0x0B;
+const RTC_DAY_HIGH: u8 = 0x0C;
+
+const DAY_HIGH_BIT: u8 = 0b00000001;
+const HALT_BIT: u8 = 0b01000000;
+const DAY_CARRY_BIT: u8 = 0b10000000;
+
+//Size of the RTC block appended to the battery RAM in .sav files (BGB / VBA-M layout)
+const RTC_SAVE_SIZE: usize = 48;
+const RTC_SAVE_SIZE_SHORT: usize = 44;
+
+/*
+Clock counter registers, stored the same way they are exposed to the CPU
+ */
+#[derive(Clone, Copy)]
+struct RtcRegisters {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    day_low: u8,
+    day_high: u8,
+}
+
+impl RtcRegisters {
+    fn new() -> Self {
+        Self {
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            day_low: 0,
+            day_high: 0,
+        }
+    }
+
+    fn read(&self, register: u8) -> u8 {
+        match register {
+            RTC_SECONDS => self.seconds & 0x3F,
+            RTC_MINUTES => self.minutes & 0x3F,
+            RTC_HOURS => self.hours & 0x1F,
+            RTC_DAY_LOW => self.day_low,
+            RTC_DAY_HIGH => self.day_high & (DAY_HIGH_BIT | HALT_BIT | DAY_CARRY_BIT),
+            _ => 0xFF
+        }
+    }
+
+    fn write(&mut self, register: u8, data: u8) {
+        match register {
+            RTC_SECONDS => self.seconds = data & 0x3F,
+            RTC_MINUTES => self.minutes = data & 0x3F,
+            RTC_HOURS => self.hours = data & 0x1F,
+            RTC_DAY_LOW => self.day_low = data,
+            RTC_DAY_HIGH => self.day_high = data & (DAY_HIGH_BIT | HALT_BIT | DAY_CARRY_BIT),
+            _ => ()
+        }
+    }
+
+    fn halted(&self) -> bool {
+        self.day_high & HALT_BIT > 0
+    }
+
+    fn days(&self) -> u16 {
+        (((self.day_high & DAY_HIGH_BIT) as u16) << 8) + self.day_low as u16
+    }
+
+    fn set_days(&mut self, days: u16) {
+        self.day_low = (days & 0xFF) as u8;
+        self.day_high = (self.day_high & !DAY_HIGH_BIT) | ((days >> 8) as u8 & DAY_HIGH_BIT);
+    }
+
+    /*
+    Counters only carry when they reach their natural limit. Values written out of range by software
+    (e.g. 62 seconds) keep counting until the register bits overflow, without carrying into the next unit.
+     */
+    fn tick_second(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 { return; }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 { return; }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 { return; }
+        self.hours = 0;
+        let days = self.days() + 1;
+        if days > 0x1FF {
+            self.day_high = self.day_high | DAY_CARRY_BIT;
+        }
+        self.set_days(days & 0x1FF);
+    }
+
+    //Advance by a (potentially very large) number of seconds, used to catch up on wall-clock time between sessions
+    fn advance(&mut self, seconds: u64) {
+        if self.halted() || seconds == 0 { return; }
+        if self.seconds >= 60 || self.minutes >= 60 || self.hours >= 24 {
+            //Out-of-range values don't follow the normal carry chain, walk them back into range one second at a time
+            let mut remaining = seconds;
+            while remaining > 0 && (self.seconds >= 60 || self.minutes >= 60 || self.hours >= 24) {
+                self.tick_second();
+                remaining -= 1;
+            }
+            return self.advance(remaining);
+        }
+        let total = self.seconds as u64
+            + self.minutes as u64 * 60
+            + self.hours as u64 * 3600
+            + self.days() as u64 * 86400
+            + seconds;
+        let days = total / 86400;
+        self.seconds = (total % 60) as u8;
+        self.minutes = ((total / 60) % 60) as u8;
+        self.hours = ((total / 3600) % 24) as u8;
+        if days > 0x1FF {
+            self.day_high = self.day_high | DAY_CARRY_BIT;
+        }
+        self.set_days((days % 0x200) as u16);
+    }
+}
+
+struct Rtc {
+    current: RtcRegisters,
+    latched: RtcRegisters,
+    cycles: u32,
+}
+
+impl Rtc {
+    fn new() -> Self {
+        Self {
+            current: RtcRegisters::new(),
+            latched: RtcRegisters::new(),
+            cycles: 0,
+        }
+    }
+
+    fn tick(&mut self) {
+        if self.current.halted() { return; }
+        self.cycles += 1;
+        if self.cycles >= constants::CLOCK_HZ as u32 {
+            self.cycles = 0;
+            self.current.tick_second();
+        }
+    }
+
+    fn latch(&mut self) {
+        self.latched = self.current;
+    }
+
+    fn write(&mut self, register: u8, data: u8) {
+        //Writing the seconds register resets the sub-second divider
+        if register == RTC_SECONDS { self.cycles = 0; }
+        self.current.write(register, data);
+    }
+
+    /*
+    48-byte layout: current S/M/H/DL/DH, latched S/M/H/DL/DH (each as a little-endian u32), then a
+    little-endian u64 UNIX timestamp of when the state was written
+     */
+    fn save_data(&self) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::with_capacity(RTC_SAVE_SIZE);
+        for registers in [self.current, self.latched].iter() {
+            for value in [registers.seconds, registers.minutes, registers.hours, registers.day_low, registers.day_high].iter() {
+                data.extend_from_slice(&(*value as u32).to_le_bytes());
+            }
+        }
+        data.extend_from_slice(&unix_time().to_le_bytes());
+        return data;
+    }
+
+    //Accepts both the 48-byte layout and the older 44-byte layout with a 32-bit timestamp
+    fn load_save_data(&mut self, data: &[u8]) {
+        if data.len() != RTC_SAVE_SIZE && data.len() != RTC_SAVE_SIZE_SHORT { return; }
+        let value = |index: usize| -> u8 { data[index * 4] };
+        self.current = RtcRegisters { seconds: value(0), minutes: value(1), hours: value(2), day_low: value(3), day_high: value(4) };
+        self.latched = RtcRegisters { seconds: value(5), minutes: value(6), hours: value(7), day_low: value(8), day_high: value(9) };
+        let mut timestamp = [0u8; 8];
+        timestamp[..data.len() - 40].copy_from_slice(&data[40..]);
+        let saved_at = u64::from_le_bytes(timestamp);
+        let now = unix_time();
+        if now > saved_at {
+            self.current.advance(now - saved_at);
+        }
+    }
+}
+
+fn unix_time() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(x) => x.as_secs(),
+        Err(_) => 0,
+    }
+}
+
+pub struct MBC3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    rom_bank: u8,
+    ram_select: u8,
+    latch_armed: bool,
+    rtc: Option<Rtc>,
+}
+
+impl MBC3 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, has_rtc: bool) -> Self {
+        Self {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_select: 0,
+            latch_armed: false,
+            rtc: if has_rtc { Some(Rtc::new()) } else { None },
+        }
+    }
+
+    fn rom_offset(&self, bank: usize, addr: u16) -> usize {
+        let banks = std::cmp::max(self.rom.len() / constants::ROM_BANK_SIZE, 1);
+        return (bank % banks) * constants::ROM_BANK_SIZE + (addr as usize & (constants::ROM_BANK_SIZE - 1));
+    }
+
+    fn ram_offset(&self, addr: u16) -> usize {
+        let offset = self.ram_select as usize * constants::EXTERNAL_RAM_SIZE + (addr as usize - constants::EXTERNAL_RAM_START);
+        return offset % self.ram.len();
+    }
+}
+
+impl MemoryBankController for MBC3 {
+    fn read(&self, addr: u16) -> u8 {
+        match addr as usize {
+            constants::ONBOARD_ROM_START..=constants::ONBOARD_ROM_END => self.rom[self.rom_offset(0, addr)],
+            constants::SWITCHABLE_ROM_START..=constants::SWITCHABLE_ROM_END => self.rom[self.rom_offset(self.rom_bank as usize, addr)],
+            constants::EXTERNAL_RAM_START..=constants::EXTERNAL_RAM_END => {
+                if !self.ram_enabled { return 0xFF; }
+                match self.ram_select {
+                    0x00..=0x07 => {
+                        if self.ram.is_empty() { return 0xFF; }
+                        self.ram[self.ram_offset(addr)]
+                    },
+                    RTC_SECONDS..=RTC_DAY_HIGH => match &self.rtc {
+                        Some(rtc) => rtc.latched.read(self.ram_select),
+                        None => 0xFF,
+                    },
+                    _ => 0xFF
+                }
+            },
+            _ => panic!("Unreachable memory")
+        }
+    }
+
+    //little-endian (least significant byte first)
+    fn read_double(&self, addr: u16) -> u16 {
+        let low = self.read(addr);
+        let high = self.read(addr + 1);
+        return ((high as u16) << 8) + low as u16;
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr as usize {
+            0x0000..=0x1FFF => self.ram_enabled = data & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                self.rom_bank = data & 0x7F;
+                if self.rom_bank == 0 { self.rom_bank = 1; }
+            },
+            0x4000..=0x5FFF => self.ram_select = data,
+            0x6000..=0x7FFF => {
+                if data == 0x01 && self.latch_armed {
+                    match &mut self.rtc {
+                        Some(rtc) => rtc.latch(),
+                        None => ()
+                    }
+                }
+                self.latch_armed = data == 0x00;
+            },
+            constants::EXTERNAL_RAM_START..=constants::EXTERNAL_RAM_END => {
+                if !self.ram_enabled { return; }
+                match self.ram_select {
+                    0x00..=0x07 => {
+                        if self.ram.is_empty() { return; }
+                        let offset = self.ram_offset(addr);
+                        self.ram[offset] = data;
+                    },
+                    RTC_SECONDS..=RTC_DAY_HIGH => match &mut self.rtc {
+                        Some(rtc) => rtc.write(self.ram_select, data),
+                        None => ()
+                    },
+                    _ => ()
+                }
+            },
+            _ => panic!("Unreachable memory")
+        }
+    }
+
+    //little-endian (least significant byte first)
+    fn write_double(&mut self, addr: u16, data: u16) {
+        self.write(addr, (data & 0x00FF) as u8);
+        self.write(addr + 1, (data >> 8) as u8);
+    }
+
+    fn tick(&mut self) {
+        match &mut self.rtc {
+            Some(rtc) => rtc.tick(),
+            None => ()
+        }
+    }
+
+    //Battery RAM followed by the RTC block, if the cartridge has a clock
+    fn save_data(&self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        match &self.rtc {
+            Some(rtc) => data.extend(rtc.save_data()),
+            None => ()
+        }
+        return data;
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        let ram_len = std::cmp::min(self.ram.len(), data.len());
+        self.ram[..ram_len].copy_from_slice(&data[..ram_len]);
+        match &mut self.rtc {
+            Some(rtc) => rtc.load_save_data(&data[ram_len..]),
+            None => ()
+        }
+    }
+
+    fn rumble(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rtc_cartridge() -> MBC3 {
+        let mut mbc = MBC3::new(vec![0; 2 * constants::ROM_BANK_SIZE], constants::EXTERNAL_RAM_SIZE, true);
+        mbc.write(0x0000, 0x0A);
+        return mbc;
+    }
+
+    fn write_rtc(mbc: &mut MBC3, register: u8, data: u8) {
+        mbc.write(0x4000, register);
+        mbc.write(0xA000, data);
+    }
+
+    fn read_rtc(mbc: &mut MBC3, register: u8) -> u8 {
+        mbc.write(0x4000, register);
+        return mbc.read(0xA000);
+    }
+
+    fn latch(mbc: &mut MBC3) {
+        mbc.write(0x6000, 0x00);
+        mbc.write(0x6000, 0x01);
+    }
+
+    #[test]
+    fn rtc_registers_survive_save_and_load() {
+        let mut mbc = rtc_cartridge();
+        //Halted, so no wall-clock time is added when the save is loaded
+        write_rtc(&mut mbc, RTC_DAY_HIGH, HALT_BIT | DAY_HIGH_BIT);
+        write_rtc(&mut mbc, RTC_SECONDS, 12);
+        write_rtc(&mut mbc, RTC_MINUTES, 34);
+        write_rtc(&mut mbc, RTC_HOURS, 5);
+        write_rtc(&mut mbc, RTC_DAY_LOW, 0x67);
+        latch(&mut mbc);
+        mbc.write(0x4000, 0x00);
+        mbc.write(0xA000, 0x99);
+        let data = mbc.save_data();
+        assert_eq!(data.len(), constants::EXTERNAL_RAM_SIZE + RTC_SAVE_SIZE);
+
+        let mut loaded = rtc_cartridge();
+        loaded.load_save_data(&data);
+        assert_eq!(read_rtc(&mut loaded, 0x00), 0x99);
+        assert_eq!(read_rtc(&mut loaded, RTC_SECONDS), 12);
+        assert_eq!(read_rtc(&mut loaded, RTC_MINUTES), 34);
+        assert_eq!(read_rtc(&mut loaded, RTC_HOURS), 5);
+        assert_eq!(read_rtc(&mut loaded, RTC_DAY_LOW), 0x67);
+        assert_eq!(read_rtc(&mut loaded, RTC_DAY_HIGH), HALT_BIT | DAY_HIGH_BIT);
+    }
+
+    #[test]
+    fn rtc_catches_up_on_time_since_save() {
+        let mut data = rtc_cartridge().save_data();
+        //Pretend the save was written 1 day, 1 hour, 1 minute and 1 second ago
+        let saved_at = unix_time() - 90061;
+        let len = data.len();
+        data[len - 8..].copy_from_slice(&saved_at.to_le_bytes());
+
+        let mut loaded = rtc_cartridge();
+        loaded.load_save_data(&data);
+        latch(&mut loaded);
+        //Allow for the clock ticking over while the test runs
+        assert!((1..=2).contains(&read_rtc(&mut loaded, RTC_SECONDS)));
+        assert_eq!(read_rtc(&mut loaded, RTC_MINUTES), 1);
+        assert_eq!(read_rtc(&mut loaded, RTC_HOURS), 1);
+        assert_eq!(read_rtc(&mut loaded, RTC_DAY_LOW), 1);
+    }
+
+    #[test]
+    fn rtc_accepts_32_bit_timestamp_layout() {
+        let mut data = vec![0; constants::EXTERNAL_RAM_SIZE];
+        for value in [30u8, 20, 10, 5, HALT_BIT, 31, 21, 11, 6, HALT_BIT].iter() {
+            data.extend_from_slice(&(*value as u32).to_le_bytes());
+        }
+        data.extend_from_slice(&(unix_time() as u32).to_le_bytes());
+        assert_eq!(data.len(), constants::EXTERNAL_RAM_SIZE + RTC_SAVE_SIZE_SHORT);
+
+        let mut loaded = rtc_cartridge();
+        loaded.load_save_data(&data);
+        assert_eq!(read_rtc(&mut loaded, RTC_SECONDS), 31);
+        assert_eq!(read_rtc(&mut loaded, RTC_DAY_LOW), 6);
+        latch(&mut loaded);
+        assert_eq!(read_rtc(&mut loaded, RTC_SECONDS), 30);
+        assert_eq!(read_rtc(&mut loaded, RTC_MINUTES), 20);
+        assert_eq!(read_rtc(&mut loaded, RTC_HOURS), 10);
+    }
+
+    fn tick_seconds(mbc: &mut MBC3, seconds: u32) {
+        for _ in 0..seconds * constants::CLOCK_HZ as u32 {
+            mbc.tick();
+        }
+    }
+
+    #[test]
+    fn rom_bank_is_7_bits_and_bank_0_maps_to_1() {
+        let mut rom = vec![0; 128 * constants::ROM_BANK_SIZE];
+        for bank in 0..128 {
+            rom[bank * constants::ROM_BANK_SIZE] = bank as u8;
+        }
+        let mut mbc = MBC3::new(rom, 0, false);
+        mbc.write(0x2000, 0x7F);
+        assert_eq!(mbc.read(0x4000), 0x7F);
+        mbc.write(0x2000, 0x00);
+        assert_eq!(mbc.read(0x4000), 1);
+        mbc.write(0x2000, 0x85);
+        assert_eq!(mbc.read(0x4000), 5);
+    }
+
+    #[test]
+    fn ram_banks_and_rtc_registers_share_the_window() {
+        let mut mbc = MBC3::new(vec![0; 2 * constants::ROM_BANK_SIZE], 4 * constants::EXTERNAL_RAM_SIZE, true);
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0x4000, 0x00);
+        mbc.write(0xA000, 0x11);
+        mbc.write(0x4000, 0x03);
+        mbc.write(0xA000, 0x33);
+        write_rtc(&mut mbc, RTC_MINUTES, 42);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, 0x00), 0x11);
+        assert_eq!(read_rtc(&mut mbc, 0x03), 0x33);
+        assert_eq!(read_rtc(&mut mbc, RTC_MINUTES), 42);
+        assert_eq!(read_rtc(&mut mbc, 0x0D), 0xFF);
+    }
+
+    #[test]
+    fn reads_return_latched_time() {
+        let mut mbc = rtc_cartridge();
+        tick_seconds(&mut mbc, 2);
+        assert_eq!(read_rtc(&mut mbc, RTC_SECONDS), 0);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, RTC_SECONDS), 2);
+        tick_seconds(&mut mbc, 1);
+        assert_eq!(read_rtc(&mut mbc, RTC_SECONDS), 2);
+        //Writing 0x01 without a preceding 0x00 doesn't latch
+        mbc.write(0x6000, 0x01);
+        assert_eq!(read_rtc(&mut mbc, RTC_SECONDS), 2);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, RTC_SECONDS), 3);
+    }
+
+    #[test]
+    fn halt_stops_the_clock() {
+        let mut mbc = rtc_cartridge();
+        write_rtc(&mut mbc, RTC_DAY_HIGH, HALT_BIT);
+        tick_seconds(&mut mbc, 1);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, RTC_SECONDS), 0);
+        write_rtc(&mut mbc, RTC_DAY_HIGH, 0);
+        tick_seconds(&mut mbc, 1);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, RTC_SECONDS), 1);
+    }
+
+    #[test]
+    fn day_counter_overflow_sets_carry() {
+        let mut mbc = rtc_cartridge();
+        write_rtc(&mut mbc, RTC_SECONDS, 59);
+        write_rtc(&mut mbc, RTC_MINUTES, 59);
+        write_rtc(&mut mbc, RTC_HOURS, 23);
+        write_rtc(&mut mbc, RTC_DAY_LOW, 0xFF);
+        write_rtc(&mut mbc, RTC_DAY_HIGH, DAY_HIGH_BIT);
+        tick_seconds(&mut mbc, 1);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, RTC_SECONDS), 0);
+        assert_eq!(read_rtc(&mut mbc, RTC_HOURS), 0);
+        assert_eq!(read_rtc(&mut mbc, RTC_DAY_LOW), 0);
+        assert_eq!(read_rtc(&mut mbc, RTC_DAY_HIGH), DAY_CARRY_BIT);
+        //The carry stays set until software clears it
+        tick_seconds(&mut mbc, 1);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, RTC_DAY_HIGH), DAY_CARRY_BIT);
+    }
+}
@@ -0,0 +1,169 @@
+/*
+Implementation for MBC5 cartridges, including rumble variants: https://gbdev.io/pandocs/MBC5.html
+
+rom_bank: 9-bit ROM bank number, low 8 bits at 0x2000-0x2FFF and bit 8 at 0x3000-0x3FFF. Unlike MBC1/MBC3, bank 0 can be mapped to 0x4000-0x7FFF
+ram_bank: 4-bit RAM bank number (0x4000-0x5FFF). On rumble cartridges bit 3 drives the motor instead
+*/
+use crate::emulator::constants;
+use crate::emulator::memory::mbc::MemoryBankController;
+
+const RUMBLE_BIT: u8 = 0b00001000;
+
+pub struct MBC5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    rom_bank: u16,
+    ram_bank: u8,
+    has_rumble: bool,
+    rumble: bool,
+}
+
+impl MBC5 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, has_rumble: bool) -> Self {
+        Self {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble,
+            rumble: false,
+        }
+    }
+
+    fn rom_offset(&self, bank: usize, addr: u16) -> usize {
+        let banks = std::cmp::max(self.rom.len() / constants::ROM_BANK_SIZE, 1);
+        return (bank % banks) * constants::ROM_BANK_SIZE + (addr as usize & (constants::ROM_BANK_SIZE - 1));
+    }
+
+    fn ram_offset(&self, addr: u16) -> usize {
+        let offset = self.ram_bank as usize * constants::EXTERNAL_RAM_SIZE + (addr as usize - constants::EXTERNAL_RAM_START);
+        return offset % self.ram.len();
+    }
+}
+
+impl MemoryBankController for MBC5 {
+    fn read(&self, addr: u16) -> u8 {
+        match addr as usize {
+            constants::ONBOARD_ROM_START..=constants::ONBOARD_ROM_END => self.rom[self.rom_offset(0, addr)],
+            constants::SWITCHABLE_ROM_START..=constants::SWITCHABLE_ROM_END => self.rom[self.rom_offset(self.rom_bank as usize, addr)],
+            constants::EXTERNAL_RAM_START..=constants::EXTERNAL_RAM_END => {
+                if !self.ram_enabled || self.ram.is_empty() { return 0xFF; }
+                self.ram[self.ram_offset(addr)]
+            },
+            _ => panic!("Unreachable memory")
+        }
+    }
+
+    //little-endian (least significant byte first)
+    fn read_double(&self, addr: u16) -> u16 {
+        let low = self.read(addr);
+        let high = self.read(addr + 1);
+        return ((high as u16) << 8) + low as u16;
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr as usize {
+            0x0000..=0x1FFF => self.ram_enabled = data == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | data as u16,
+            0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | (((data & 0b1) as u16) << 8),
+            0x4000..=0x5FFF => {
+                if self.has_rumble {
+                    self.rumble = data & RUMBLE_BIT > 0;
+                    self.ram_bank = data & 0x07;
+                } else {
+                    self.ram_bank = data & 0x0F;
+                }
+            },
+            0x6000..=0x7FFF => (),
+            constants::EXTERNAL_RAM_START..=constants::EXTERNAL_RAM_END => {
+                if !self.ram_enabled || self.ram.is_empty() { return; }
+                let offset = self.ram_offset(addr);
+                self.ram[offset] = data;
+            },
+            _ => panic!("Unreachable memory")
+        }
+    }
+
+    //little-endian (least significant byte first)
+    fn write_double(&mut self, addr: u16, data: u16) {
+        self.write(addr, (data & 0x00FF) as u8);
+        self.write(addr + 1, (data >> 8) as u8);
+    }
+
+    fn tick(&mut self) {}
+
+    fn save_data(&self) -> Vec<u8> {
+        return self.ram.clone();
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        let len = std::cmp::min(self.ram.len(), data.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+    }
+
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //ROM where the first two bytes of every bank hold its bank number, little-endian
+    fn banked_rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * constants::ROM_BANK_SIZE];
+        for bank in 0..banks {
+            rom[bank * constants::ROM_BANK_SIZE] = (bank & 0xFF) as u8;
+            rom[bank * constants::ROM_BANK_SIZE + 1] = (bank >> 8) as u8;
+        }
+        return rom;
+    }
+
+    #[test]
+    fn rom_bank_number_is_9_bits() {
+        let mut mbc = MBC5::new(banked_rom(512), 0, false);
+        mbc.write(0x2000, 0x23);
+        assert_eq!(mbc.read_double(0x4000), 0x023);
+        mbc.write(0x3000, 0x01);
+        assert_eq!(mbc.read_double(0x4000), 0x123);
+        //Only bit 0 of the 0x3000 register is used
+        mbc.write(0x3000, 0xFE);
+        assert_eq!(mbc.read_double(0x4000), 0x023);
+    }
+
+    #[test]
+    fn bank_0_can_be_mapped_high() {
+        let mut mbc = MBC5::new(banked_rom(4), 0, false);
+        assert_eq!(mbc.read(0x4000), 1);
+        mbc.write(0x2000, 0x00);
+        assert_eq!(mbc.read(0x4000), 0);
+    }
+
+    #[test]
+    fn rumble_bit_is_not_part_of_ram_bank() {
+        let mut mbc = MBC5::new(banked_rom(2), 8 * constants::EXTERNAL_RAM_SIZE, true);
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0x4000, 0x01);
+        mbc.write(0xA000, 0x11);
+        mbc.write(0x4000, 0x09);
+        assert!(mbc.rumble());
+        assert_eq!(mbc.read(0xA000), 0x11);
+        mbc.write(0x4000, 0x01);
+        assert!(!mbc.rumble());
+        assert_eq!(mbc.read(0xA000), 0x11);
+    }
+
+    #[test]
+    fn bit_3_selects_ram_bank_without_rumble() {
+        let mut mbc = MBC5::new(banked_rom(2), 16 * constants::EXTERNAL_RAM_SIZE, false);
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0x4000, 0x01);
+        mbc.write(0xA000, 0x11);
+        mbc.write(0x4000, 0x09);
+        assert!(!mbc.rumble());
+        assert_eq!(mbc.read(0xA000), 0x00);
+    }
+}
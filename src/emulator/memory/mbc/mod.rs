@@ -1,4 +1,8 @@
 pub mod mbc0;
+pub mod mbc1;
+pub mod mbc2;
+pub mod mbc3;
+pub mod mbc5;
 
 pub trait MemoryBankController {
     //fn init(&self, data: Vec<u8>);
@@ -6,4 +10,12 @@ pub trait MemoryBankController {
     fn read_double(&self, addr: u16) -> u16;
     fn write(&mut self, addr:u16, data: u8);
     fn write_double(&mut self, addr:u16, data: u16);
-}
\ No newline at end of file
+    //Called once per clock for cartridge hardware that keeps time (MBC3 RTC)
+    fn tick(&mut self);
+    //Raw battery-backed state, in the layout used by .sav files
+    fn save_data(&self) -> Vec<u8>;
+    fn load_save_data(&mut self, data: &[u8]);
+    //True while a rumble cartridge is driving its motor
+    fn rumble(&self) -> bool;
+}
+
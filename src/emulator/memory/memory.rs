@@ -1,106 +1,714 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
 use crate::emulator::constants;
 use crate::emulator::memory::mbc::*;
-use crate::emulator::constants::{FOUR_KB, ONBOARD_ROM_END};
+use crate::emulator::memory::header::{CartridgeHeader, HeaderError, Mapper};
+use crate::emulator::cpu::interrupts::{Interrupt, InterruptRegisters};
+use crate::emulator::timer::timer::Timer;
+use crate::emulator::joypad::joypad::{Button, Joypad};
+use crate::emulator::ppu::ppu::{PPU, PpuMode};
+use crate::emulator::apu::apu::APU;
+use crate::emulator::serial::serial::Serial;
+use crate::emulator::emulator::Platform;
+
+//Reasons a boot ROM can't be used
+#[derive(Debug)]
+pub enum BootRomError {
+    Io(std::io::Error),
+    //Length in bytes, neither a DMG nor a CGB boot ROM
+    InvalidSize(usize),
+}
+
+impl fmt::Display for BootRomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BootRomError::Io(e) => write!(f, "Unable to read boot ROM: {}", e),
+            BootRomError::InvalidSize(x) => write!(f, "Invalid boot ROM size {}, expected {} (DMG) or {} (CGB) bytes",
+                                                   x, constants::DMG_BOOT_ROM_SIZE, constants::CGB_BOOT_ROM_SIZE),
+        }
+    }
+}
+
+impl std::error::Error for BootRomError {}
+
+/*
+Bits of each I/O register (0xFF00-0xFF7F) that always read as 1, indexed by address - 0xFF00.
+Unmapped addresses and write-only registers (HDMA1-4, 0xFF50) read as 0xFF. Where DMG and CGB differ this holds the
+CGB value, the owning subsystem adds the extra DMG bits and CGB-only registers read as 0xFF on DMG anyway.
+ */
+const IO_READ_MASKS: [u8; 0x80] = [
+    //P1    SB    SC          DIV   TIMA  TMA   TAC                                                       IF
+    0xC0, 0x00, 0x7C, 0xFF, 0x00, 0x00, 0x00, 0xF8, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xE0,
+    //NR10  NR11  NR12  NR13  NR14        NR21  NR22  NR23  NR24  NR30  NR31  NR32  NR33  NR34
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, 0xFF, 0x3F, 0x00, 0xFF, 0xBF, 0x7F, 0xFF, 0x9F, 0xFF, 0xBF, 0xFF,
+    //NR41  NR42  NR43  NR44  NR50  NR51  NR52
+    0xFF, 0x00, 0x00, 0xBF, 0x00, 0x00, 0x70, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    //Wave RAM
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    //LCDC  STAT  SCY   SCX   LY    LYC   DMA   BGP   OBP0  OBP1  WY    WX    KEY0  KEY1        VBK
+    0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x7E, 0xFF, 0xFE,
+    //BOOT  HDMA1 HDMA2 HDMA3 HDMA4 HDMA5
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    //                                              BCPS  BCPD  OCPS  OCPD
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x40, 0x00, 0x40, 0x00, 0xFF, 0xFF, 0xFF, 0xFF,
+    //SVBK
+    0xF8, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+
+/*
+In-progress OAM DMA transfer: https://gbdev.io/pandocs/OAM_DMA_Transfer.html
+source: start address (written value * 0x100)
+index: next OAM byte to copy
+delay: clocks left before the first byte is copied
+cycle: clocks since the last byte was copied, one byte is copied every machine cycle
+ */
+struct OamDma {
+    source: u16,
+    index: usize,
+    delay: u32,
+    cycle: u32,
+}
+
+/*
+CGB VRAM DMA: https://gbdev.io/pandocs/CGB_Registers.html#lcd-vram-dma-transfers
+source: next address to copy from (HDMA1/HDMA2, low 4 bits ignored)
+destination: next VRAM address to copy to (HDMA3/HDMA4, always inside 0x8000-0x9FF0)
+blocks: 16-byte blocks left to copy
+hblank: an HBlank transfer is in progress, one block is copied at the start of each HBlank
+stall: clocks the CPU stays halted while the DMA controller owns the bus
+ */
+struct VramDma {
+    source: u16,
+    destination: u16,
+    blocks: u8,
+    hblank: bool,
+    stall: u32,
+}
 
+impl VramDma {
+    fn new() -> Self {
+        Self {
+            source: 0,
+            destination: constants::SWITCHABLE_VRAM_START as u16,
+            blocks: 0,
+            hblank: false,
+            stall: 0,
+        }
+    }
+}
+
+/*
+Memory map and system bus. Memory-mapped subsystems (timer, joypad, serial port, PPU, APU, interrupt registers) are owned here
+so every CPU access to 0xFF00-0xFFFF is routed to the single component that owns the register.
+ */
 pub struct Memory {
-    header: [u8; 0x50],
-    onboard_rom: [u8; constants::SIXTEEN_KB],
-    memory_bank_controller: Box<MemoryBankController>,
-    vram: [[u8; constants::EIGHT_KB]; 2],
+    pub timer: Timer,
+    pub joypad: Joypad,
+    pub serial: Serial,
+    pub ppu: PPU,
+    pub apu: APU,
+    pub interrupts: InterruptRegisters,
+    platform: Platform,
+    key0: u8,
+    compatibility: bool,
+    header: CartridgeHeader,
+    memory_bank_controller: Box<dyn MemoryBankController>,
+    vram: Box<[[u8; constants::EIGHT_KB]; 2]>,
     vram_active_bank: usize,
     onboard_wram: [u8; constants::FOUR_KB],
-    switchable_wram: [[u8; constants::FOUR_KB]; 7],
+    switchable_wram: Box<[[u8; constants::FOUR_KB]; 7]>,
     wram_active_bank: usize,
-    oam: [u8; 0x9F],
-    io_reg: [u8; 0x7F],
+    svbk: u8,
+    oam: [u8; constants::OAM_SIZE],
+    io_reg: [u8; 0x80],
     hram: [u8; 0x8E],
-    ie_reg: u8,
     vram_lock: bool,
     oam_lock: bool,
+    save_path: Option<PathBuf>,
+    save_dirty: bool,
+    boot_rom: Option<Vec<u8>>,
+    dma: Option<OamDma>,
+    vram_dma: VramDma,
+    double_speed: bool,
+    speed_switch_armed: bool,
 }
 
 impl Memory {
-    pub fn new(path: String) -> Self {
-        let rom_data = std::fs::read(path).unwrap();
-        println!("{}", rom_data.len());
-        let cartridge_type = rom_data[constants::CARTRIDGE_TYPE];
-        let mbc = match cartridge_type {
-            0x00 => Box::new(mbc0::MBC0::new()),
-            _ => panic!("Unsupported cartridge type")
+    /*
+    Build the memory map around a cartridge image. Battery RAM is only persisted when the image came from a file (path).
+    Fails on an image without a complete header or with a mapper that isn't implemented.
+     */
+    pub fn from_rom(rom_data: Vec<u8>, path: Option<&str>) -> Result<Self, HeaderError> {
+        let header = CartridgeHeader::parse(&rom_data)?;
+        let ram_size = header.ram_size;
+        let mbc: Box<dyn MemoryBankController> = match header.mapper() {
+            Mapper::None => Box::new(mbc0::MBC0::new(rom_data)),
+            Mapper::MBC1 => Box::new(mbc1::MBC1::new(rom_data, ram_size)),
+            Mapper::MBC2 => Box::new(mbc2::MBC2::new(rom_data)),
+            Mapper::MBC3 => Box::new(mbc3::MBC3::new(rom_data, ram_size, header.has_rtc())),
+            Mapper::MBC5 => Box::new(mbc5::MBC5::new(rom_data, ram_size, header.has_rumble())),
+            Mapper::Unsupported(x) => return Err(HeaderError::UnsupportedCartridge(x))
+        };
+        //Battery-backed cartridges keep their RAM in <rom>.sav next to the ROM
+        let save_path = match (header.has_battery(), path) {
+            (true, Some(x)) => Some(Path::new(x).with_extension("sav")),
+            _ => None,
         };
         let mut mem = Self {
-            header: [0; 0x50],
-            onboard_rom: [0; constants::ROM_BANK_SIZE],
+            timer: Timer::power_on(),
+            joypad: Joypad::new(),
+            serial: Serial::new(),
+            ppu: PPU::new(),
+            apu: APU::new(),
+            interrupts: InterruptRegisters::new(),
+            platform: Platform::DMG,
+            key0: 0,
+            compatibility: false,
+            header,
             memory_bank_controller: mbc,
-            vram: [[0; constants::EIGHT_KB]; 2],
+            vram: Box::new([[0; constants::EIGHT_KB]; 2]),
             vram_active_bank: 0,
             onboard_wram: [0; constants::FOUR_KB],
-            switchable_wram: [[0; constants::FOUR_KB]; 7],
+            switchable_wram: Box::new([[0; constants::FOUR_KB]; 7]),
             wram_active_bank: 0,
-            oam: [0; 0x9F],
-            io_reg: [0; 0x7F],
+            svbk: 0,
+            oam: [0; constants::OAM_SIZE],
+            io_reg: [0; 0x80],
             hram: [0; 0x8E],
-            ie_reg: 0,
             vram_lock: false,
             oam_lock: false,
+            save_path,
+            save_dirty: false,
+            boot_rom: None,
+            dma: None,
+            vram_dma: VramDma::new(),
+            double_speed: false,
+            speed_switch_armed: false,
         };
-        for i in 0x0100..0x0150 { mem.header[i - 0x0100] = rom_data[i] }
-        for i in constants::ONBOARD_ROM_START..=ONBOARD_ROM_END { mem.onboard_rom[i] = rom_data[i] }
-        //TODO -- currently only supports MBC0
-        for i in constants::SWITCHABLE_ROM_START..=constants::SWITCHABLE_ROM_END { mem.memory_bank_controller.write(i as u16, rom_data[i]) }
-        return mem;
+        mem.load_battery();
+        return Ok(mem);
     }
 
     pub fn read(&self, addr: u16) -> u8 {
+        if self.dma_blocks_read(addr) { return 0xFF; }
+        match &self.boot_rom {
+            Some(boot_rom) => if Memory::boot_rom_mapped(boot_rom, addr) { return boot_rom[addr as usize]; },
+            None => ()
+        }
         let byte: u8 = match addr as usize {
-            constants::ONBOARD_ROM_START..=constants::ONBOARD_ROM_END => self.onboard_rom[addr as usize],
+            constants::ONBOARD_ROM_START..=constants::ONBOARD_ROM_END => self.memory_bank_controller.read(addr),
             constants::SWITCHABLE_ROM_START..=constants::SWITCHABLE_ROM_END => self.memory_bank_controller.read(addr),
-            constants::SWITCHABLE_VRAM_START..=constants::SWITCHABLE_VRAM_END => self.vram[self.vram_active_bank][addr as usize - constants::SWITCHABLE_VRAM_START],
+            constants::SWITCHABLE_VRAM_START..=constants::SWITCHABLE_VRAM_END => {
+                if self.vram_lock { return 0xFF; }
+                self.vram[self.vram_active_bank][addr as usize - constants::SWITCHABLE_VRAM_START]
+            },
             constants::EXTERNAL_RAM_START..=constants::EXTERNAL_RAM_END => self.memory_bank_controller.read(addr),
             constants::ONBOARD_WRAM_START..=constants::ONBOARD_WRAM_END => self.onboard_wram[addr as usize - constants::ONBOARD_WRAM_START],
             constants::SWITCHABLE_WRAM_START..=constants::SWITCHABLE_WRAM_END => self.switchable_wram[self.wram_active_bank][addr as usize - constants::SWITCHABLE_WRAM_START],
             constants::ECHO_RAM_LOW_START..=constants::ECHO_RAM_LOW_END => self.onboard_wram[addr as usize - constants::ONBOARD_WRAM_START],
-            constants::ECHO_RAM_HIGH_START..=constants::ECHO_RAM_HIGH_END => self.memory_bank_controller.read(addr),
-            constants::OAM_START..=constants::OAM_END => self.oam[addr as usize - constants::OAM_START],
-            constants::IO_REG_START..=constants::IO_REG_END => self.io_reg[addr as usize - constants::IO_REG_START],
+            constants::ECHO_RAM_HIGH_START..=constants::ECHO_RAM_HIGH_END => self.switchable_wram[self.wram_active_bank][addr as usize - constants::ECHO_RAM_HIGH_START],
+            constants::OAM_START..=constants::OAM_END => {
+                if self.oam_lock { return 0xFF; }
+                self.oam[addr as usize - constants::OAM_START]
+            },
+            constants::UNUSABLE_START..=constants::UNUSABLE_END => 0xFF,
+            constants::IO_REG_START..=constants::IO_REG_END => self.read_io(addr),
             constants::HRAM_START..=constants::HRAM_END => self.hram[addr as usize - constants::HRAM_START],
-            constants::IE_REGISTER => self.ie_reg,
+            constants::IE_REGISTER => self.interrupts.read_enable(),
             _ => panic!("Unreachable address")
         };
         return byte;
     }
 
     pub fn write(&mut self, addr: u16, data: u8) {
+        //Writing a non-zero value to 0xFF50 unmaps the boot ROM until the next power cycle
+        if addr as usize == constants::BOOT_ROM_DISABLE && data != 0 && self.boot_rom.is_some() {
+            self.boot_rom = None;
+            //The CGB boot ROM writes KEY0 to tell the hardware a DMG game is about to start
+            if self.platform == Platform::GBC && self.key0 & constants::KEY0_DMG_MODE > 0 {
+                self.enter_compatibility_mode();
+            }
+        }
+        if self.dma_conflict(addr) { return; }
+        if addr as usize == constants::DMA {
+            self.start_dma(data);
+        }
         match addr as usize {
-            constants::ONBOARD_ROM_START..=constants::ONBOARD_ROM_END => self.onboard_rom[addr as usize] = data,
+            constants::ONBOARD_ROM_START..=constants::ONBOARD_ROM_END => self.memory_bank_controller.write(addr, data),
             constants::SWITCHABLE_ROM_START..=constants::SWITCHABLE_ROM_END => self.memory_bank_controller.write(addr, data),
-            constants::SWITCHABLE_VRAM_START..=constants::SWITCHABLE_VRAM_END => self.vram[self.vram_active_bank][addr as usize - constants::SWITCHABLE_VRAM_START] = data,
-            constants::EXTERNAL_RAM_START..=constants::EXTERNAL_RAM_END => self.memory_bank_controller.write(addr, data),
+            constants::SWITCHABLE_VRAM_START..=constants::SWITCHABLE_VRAM_END => {
+                if self.vram_lock { return; }
+                self.vram[self.vram_active_bank][addr as usize - constants::SWITCHABLE_VRAM_START] = data;
+            },
+            constants::EXTERNAL_RAM_START..=constants::EXTERNAL_RAM_END => {
+                self.memory_bank_controller.write(addr, data);
+                self.save_dirty = true;
+            },
             constants::ONBOARD_WRAM_START..=constants::ONBOARD_WRAM_END => self.onboard_wram[addr as usize - constants::ONBOARD_WRAM_START] = data,
             constants::SWITCHABLE_WRAM_START..=constants::SWITCHABLE_WRAM_END => self.switchable_wram[self.wram_active_bank][addr as usize - constants::SWITCHABLE_WRAM_START] = data,
             constants::ECHO_RAM_LOW_START..=constants::ECHO_RAM_LOW_END => self.onboard_wram[addr as usize - constants::ONBOARD_WRAM_START] = data,
-            constants::ECHO_RAM_HIGH_START..=constants::ECHO_RAM_HIGH_END => self.memory_bank_controller.write(addr, data),
-            constants::OAM_START..=constants::OAM_END => self.oam[addr as usize - constants::OAM_START] = data,
-            constants::IO_REG_START..=constants::IO_REG_END => self.io_reg[addr as usize - constants::IO_REG_START] = data,
+            constants::ECHO_RAM_HIGH_START..=constants::ECHO_RAM_HIGH_END => self.switchable_wram[self.wram_active_bank][addr as usize - constants::ECHO_RAM_HIGH_START] = data,
+            constants::OAM_START..=constants::OAM_END => {
+                if self.oam_lock { return; }
+                self.oam[addr as usize - constants::OAM_START] = data;
+            },
+            constants::UNUSABLE_START..=constants::UNUSABLE_END => (),
+            constants::IO_REG_START..=constants::IO_REG_END => self.write_io(addr, data),
             constants::HRAM_START..=constants::HRAM_END => self.hram[addr as usize - constants::HRAM_START] = data,
-            constants::IE_REGISTER => self.ie_reg = data,
+            constants::IE_REGISTER => self.interrupts.write_enable(data),
             _ => panic!("Unreachable address")
         };
     }
 
-    pub fn lock_vram(&mut self) {
+    /*
+    I/O register dispatch: https://gbdev.io/pandocs/Hardware_Reg_List.html
+    Registers owned by a subsystem are forwarded to it. Registers without an owner yet are kept in io_reg.
+    Either way the unused bits from IO_READ_MASKS read back as 1 like they do on hardware.
+     */
+    fn read_io(&self, addr: u16) -> u8 {
+        let value = match addr as usize {
+            constants::P1 => self.joypad.read_register(),
+            constants::SB..=constants::SC => self.serial.read_register(addr),
+            constants::DIV..=constants::TAC => self.timer.read_register(addr),
+            constants::IF => self.interrupts.read_flags(),
+            constants::NR10..=constants::WAVE_RAM_END => self.apu.read_register(addr),
+            constants::LCDC..=constants::LYC | constants::BGP..=constants::WX | constants::BCPS..=constants::OCPD => self.ppu.read_register(addr),
+            constants::VBK => if self.cgb_mode() { 0b11111110 | self.vram_active_bank as u8 } else { 0xFF },
+            constants::KEY0 => if self.key0_writable() { self.key0 } else { 0xFF },
+            constants::KEY1 => if self.cgb_mode() { 0b01111110 | ((self.double_speed as u8) << 7) | self.speed_switch_armed as u8 } else { 0xFF },
+            constants::HDMA5 => if self.cgb_mode() { self.read_hdma5() } else { 0xFF },
+            constants::SVBK => if self.cgb_mode() { 0b11111000 | self.svbk } else { 0xFF },
+            _ => self.io_reg[addr as usize - constants::IO_REG_START]
+        };
+        return value | IO_READ_MASKS[addr as usize - constants::IO_REG_START];
+    }
+
+    fn write_io(&mut self, addr: u16, data: u8) {
+        match addr as usize {
+            constants::P1 => if self.joypad.write_register(data) { self.interrupts.set_flag(Interrupt::Joypad); },
+            constants::SB..=constants::SC => self.serial.write_register(addr, data),
+            constants::DIV..=constants::TAC => self.timer.write_register(addr, data),
+            constants::IF => self.interrupts.write_flags(data),
+            constants::NR10..=constants::WAVE_RAM_END => self.apu.write_register(addr, data),
+            constants::LCDC..=constants::LYC | constants::BGP..=constants::WX | constants::BCPS..=constants::OCPD => self.ppu.write_register(addr, data),
+            constants::KEY0 => if self.key0_writable() { self.key0 = data; },
+            constants::KEY1 => if self.cgb_mode() { self.speed_switch_armed = data & 0b1 > 0; },
+            constants::VBK => self.write_vbk(data),
+            constants::HDMA1..=constants::HDMA5 => self.write_vram_dma(addr, data),
+            constants::SVBK => self.write_svbk(data),
+            _ => self.io_reg[addr as usize - constants::IO_REG_START] = data
+        }
+    }
+
+    /*
+    CGB bank switching: https://gbdev.io/pandocs/CGB_Registers.html
+    VBK selects VRAM bank 0-1 at 0x8000-0x9FFF, SVBK selects WRAM bank 1-7 at 0xD000-0xDFFF (writing 0 selects bank 1).
+    Both are ignored on DMG hardware, which only has the first bank of each.
+    Echo RAM at 0xF000-0xFDFF mirrors whichever WRAM bank is selected.
+     */
+    fn write_vbk(&mut self, data: u8) {
+        if !self.cgb_mode() { return; }
+        self.vram_active_bank = (data & 0b1) as usize;
+    }
+
+    fn write_svbk(&mut self, data: u8) {
+        if !self.cgb_mode() { return; }
+        //The written value reads back as is, only the bank selection treats 0 as 1
+        self.svbk = data & 0b111;
+        let bank = match self.svbk {
+            0 => 1,
+            x => x as usize,
+        };
+        //switchable_wram[0] holds bank 1
+        self.wram_active_bank = bank - 1;
+    }
+
+    //Button state from the frontend, requests the joypad interrupt when an input line goes low
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        if self.joypad.set_button(button, pressed) {
+            self.interrupts.set_flag(Interrupt::Joypad);
+        }
+    }
+
+    /*
+    Serial port, see serial::serial. Each of these raises the serial interrupt when a transfer completes.
+    A transfer in progress while the cable is unplugged finishes with 0xFF shifted in.
+     */
+    pub fn tick_serial(&mut self) {
+        if self.serial.tick() {
+            self.interrupts.set_flag(Interrupt::Serial);
+        }
+    }
+
+    pub fn serial_transfer(&mut self) -> Option<u8> {
+        self.serial.take_transfer()
+    }
+
+    pub fn serial_complete(&mut self, data: u8) {
+        if self.serial.complete(data) {
+            self.interrupts.set_flag(Interrupt::Serial);
+        }
+    }
+
+    //Byte clocked in by the peer, returns the byte it receives in exchange
+    pub fn serial_receive(&mut self, data: u8) -> u8 {
+        match self.serial.receive(data) {
+            Some(x) => {
+                self.interrupts.set_flag(Interrupt::Serial);
+                x
+            },
+            None => 0xFF,
+        }
+    }
+
+    //Ticks until a running internally clocked transfer finishes, the serial clock runs twice per tick in double-speed mode
+    pub fn serial_ticks_remaining(&self) -> Option<u32> {
+        match self.serial.clocks_remaining() {
+            Some(x) => Some(if self.double_speed { (x + 1) / 2 } else { x }),
+            None => None,
+        }
+    }
+
+    pub fn set_serial_connected(&mut self, connected: bool) {
+        if self.serial.set_connected(connected) {
+            self.interrupts.set_flag(Interrupt::Serial);
+        }
+    }
+
+    /*
+    CGB speed switch: https://gbdev.io/pandocs/CGB_Registers.html#ff4d--key1-cgb-mode-only-prepare-speed-switch
+    KEY1 bit 0 arms the switch and bit 7 reports the current speed. The switch happens when the CPU executes STOP,
+    which returns true here so the CPU carries on instead of entering STOP mode. DIV is reset either way.
+     */
+    pub fn stop(&mut self) -> bool {
+        self.timer.write_counter();
+        if !self.speed_switch_armed { return false; }
+        self.speed_switch_armed = false;
+        self.double_speed = !self.double_speed;
+        return true;
+    }
+
+    pub fn double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    /*
+    HDMA1-4 are write-only. Writing HDMA5 starts a transfer of ((data & 0x7F) + 1) * 16 bytes:
+        * bit 7 clear: general-purpose DMA, everything is copied at once and the CPU is halted until it's done
+        * bit 7 set: HBlank DMA, 16 bytes are copied at the start of each HBlank (immediately if the LCD is off)
+    Writing HDMA5 with bit 7 clear while an HBlank transfer is running cancels it instead.
+     */
+    fn write_vram_dma(&mut self, addr: u16, data: u8) {
+        if !self.cgb_mode() { return; }
+        let dma = &mut self.vram_dma;
+        match addr as usize {
+            constants::HDMA1 => dma.source = (dma.source & 0x00FF) | ((data as u16) << 8),
+            constants::HDMA2 => dma.source = (dma.source & 0xFF00) | (data & 0xF0) as u16,
+            constants::HDMA3 => dma.destination = (dma.destination & 0x00FF) | (((data & 0x1F) as u16) << 8) | 0x8000,
+            constants::HDMA4 => dma.destination = (dma.destination & 0xFF00) | (data & 0xF0) as u16,
+            constants::HDMA5 => {
+                if dma.hblank && data & 0x80 == 0 {
+                    dma.hblank = false;
+                    return;
+                }
+                dma.blocks = (data & 0x7F) + 1;
+                if data & 0x80 == 0 {
+                    while self.vram_dma.blocks > 0 {
+                        self.copy_vram_dma_block();
+                    }
+                } else {
+                    dma.hblank = true;
+                    if self.ppu.read_register(constants::LCDC as u16) & constants::LCDC_ENABLE == 0 {
+                        self.copy_vram_dma_block();
+                    }
+                }
+            },
+            _ => ()
+        }
+    }
+
+    //Bit 7 is clear while an HBlank transfer is running. The low bits hold the remaining length in blocks, minus 1.
+    fn read_hdma5(&self) -> u8 {
+        let remaining = self.vram_dma.blocks.wrapping_sub(1) & 0x7F;
+        if self.vram_dma.hblank { remaining } else { 0x80 | remaining }
+    }
+
+    fn copy_vram_dma_block(&mut self) {
+        for _ in 0..constants::VRAM_DMA_BLOCK_SIZE {
+            let data = self.read_dma_source(self.vram_dma.source);
+            let offset = self.vram_dma.destination as usize - constants::SWITCHABLE_VRAM_START;
+            self.vram[self.vram_active_bank][offset] = data;
+            self.vram_dma.source = self.vram_dma.source.wrapping_add(1);
+            self.vram_dma.destination = 0x8000 | (self.vram_dma.destination.wrapping_add(1) & 0x1FFF);
+        }
+        self.vram_dma.blocks -= 1;
+        self.vram_dma.stall += constants::VRAM_DMA_CYCLES_PER_BLOCK;
+        if self.vram_dma.blocks == 0 { self.vram_dma.hblank = false; }
+    }
+
+    //True while a VRAM DMA transfer holds the CPU off the bus, counts down one clock per call
+    pub fn tick_vram_dma_stall(&mut self) -> bool {
+        if self.vram_dma.stall == 0 { return false; }
+        self.vram_dma.stall -= 1;
+        return true;
+    }
+
+    /*
+    Advance the PPU by one dot and raise its interrupts. The CPU loses access to OAM during OAM scan,
+    and to both VRAM and OAM while the PPU is drawing.
+     */
+    pub fn tick_ppu(&mut self) {
+        let previous_mode = self.ppu.mode();
+        for interrupt in self.ppu.tick(&self.vram, &self.oam) {
+            self.interrupts.set_flag(interrupt);
+        }
+        //An HBlank VRAM DMA copies one block as each visible line enters HBlank
+        if self.vram_dma.hblank && previous_mode == PpuMode::Drawing && self.ppu.mode() == PpuMode::HBlank {
+            self.copy_vram_dma_block();
+        }
+        match self.ppu.mode() {
+            PpuMode::OamScan => {
+                self.unlock_vram();
+                self.lock_oam();
+            },
+            PpuMode::Drawing => {
+                self.lock_vram();
+                self.lock_oam();
+            },
+            PpuMode::HBlank | PpuMode::VBlank => {
+                self.unlock_vram();
+                self.unlock_oam();
+            }
+        }
+    }
+
+    //Advance cartridge hardware by one clock
+    pub fn tick_mbc(&mut self) {
+        self.memory_bank_controller.tick();
+    }
+
+    pub fn save_data(&self) -> Vec<u8> {
+        self.memory_bank_controller.save_data()
+    }
+
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        self.memory_bank_controller.load_save_data(data);
+    }
+
+    /*
+    OAM DMA copies 160 bytes from (value * 0x100) to OAM, one byte per machine cycle, after a one machine cycle startup delay.
+    While it runs every CPU read outside HRAM returns 0xFF, so code has to run from HRAM. Writes are only dropped on
+    the bus the transfer is reading from (external bus or VRAM bus) and to OAM, I/O registers stay writable.
+     */
+    fn start_dma(&mut self, data: u8) {
+        self.dma = Some(OamDma {
+            source: (data as u16) << 8,
+            index: 0,
+            delay: constants::OAM_DMA_STARTUP_CYCLES,
+            cycle: 0,
+        });
+    }
+
+    //Advance an active OAM DMA transfer by one clock
+    pub fn tick_dma(&mut self) {
+        let mut dma = match self.dma.take() {
+            Some(x) => x,
+            None => return,
+        };
+        if dma.delay > 0 {
+            dma.delay -= 1;
+            self.dma = Some(dma);
+            return;
+        }
+        dma.cycle += 1;
+        if dma.cycle == constants::OAM_DMA_CYCLES_PER_BYTE {
+            dma.cycle = 0;
+            self.oam[dma.index] = self.read_dma_source(dma.source + dma.index as u16);
+            dma.index += 1;
+            if dma.index == constants::OAM_DMA_LENGTH { return; }
+        }
+        self.dma = Some(dma);
+    }
+
+    //Reads as seen by the DMA controller, which ignores the PPU locks. Sources above 0xDFFF hit echo RAM.
+    fn read_dma_source(&self, addr: u16) -> u8 {
+        match addr as usize {
+            constants::ONBOARD_ROM_START..=constants::SWITCHABLE_ROM_END => self.memory_bank_controller.read(addr),
+            constants::SWITCHABLE_VRAM_START..=constants::SWITCHABLE_VRAM_END => self.vram[self.vram_active_bank][addr as usize - constants::SWITCHABLE_VRAM_START],
+            constants::EXTERNAL_RAM_START..=constants::EXTERNAL_RAM_END => self.memory_bank_controller.read(addr),
+            constants::ONBOARD_WRAM_START..=constants::ONBOARD_WRAM_END => self.onboard_wram[addr as usize - constants::ONBOARD_WRAM_START],
+            constants::SWITCHABLE_WRAM_START..=constants::SWITCHABLE_WRAM_END => self.switchable_wram[self.wram_active_bank][addr as usize - constants::SWITCHABLE_WRAM_START],
+            _ => self.read_dma_source(addr - 0x2000),
+        }
+    }
+
+    fn on_video_bus(addr: u16) -> bool {
+        match addr as usize {
+            constants::SWITCHABLE_VRAM_START..=constants::SWITCHABLE_VRAM_END => true,
+            _ => false
+        }
+    }
+
+    //True if a CPU write to addr collides with a running OAM DMA transfer
+    fn dma_conflict(&self, addr: u16) -> bool {
+        let dma = match &self.dma {
+            Some(x) => x,
+            None => return false,
+        };
+        if dma.delay > 0 { return false; }
+        match addr as usize {
+            constants::OAM_START..=0xFEFF => true,
+            constants::IO_REG_START..=constants::IE_REGISTER => false,
+            _ => Memory::on_video_bus(addr) == Memory::on_video_bus(dma.source),
+        }
+    }
+
+    //Only HRAM stays readable while a transfer is copying
+    fn dma_blocks_read(&self, addr: u16) -> bool {
+        match &self.dma {
+            Some(dma) if dma.delay == 0 => match addr as usize {
+                constants::HRAM_START..=constants::HRAM_END => false,
+                _ => true,
+            },
+            _ => false,
+        }
+    }
+
+    /*
+    Overlays a boot ROM on the cartridge ROM. A DMG boot ROM covers 0x0000-0x00FF, a CGB boot ROM also
+    covers 0x0200-0x08FF, leaving the cartridge header at 0x0100-0x01FF visible.
+     */
+    pub fn load_boot_rom(&mut self, data: Vec<u8>) -> Result<(), BootRomError> {
+        match data.len() {
+            constants::DMG_BOOT_ROM_SIZE | constants::CGB_BOOT_ROM_SIZE => self.boot_rom = Some(data),
+            x => return Err(BootRomError::InvalidSize(x)),
+        }
+        return Ok(());
+    }
+
+    fn boot_rom_mapped(boot_rom: &Vec<u8>, addr: u16) -> bool {
+        match addr as usize {
+            constants::ONBOARD_ROM_START..=constants::BOOT_ROM_LOW_END => true,
+            constants::BOOT_ROM_HIGH_START..=constants::BOOT_ROM_HIGH_END => boot_rom.len() == constants::CGB_BOOT_ROM_SIZE,
+            _ => false
+        }
+    }
+
+    /*
+    Battery RAM persistence. The .sav file is the raw external RAM contents (plus the RTC block for MBC3+TIMER),
+    the same layout used by other emulators so saves can be moved between them.
+     */
+    fn load_battery(&mut self) {
+        let path = match &self.save_path {
+            Some(x) => x,
+            None => return,
+        };
+        match std::fs::read(path) {
+            Ok(data) => self.memory_bank_controller.load_save_data(&data),
+            //A game that has never been saved has no file yet
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => println!("Unable to read save file {}: {}", path.display(), e),
+        }
+    }
+
+    pub fn save_battery(&mut self) -> std::io::Result<()> {
+        let path = match &self.save_path {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        //Write to a temporary file first so a crash mid-write can't truncate the existing save
+        let temp_path = path.with_extension("sav.tmp");
+        std::fs::write(&temp_path, self.memory_bank_controller.save_data())?;
+        std::fs::rename(&temp_path, path)?;
+        self.save_dirty = false;
+        return Ok(());
+    }
+
+    //Only touch the disk if external RAM was written since the last save
+    pub fn flush_battery(&mut self) -> std::io::Result<()> {
+        if !self.save_dirty { return Ok(()); }
+        return self.save_battery();
+    }
+
+    pub fn header(&self) -> &CartridgeHeader {
+        &self.header
+    }
+
+    //Hardware model being emulated, decides whether the CGB-only registers are active
+    pub fn set_platform(&mut self, platform: Platform) {
+        self.platform = platform;
+        self.compatibility = false;
+        self.ppu.set_cgb_mode(platform == Platform::GBC);
+        self.serial.set_cgb_mode(platform == Platform::GBC);
+        self.vram_active_bank = 0;
+        self.wram_active_bank = 0;
+        self.svbk = 0;
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    /*
+    I/O registers as the boot ROM leaves them: https://gbdev.io/pandocs/Power_Up_Sequence.html#hardware-registers
+    Used when starting at 0x0100 without a boot ROM. The sound channels aren't retriggered, the boot chime would
+    have died away by the time the game starts.
+     */
+    pub fn load_post_boot_state(&mut self) {
+        self.ppu.load_post_boot_state();
+        for (addr, data) in constants::POST_BOOT_APU.iter() {
+            self.apu.write_register(*addr as u16, *data);
+        }
+    }
+
+    /*
+    DMG compatibility mode: https://gbdev.io/pandocs/CGB_Registers.html#ff4c--key0sys-cgb-mode-only-cpu-mode-select
+    CGB hardware running a DMG game behaves like a DMG, except that BGP/OBP0/OBP1 are coloured through the CGB
+    palettes. VBK, SVBK, KEY1, HDMA and the palette registers are disabled and the first VRAM/WRAM banks stay mapped.
+     */
+    pub fn enter_compatibility_mode(&mut self) {
+        if self.platform != Platform::GBC { return; }
+        self.compatibility = true;
+        self.ppu.set_compatibility_mode();
+        self.serial.set_cgb_mode(false);
+        self.vram_active_bank = 0;
+        self.wram_active_bank = 0;
+        self.svbk = 0;
+        self.vram_dma.hblank = false;
+        self.vram_dma.blocks = 0;
+    }
+
+    //Registers only present in CGB mode, not on a DMG or in DMG compatibility mode
+    fn cgb_mode(&self) -> bool {
+        self.platform == Platform::GBC && !self.compatibility
+    }
+
+    //KEY0 can only be written by the CGB boot ROM, it locks once the boot ROM unmaps itself
+    fn key0_writable(&self) -> bool {
+        self.platform == Platform::GBC && self.boot_rom.is_some()
+    }
+
+    pub fn rumble(&self) -> bool {
+        self.memory_bank_controller.rumble()
+    }
+
+    /*
+    VRAM / OAM locks are driven by the PPU mode. While locked, CPU reads return 0xFF and writes are dropped.
+     */
+    fn lock_vram(&mut self) {
         self.vram_lock = true;
     }
 
-    pub fn unlock_vram(&mut self) {
+    fn unlock_vram(&mut self) {
         self.vram_lock = false;
     }
 
-    pub fn lock_oam(&mut self) {
+    fn lock_oam(&mut self) {
         self.oam_lock = true;
     }
 
-    pub fn unlock_oam(&mut self) {
+    fn unlock_oam(&mut self) {
         self.oam_lock = false;
     }
 
@@ -110,4 +718,258 @@ impl Memory {
             println!("{:#04x}", self.read(i as u16));
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //32 KiB MBC1+RAM+BATTERY image with 8 KiB of RAM
+    fn battery_rom() -> Vec<u8> {
+        let mut rom = vec![0; 2 * constants::ROM_BANK_SIZE];
+        rom[constants::CARTRIDGE_TYPE] = 0x03;
+        rom[constants::RAM_SIZE] = 0x02;
+        return rom;
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gameboyo_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        return dir;
+    }
+
+    #[test]
+    fn battery_ram_round_trips_through_sav_file() {
+        let dir = temp_dir("sav_round_trip");
+        let rom_path = dir.join("game.gb");
+        let rom_path = rom_path.to_str().unwrap();
+        let mut memory = Memory::from_rom(battery_rom(), Some(rom_path)).unwrap();
+        memory.write(0x0000, 0x0A);
+        memory.write(0xA000, 0x12);
+        memory.write(0xBFFF, 0x34);
+        memory.flush_battery().unwrap();
+        assert!(dir.join("game.sav").exists());
+        assert!(!dir.join("game.sav.tmp").exists());
+
+        let mut reloaded = Memory::from_rom(battery_rom(), Some(rom_path)).unwrap();
+        reloaded.write(0x0000, 0x0A);
+        assert_eq!(reloaded.read(0xA000), 0x12);
+        assert_eq!(reloaded.read(0xBFFF), 0x34);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn flush_only_writes_after_ram_changes() {
+        let dir = temp_dir("sav_flush");
+        let rom_path = dir.join("game.gb");
+        let mut memory = Memory::from_rom(battery_rom(), Some(rom_path.to_str().unwrap())).unwrap();
+        memory.flush_battery().unwrap();
+        assert!(!dir.join("game.sav").exists());
+        memory.write(0x0000, 0x0A);
+        memory.write(0xA000, 0x01);
+        memory.flush_battery().unwrap();
+        assert!(dir.join("game.sav").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn in_memory_images_are_never_saved() {
+        let mut memory = Memory::from_rom(battery_rom(), None).unwrap();
+        memory.write(0x0000, 0x0A);
+        memory.write(0xA000, 0x01);
+        assert!(memory.flush_battery().is_ok());
+        assert_eq!(memory.save_data()[0], 0x01);
+    }
+
+    #[test]
+    fn key1_reports_current_speed_and_armed_switch() {
+        let mut memory = Memory::from_rom(battery_rom(), None).unwrap();
+        memory.set_platform(Platform::GBC);
+        assert_eq!(memory.read(constants::KEY1 as u16), 0x7E);
+        memory.write(constants::KEY1 as u16, 0x01);
+        assert_eq!(memory.read(constants::KEY1 as u16), 0x7F);
+        assert!(memory.stop());
+        assert_eq!(memory.read(constants::KEY1 as u16), 0xFE);
+    }
+
+    #[test]
+    fn stop_toggles_speed_only_when_armed() {
+        let mut memory = Memory::from_rom(battery_rom(), None).unwrap();
+        memory.set_platform(Platform::GBC);
+        assert!(!memory.stop());
+        assert!(!memory.double_speed());
+        memory.write(constants::KEY1 as u16, 0x01);
+        assert!(memory.stop());
+        assert!(memory.double_speed());
+        assert!(!memory.stop());
+        assert!(memory.double_speed());
+        memory.write(constants::KEY1 as u16, 0x01);
+        assert!(memory.stop());
+        assert!(!memory.double_speed());
+    }
+
+    #[test]
+    fn key1_is_unmapped_on_dmg() {
+        let mut memory = Memory::from_rom(battery_rom(), None).unwrap();
+        memory.write(constants::KEY1 as u16, 0x01);
+        assert_eq!(memory.read(constants::KEY1 as u16), 0xFF);
+        assert!(!memory.stop());
+    }
+
+    #[test]
+    fn unusable_images_are_rejected() {
+        assert_eq!(Memory::from_rom(vec![0; 0x100], None).err(), Some(HeaderError::TooSmall(0x100)));
+        let mut rom = battery_rom();
+        rom[constants::CARTRIDGE_TYPE] = 0xFC;
+        assert_eq!(Memory::from_rom(rom, None).err(), Some(HeaderError::UnsupportedCartridge(0xFC)));
+    }
+
+    #[test]
+    fn oam_dma_leaves_only_hram_readable() {
+        let mut memory = Memory::from_rom(battery_rom(), None).unwrap();
+        for i in 0..constants::OAM_DMA_LENGTH {
+            memory.write((constants::ONBOARD_WRAM_START + i) as u16, i as u8);
+        }
+        memory.write(constants::HRAM_START as u16, 0x5A);
+        memory.write(constants::IE_REGISTER as u16, 0x1F);
+        memory.write(constants::DMA as u16, 0xC0);
+        //Nothing is blocked during the startup delay
+        for _ in 0..constants::OAM_DMA_STARTUP_CYCLES {
+            assert_eq!(memory.read(constants::ONBOARD_WRAM_START as u16 + 1), 0x01);
+            memory.tick_dma();
+        }
+        for _ in 0..constants::OAM_DMA_LENGTH as u32 * constants::OAM_DMA_CYCLES_PER_BYTE {
+            assert_eq!(memory.read(constants::HRAM_START as u16), 0x5A);
+            for addr in [0x0150, 0x4000, 0x8000, 0xA000, 0xC001, 0xE001, 0xFE00, 0xFF44, 0xFFFF].iter() {
+                assert_eq!(memory.read(*addr), 0xFF, "{:#06x}", addr);
+            }
+            memory.tick_dma();
+        }
+        assert_eq!(memory.read(constants::ONBOARD_WRAM_START as u16 + 1), 0x01);
+        assert_eq!(memory.read(constants::IE_REGISTER as u16), 0x1F);
+        for i in 0..constants::OAM_DMA_LENGTH {
+            assert_eq!(memory.read((constants::OAM_START + i) as u16), i as u8);
+        }
+    }
+
+    //Write 0x00 to every I/O register (except DMA, which would start a transfer) and read them all back
+    fn io_zeroed(platform: Platform) -> Vec<u8> {
+        let mut memory = Memory::from_rom(battery_rom(), None).unwrap();
+        memory.set_platform(platform);
+        memory.write(constants::NR52 as u16, 0x00);
+        for addr in constants::IO_REG_START..=constants::IO_REG_END {
+            if addr != constants::DMA { memory.write(addr as u16, 0x00); }
+        }
+        return (constants::IO_REG_START..=constants::IO_REG_END).map(|addr| memory.read(addr as u16)).collect();
+    }
+
+    #[test]
+    fn io_registers_read_back_with_unused_bits_set() {
+        let values = io_zeroed(Platform::GBC);
+        for (index, value) in values.iter().enumerate() {
+            let expected = match index + constants::IO_REG_START {
+                //No buttons pressed
+                constants::P1 => 0xCF,
+                //LY = LYC = 0
+                constants::STAT => 0x84,
+                //Locked without a boot ROM
+                constants::KEY0 => 0xFF,
+                //The one-block transfer started by the write has finished
+                constants::HDMA5 => 0xFF,
+                _ => IO_READ_MASKS[index],
+            };
+            assert_eq!(*value, expected, "{:#06x}", index + constants::IO_REG_START);
+        }
+    }
+
+    #[test]
+    fn cgb_registers_are_unmapped_on_dmg() {
+        let values = io_zeroed(Platform::DMG);
+        assert_eq!(values[constants::SC - constants::IO_REG_START], 0x7E);
+        for addr in [constants::KEY1, constants::VBK, constants::HDMA5, constants::BCPS, constants::BCPD,
+                     constants::OCPS, constants::OCPD, constants::SVBK].iter() {
+            assert_eq!(values[addr - constants::IO_REG_START], 0xFF, "{:#06x}", addr);
+        }
+        assert_eq!(values[constants::TAC - constants::IO_REG_START], 0xF8);
+        assert_eq!(values[constants::IF - constants::IO_REG_START], 0xE0);
+    }
+
+    //CGB memory with 0x80 bytes of numbered data at 0xC000 and HDMA1-4 set to copy it to 0x8000
+    fn vram_dma_memory() -> Memory {
+        let mut memory = Memory::from_rom(battery_rom(), None).unwrap();
+        memory.set_platform(Platform::GBC);
+        for i in 0..0x80 {
+            memory.write((constants::ONBOARD_WRAM_START + i) as u16, i as u8 + 1);
+        }
+        memory.write(constants::HDMA1 as u16, 0xC0);
+        memory.write(constants::HDMA2 as u16, 0x00);
+        memory.write(constants::HDMA3 as u16, 0x00);
+        memory.write(constants::HDMA4 as u16, 0x00);
+        return memory;
+    }
+
+    fn vram_dma_line(memory: &mut Memory) {
+        for _ in 0..constants::DOTS_PER_LINE {
+            memory.tick_ppu();
+        }
+    }
+
+    #[test]
+    fn general_purpose_vram_dma_copies_the_requested_blocks() {
+        let mut memory = vram_dma_memory();
+        memory.write(constants::HDMA5 as u16, 0x02);
+        for i in 0..0x30 {
+            assert_eq!(memory.read((constants::SWITCHABLE_VRAM_START + i) as u16), i as u8 + 1);
+        }
+        assert_eq!(memory.read((constants::SWITCHABLE_VRAM_START + 0x30) as u16), 0x00);
+        assert_eq!(memory.read(constants::HDMA5 as u16), 0xFF);
+        let mut stall = 0;
+        while memory.tick_vram_dma_stall() {
+            stall += 1;
+        }
+        assert_eq!(stall, 3 * constants::VRAM_DMA_CYCLES_PER_BLOCK);
+    }
+
+    #[test]
+    fn hblank_vram_dma_reports_the_remaining_length() {
+        let mut memory = vram_dma_memory();
+        memory.write(constants::LCDC as u16, constants::LCDC_ENABLE);
+        memory.write(constants::HDMA5 as u16, 0x82);
+        assert_eq!(memory.read(constants::HDMA5 as u16), 0x02);
+        vram_dma_line(&mut memory);
+        assert_eq!(memory.read(constants::HDMA5 as u16), 0x01);
+        vram_dma_line(&mut memory);
+        vram_dma_line(&mut memory);
+        assert_eq!(memory.read(constants::HDMA5 as u16), 0xFF);
+        assert_eq!(memory.read((constants::SWITCHABLE_VRAM_START + 0x2F) as u16), 0x30);
+    }
+
+    #[test]
+    fn clearing_bit_7_cancels_an_hblank_vram_dma() {
+        let mut memory = vram_dma_memory();
+        memory.write(constants::LCDC as u16, constants::LCDC_ENABLE);
+        memory.write(constants::HDMA5 as u16, 0x83);
+        vram_dma_line(&mut memory);
+        vram_dma_line(&mut memory);
+        memory.write(constants::HDMA5 as u16, 0x00);
+        //Inactive, with the two blocks that were left
+        assert_eq!(memory.read(constants::HDMA5 as u16), 0x81);
+        vram_dma_line(&mut memory);
+        assert_eq!(memory.read((constants::SWITCHABLE_VRAM_START + 0x1F) as u16), 0x20);
+        assert_eq!(memory.read((constants::SWITCHABLE_VRAM_START + 0x20) as u16), 0x00);
+    }
+
+    #[test]
+    fn joypad_falling_edges_set_if() {
+        let mut memory = Memory::from_rom(battery_rom(), None).unwrap();
+        memory.write(constants::P1 as u16, 0b00010000);
+        memory.set_button(Button::Up, true);
+        assert!(!memory.interrupts.get_flag(Interrupt::Joypad));
+        memory.set_button(Button::B, true);
+        assert!(memory.interrupts.get_flag(Interrupt::Joypad));
+        memory.interrupts.reset_flag(Interrupt::Joypad);
+        //Selecting the d-pad row with Up held
+        memory.write(constants::P1 as u16, 0b00100000);
+        assert!(memory.interrupts.get_flag(Interrupt::Joypad));
+    }
+}
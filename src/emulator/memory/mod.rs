@@ -1,2 +1,4 @@
 pub mod memory;
-pub mod mbc;
\ No newline at end of file
+pub mod mbc;
+pub mod header;
+pub mod gbs;
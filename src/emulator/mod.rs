@@ -1,8 +1,10 @@
 pub mod emulator;
+pub mod linked;
 pub mod constants;
 pub mod memory;
 pub mod cpu;
 pub mod ppu;
 pub mod apu;
 pub mod joypad;
-pub mod timer;
\ No newline at end of file
+pub mod timer;
+pub mod serial;
\ No newline at end of file
@@ -1,19 +1,720 @@
+/*
+Picture Processing Unit: https://gbdev.io/pandocs/Rendering.html
+
+Renders one scanline at a time into a 160x144 framebuffer of 15-bit colours (bits 0-4 red, 5-9 green, 10-14 blue).
+DMG shades are mapped to greys (or through CGB palettes in compatibility mode), CGB colours come straight from palette RAM.
+Owns the LCD registers at 0xFF40-0xFF4B (except DMA) and the CGB palettes at 0xFF68-0xFF6B, VRAM and OAM are passed in by Memory.
+
+framebuffer: colour of each pixel, row-major
+line_bg: colour index (before palette mapping) of the background/window pixels on the current line
+line_priority: CGB BG attribute bit 7 of each pixel on the current line
+cgb: true when running in CGB mode (tile attributes, colour palettes)
+compatibility: CGB hardware running a DMG game, BGP/OBP0/OBP1 pick colours from CGB palettes BG 0 / OBJ 0 / OBJ 1
+bg_palette_ram / obj_palette_ram: 8 palettes x 4 colours x 2 bytes (little-endian) each
+bcps / ocps: palette RAM index (bits 0-5) and auto-increment flag (bit 7)
+window_line: internal window line counter, only advances on lines where the window was drawn
+window_triggered: WY matched LY at the start of a line this frame, the window can only appear from then on
+last_line: LY already reads 0 but line 153 is still running, the frame wraps when it ends
+dot: position within the current 456-dot scanline
+stat_line: internal STAT interrupt line, the OR of all enabled STAT sources
+ */
+use crate::emulator::constants;
+use crate::emulator::cpu::interrupts::Interrupt;
+
+const SCREEN_WIDTH: usize = constants::SCREEN_X_DIM as usize;
+const SCREEN_HEIGHT: usize = constants::SCREEN_Y_DIM as usize;
+
+pub type Vram = [[u8; constants::EIGHT_KB]; 2];
+pub type Oam = [u8; constants::OAM_SIZE];
+
+//DMG shades 0-3 as 15-bit greys
+const DMG_SHADES: [u16; 4] = [0x7FFF, 0x56B5, 0x294A, 0x0000];
+
+/*
+Each visible line is OAM scan (80 dots) -> drawing (172 dots) -> HBlank (204 dots).
+Lines 144-153 are VBlank. The discriminant is the value exposed in STAT bits 0-1.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PpuMode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
 pub struct PPU {
-    pixels: [bool; 160*144],
-    LCDC: u8,
-    LY: u8,
-    LYC: u8,
-    STAT: u8,
+    framebuffer: Box<[u16; SCREEN_WIDTH * SCREEN_HEIGHT]>,
+    line_bg: [u8; SCREEN_WIDTH],
+    line_priority: [bool; SCREEN_WIDTH],
+    cgb: bool,
+    compatibility: bool,
+    bg_palette_ram: [u8; constants::PALETTE_RAM_SIZE],
+    obj_palette_ram: [u8; constants::PALETTE_RAM_SIZE],
+    bcps: u8,
+    ocps: u8,
+    lcdc: u8,
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    window_line: u8,
+    window_triggered: bool,
+    last_line: bool,
+    dot: u16,
+    mode: PpuMode,
+    stat_line: bool,
 }
 
 impl PPU {
     pub fn new() -> Self {
         Self {
-            pixels: [false; 160*144],
-            LCDC: 0,
-            LY: 0,
-            LYC: 0,
-            STAT: 0b10000000,
+            framebuffer: Box::new([DMG_SHADES[0]; SCREEN_WIDTH * SCREEN_HEIGHT]),
+            line_bg: [0; SCREEN_WIDTH],
+            line_priority: [false; SCREEN_WIDTH],
+            cgb: false,
+            compatibility: false,
+            bg_palette_ram: [0xFF; constants::PALETTE_RAM_SIZE],
+            obj_palette_ram: [0xFF; constants::PALETTE_RAM_SIZE],
+            bcps: 0,
+            ocps: 0,
+            lcdc: 0,
+            stat: 0b10000000,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
+            window_line: 0,
+            window_triggered: false,
+            last_line: false,
+            dot: 0,
+            mode: PpuMode::OamScan,
+            stat_line: false,
+        }
+    }
+
+    /*
+    Where the boot ROM leaves the PPU: LCD on with the background enabled, BGP = 0xFC, and the last dot of the frame
+    so STAT reads 0x85 (VBlank, LY = LYC = 0). PPU::new is the cold power-on state the boot ROM itself starts from.
+     */
+    pub fn load_post_boot_state(&mut self) {
+        self.lcdc = constants::POST_BOOT_LCDC;
+        self.bgp = constants::POST_BOOT_BGP;
+        self.ly = 0;
+        self.dot = 0;
+        self.window_line = 0;
+        self.window_triggered = false;
+        self.last_line = false;
+        self.mode = PpuMode::VBlank;
+    }
+
+    pub fn framebuffer(&self) -> &[u16] {
+        &self.framebuffer[..]
+    }
+
+    pub fn set_cgb_mode(&mut self, cgb: bool) {
+        self.cgb = cgb;
+        self.compatibility = false;
+    }
+
+    /*
+    DMG compatibility mode, entered when the CGB boot ROM finishes with a DMG game. Rendering follows DMG rules
+    (no tile attributes, DMG object priority) and the palette registers are locked, but colours still come from
+    the palettes the boot ROM left in palette RAM.
+     */
+    pub fn set_compatibility_mode(&mut self) {
+        self.cgb = false;
+        self.compatibility = true;
+    }
+
+    //Load greys into BG palette 0 and OBJ palettes 0-1, for compatibility mode without a boot ROM to choose colours
+    pub fn load_dmg_palettes(&mut self) {
+        for palette in [&mut self.bg_palette_ram[0..8], &mut self.obj_palette_ram[0..16]].iter_mut() {
+            for (index, entry) in palette.chunks_mut(2).enumerate() {
+                let color = DMG_SHADES[index % 4];
+                entry[0] = color as u8;
+                entry[1] = (color >> 8) as u8;
+            }
+        }
+    }
+
+    pub fn mode(&self) -> PpuMode {
+        self.mode
+    }
+
+    /*
+    Advance the PPU by one dot, returning any interrupts requested on this dot.
+    The scanline is drawn in one go when mode 3 ends. Line 153 switches LY to 0 after LAST_LINE_LY_RESET_DOT dots,
+    so LYC = 0 matches (and can interrupt) before line 0 starts.
+     */
+    pub fn tick(&mut self, vram: &Vram, oam: &Oam) -> Vec<Interrupt> {
+        let mut interrupts: Vec<Interrupt> = Vec::new();
+        //With the LCD off LY is held at 0 and the PPU stays in HBlank
+        if self.lcdc & constants::LCDC_ENABLE == 0 {
+            self.dot = 0;
+            self.ly = 0;
+            self.window_line = 0;
+            self.window_triggered = false;
+            self.last_line = false;
+            self.mode = PpuMode::HBlank;
+            self.stat_line = false;
+            return interrupts;
+        }
+        self.dot += 1;
+        if self.dot == constants::DOTS_PER_LINE {
+            self.dot = 0;
+            if self.last_line {
+                self.last_line = false;
+                self.window_line = 0;
+                self.window_triggered = false;
+            } else {
+                self.ly += 1;
+            }
+        } else if self.ly == constants::LINES_PER_FRAME - 1 && self.dot == constants::LAST_LINE_LY_RESET_DOT {
+            self.ly = 0;
+            self.last_line = true;
+        }
+        let mode = if self.ly as usize >= SCREEN_HEIGHT || self.last_line {
+            PpuMode::VBlank
+        } else if self.dot < constants::OAM_SCAN_END_DOT {
+            PpuMode::OamScan
+        } else if self.dot < constants::DRAWING_END_DOT {
+            PpuMode::Drawing
+        } else {
+            PpuMode::HBlank
+        };
+        if mode != self.mode {
+            match mode {
+                //The window Y condition is only checked at the start of a line
+                PpuMode::OamScan => if self.ly == self.wy { self.window_triggered = true; },
+                PpuMode::HBlank => self.render_scanline(vram, oam),
+                PpuMode::VBlank => interrupts.push(Interrupt::VerticalBlanking),
+                _ => ()
+            }
+            self.mode = mode;
+        }
+        //STAT interrupts fire on the rising edge of the combined line, so back-to-back sources "block" each other
+        let stat_line = self.stat_line_high();
+        if stat_line && !self.stat_line {
+            interrupts.push(Interrupt::LcdStat);
+        }
+        self.stat_line = stat_line;
+        return interrupts;
+    }
+
+    //Entering VBlank also raises the mode 2 (OAM) source for the first dot of line 144, as the hardware does
+    fn stat_line_high(&self) -> bool {
+        let mode_source = match self.mode {
+            PpuMode::HBlank => self.stat & constants::STAT_HBLANK_SOURCE > 0,
+            PpuMode::VBlank => {
+                let vblank_entry = self.ly as usize == SCREEN_HEIGHT && self.dot == 0;
+                self.stat & constants::STAT_VBLANK_SOURCE > 0 || (vblank_entry && self.stat & constants::STAT_OAM_SOURCE > 0)
+            },
+            PpuMode::OamScan => self.stat & constants::STAT_OAM_SOURCE > 0,
+            PpuMode::Drawing => false,
+        };
+        return mode_source || (self.ly == self.lyc && self.stat & constants::STAT_LYC_SOURCE > 0);
+    }
+
+    /*
+    CPU access to the LCD registers
+        * STAT: bit 7 reads as 1, the mode (bits 0-1) and LY=LYC flag (bit 2) are read-only
+        * LY: read-only
+        * BCPS/OCPS: bit 6 reads as 1
+        * BCPD/OCPD: inaccessible while the PPU is drawing (reads 0xFF, writes dropped). A write still advances the index
+          if auto-increment is on. All four palette registers only exist in CGB mode.
+     */
+    pub fn read_register(&self, addr: u16) -> u8 {
+        match addr as usize {
+            constants::LCDC => self.lcdc,
+            constants::STAT => {
+                let coincidence = if self.ly == self.lyc { constants::STAT_COINCIDENCE } else { 0 };
+                let mode = if self.lcdc & constants::LCDC_ENABLE > 0 { self.mode as u8 } else { 0 };
+                0b10000000 | (self.stat & 0b01111000) | coincidence | mode
+            },
+            constants::SCY => self.scy,
+            constants::SCX => self.scx,
+            constants::LY => self.ly,
+            constants::LYC => self.lyc,
+            constants::BGP => self.bgp,
+            constants::OBP0 => self.obp0,
+            constants::OBP1 => self.obp1,
+            constants::WY => self.wy,
+            constants::WX => self.wx,
+            constants::BCPS if self.cgb => 0b01000000 | self.bcps,
+            constants::BCPD if self.cgb => {
+                if self.palette_locked() { return 0xFF; }
+                self.bg_palette_ram[(self.bcps & 0x3F) as usize]
+            },
+            constants::OCPS if self.cgb => 0b01000000 | self.ocps,
+            constants::OCPD if self.cgb => {
+                if self.palette_locked() { return 0xFF; }
+                self.obj_palette_ram[(self.ocps & 0x3F) as usize]
+            },
+            _ => 0xFF
+        }
+    }
+
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        match addr as usize {
+            constants::LCDC => self.lcdc = data,
+            constants::STAT => self.stat = data & 0b01111000,
+            constants::SCY => self.scy = data,
+            constants::SCX => self.scx = data,
+            constants::LY => (),
+            constants::LYC => self.lyc = data,
+            constants::BGP => self.bgp = data,
+            constants::OBP0 => self.obp0 = data,
+            constants::OBP1 => self.obp1 = data,
+            constants::WY => self.wy = data,
+            constants::WX => self.wx = data,
+            constants::BCPS if self.cgb => self.bcps = data & 0b10111111,
+            constants::BCPD if self.cgb => {
+                if !self.palette_locked() { self.bg_palette_ram[(self.bcps & 0x3F) as usize] = data; }
+                self.bcps = PPU::increment_palette_index(self.bcps);
+            },
+            constants::OCPS if self.cgb => self.ocps = data & 0b10111111,
+            constants::OCPD if self.cgb => {
+                if !self.palette_locked() { self.obj_palette_ram[(self.ocps & 0x3F) as usize] = data; }
+                self.ocps = PPU::increment_palette_index(self.ocps);
+            },
+            _ => ()
+        }
+    }
+
+    fn palette_locked(&self) -> bool {
+        self.lcdc & constants::LCDC_ENABLE > 0 && self.mode == PpuMode::Drawing
+    }
+
+    //Index wraps within 0-63, the auto-increment flag is kept
+    fn increment_palette_index(spec: u8) -> u8 {
+        if spec & constants::PALETTE_AUTO_INCREMENT == 0 { return spec; }
+        return constants::PALETTE_AUTO_INCREMENT | ((spec + 1) & 0x3F);
+    }
+
+    fn render_scanline(&mut self, vram: &Vram, oam: &Oam) {
+        self.render_background(vram);
+        self.render_sprites(vram, oam);
+    }
+
+    /*
+    Background and window layers
+        * LCDC.0: DMG: BG/window enable, when clear both layers are blank (colour 0)
+                  CGB: BG/window master priority, when clear objects are always drawn on top
+        * LCDC.3: BG tile map, 0 = 0x9800, 1 = 0x9C00
+        * LCDC.4: tile data, 0 = 0x8800 (signed tile numbers from 0x9000), 1 = 0x8000 (unsigned)
+        * LCDC.5: window enable, the window starts on the first line where LY matched WY this frame
+        * LCDC.6: window tile map, 0 = 0x9800, 1 = 0x9C00
+    In CGB mode each tile map entry has an attribute byte at the same address in VRAM bank 1
+    (palette, tile data bank, X/Y flip, BG-over-OBJ priority).
+     */
+    fn render_background(&mut self, vram: &Vram) {
+        let row = self.ly as usize * SCREEN_WIDTH;
+        if !self.cgb && self.lcdc & constants::LCDC_BG_ENABLE == 0 {
+            let blank = self.shade_color(&self.bg_palette_ram, 0, 0);
+            for x in 0..SCREEN_WIDTH {
+                self.line_bg[x] = 0;
+                self.line_priority[x] = false;
+                self.framebuffer[row + x] = blank;
+            }
+            return;
+        }
+        let window_visible = self.lcdc & constants::LCDC_WINDOW_ENABLE > 0 && self.window_triggered && self.wx <= 166;
+        let mut window_drawn = false;
+        for x in 0..SCREEN_WIDTH {
+            let in_window = window_visible && x + 7 >= self.wx as usize;
+            let (map_base, map_x, map_y) = if in_window {
+                window_drawn = true;
+                let base = if self.lcdc & constants::LCDC_WINDOW_MAP > 0 { 0x9C00 } else { 0x9800 };
+                (base, x + 7 - self.wx as usize, self.window_line as usize)
+            } else {
+                let base = if self.lcdc & constants::LCDC_BG_MAP > 0 { 0x9C00 } else { 0x9800 };
+                (base, (x + self.scx as usize) & 0xFF, (self.ly as usize + self.scy as usize) & 0xFF)
+            };
+            let map_addr = (map_base + (map_y / 8) * 32 + map_x / 8) as u16;
+            let tile_index = PPU::vram_byte(vram, 0, map_addr);
+            let attributes = if self.cgb { PPU::vram_byte(vram, 1, map_addr) } else { 0 };
+            let bank = if attributes & constants::BG_ATTR_BANK > 0 { 1 } else { 0 };
+            let tile_x = if attributes & constants::BG_ATTR_X_FLIP > 0 { 7 - map_x % 8 } else { map_x % 8 };
+            let tile_y = if attributes & constants::BG_ATTR_Y_FLIP > 0 { 7 - map_y % 8 } else { map_y % 8 };
+            let color = self.tile_pixel(vram, bank, tile_index, tile_x, tile_y);
+            self.line_bg[x] = color;
+            self.line_priority[x] = attributes & constants::BG_ATTR_PRIORITY > 0;
+            self.framebuffer[row + x] = if self.cgb {
+                PPU::cgb_color(&self.bg_palette_ram, attributes & constants::BG_ATTR_PALETTE, color)
+            } else {
+                self.shade_color(&self.bg_palette_ram, 0, PPU::apply_palette(self.bgp, color))
+            };
+        }
+        if window_drawn {
+            self.window_line += 1;
+        }
+    }
+
+    /*
+    Objects (sprites) from OAM: https://gbdev.io/pandocs/OAM.html
+        * Up to 10 objects are selected per line, in OAM order, based on Y position only
+        * Among overlapping objects the one with the smaller X wins, ties go to the lower OAM index.
+          In CGB mode only the OAM index matters.
+        * Colour 0 is transparent, a transparent pixel lets lower-priority objects show through
+        * Attribute bit 7 (BG over OBJ) hides the object behind BG/window colours 1-3. In CGB mode the BG attribute
+          priority bit does the same, and clearing LCDC.0 overrides both.
+        * CGB mode: attribute bits 0-2 select the colour palette, bit 3 the VRAM bank of the tile data
+     */
+    fn render_sprites(&mut self, vram: &Vram, oam: &Oam) {
+        if self.lcdc & constants::LCDC_OBJ_ENABLE == 0 { return; }
+        let height: i16 = if self.lcdc & constants::LCDC_OBJ_SIZE > 0 { 16 } else { 8 };
+        let ly = self.ly as i16;
+        let mut selected: Vec<usize> = Vec::with_capacity(constants::OBJECTS_PER_LINE);
+        for index in 0..constants::OBJECT_COUNT {
+            let y = oam[index * 4] as i16 - 16;
+            if ly >= y && ly < y + height {
+                selected.push(index);
+                if selected.len() == constants::OBJECTS_PER_LINE { break; }
+            }
+        }
+        //Stable sort keeps OAM order for objects sharing an X coordinate
+        if !self.cgb {
+            selected.sort_by_key(|index| oam[index * 4 + 1]);
+        }
+
+        let row = self.ly as usize * SCREEN_WIDTH;
+        let mut claimed = [false; SCREEN_WIDTH];
+        for index in selected {
+            let y = oam[index * 4] as i16 - 16;
+            let x = oam[index * 4 + 1] as i16 - 8;
+            let mut tile = oam[index * 4 + 2];
+            let attributes = oam[index * 4 + 3];
+            if height == 16 { tile = tile & 0xFE; }
+            let mut line = ly - y;
+            if attributes & constants::OBJ_Y_FLIP > 0 { line = height - 1 - line; }
+            let tile_addr = 0x8000 + tile as u16 * 16 + line as u16 * 2;
+            let bank = if self.cgb && attributes & constants::OBJ_BANK > 0 { 1 } else { 0 };
+            let low = PPU::vram_byte(vram, bank, tile_addr);
+            let high = PPU::vram_byte(vram, bank, tile_addr + 1);
+            let (palette, dmg_palette) = if attributes & constants::OBJ_PALETTE > 0 { (1, self.obp1) } else { (0, self.obp0) };
+            for px in 0..8 {
+                let screen_x = x + px;
+                if screen_x < 0 || screen_x >= SCREEN_WIDTH as i16 { continue; }
+                let screen_x = screen_x as usize;
+                if claimed[screen_x] { continue; }
+                let column = if attributes & constants::OBJ_X_FLIP > 0 { 7 - px as usize } else { px as usize };
+                let color = PPU::pixel_color(low, high, column);
+                if color == 0 { continue; }
+                claimed[screen_x] = true;
+                if self.background_wins(screen_x, attributes) { continue; }
+                self.framebuffer[row + screen_x] = if self.cgb {
+                    PPU::cgb_color(&self.obj_palette_ram, attributes & constants::OBJ_CGB_PALETTE, color)
+                } else {
+                    self.shade_color(&self.obj_palette_ram, palette, PPU::apply_palette(dmg_palette, color))
+                };
+            }
+        }
+    }
+
+    //True if the BG/window pixel at x hides an object pixel drawn over it
+    fn background_wins(&self, x: usize, attributes: u8) -> bool {
+        if self.line_bg[x] == 0 { return false; }
+        if self.cgb && self.lcdc & constants::LCDC_BG_ENABLE == 0 { return false; }
+        return attributes & constants::OBJ_BG_PRIORITY > 0 || (self.cgb && self.line_priority[x]);
+    }
+
+    //Colour index (0-3) of one pixel of a BG/window tile, honouring the LCDC.4 addressing mode
+    fn tile_pixel(&self, vram: &Vram, bank: usize, tile_index: u8, x: usize, y: usize) -> u8 {
+        let tile_addr = if self.lcdc & constants::LCDC_TILE_DATA > 0 {
+            0x8000 + tile_index as usize * 16
+        } else {
+            (0x9000 + (tile_index as i8 as i32) * 16) as usize
+        };
+        let low = PPU::vram_byte(vram, bank, (tile_addr + y * 2) as u16);
+        let high = PPU::vram_byte(vram, bank, (tile_addr + y * 2 + 1) as u16);
+        return PPU::pixel_color(low, high, x);
+    }
+
+    fn vram_byte(vram: &Vram, bank: usize, addr: u16) -> u8 {
+        vram[bank][addr as usize - constants::SWITCHABLE_VRAM_START]
+    }
+
+    //Bit 7 of each byte is the leftmost pixel, the high byte holds the upper bit of the colour index
+    fn pixel_color(low: u8, high: u8, x: usize) -> u8 {
+        let bit = 7 - x;
+        return (((high >> bit) & 0b1) << 1) | ((low >> bit) & 0b1);
+    }
+
+    fn apply_palette(palette: u8, color: u8) -> u8 {
+        (palette >> (color * 2)) & 0b11
+    }
+
+    //Colour of a DMG shade: a grey, or entry `shade` of CGB palette `palette` in compatibility mode
+    fn shade_color(&self, palette_ram: &[u8; constants::PALETTE_RAM_SIZE], palette: u8, shade: u8) -> u16 {
+        if self.compatibility { return PPU::cgb_color(palette_ram, palette, shade); }
+        return DMG_SHADES[shade as usize];
+    }
+
+    //15-bit colour of entry `color` in CGB palette `palette`
+    fn cgb_color(palette_ram: &[u8; constants::PALETTE_RAM_SIZE], palette: u8, color: u8) -> u16 {
+        let index = palette as usize * 8 + color as usize * 2;
+        return ((palette_ram[index + 1] as u16) << 8 | palette_ram[index] as u16) & 0x7FFF;
+    }
+}
+
+/*
+Convert a 15-bit framebuffer colour to 24-bit RGB.
+Without correction each 5-bit channel is scaled to the full 0-255 range. With correction the channels are mixed and
+compressed the way the CGB's LCD displays them, so games tuned for the real screen don't look oversaturated.
+ */
+pub fn to_rgb(color: u16, color_correction: bool) -> [u8; 3] {
+    let r = (color & 0x1F) as u32;
+    let g = ((color >> 5) & 0x1F) as u32;
+    let b = ((color >> 10) & 0x1F) as u32;
+    if !color_correction {
+        return [((r << 3) | (r >> 2)) as u8, ((g << 3) | (g >> 2)) as u8, ((b << 3) | (b >> 2)) as u8];
+    }
+    let corrected_r = std::cmp::min(960, r * 26 + g * 4 + b * 2) >> 2;
+    let corrected_g = std::cmp::min(960, g * 24 + b * 8) >> 2;
+    let corrected_b = std::cmp::min(960, r * 6 + g * 4 + b * 22) >> 2;
+    return [corrected_r as u8, corrected_g as u8, corrected_b as u8];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Tiles 1-3 are solid colour 1-3, tile 4 is colour 1 on its left half and transparent on the right
+    fn vram() -> Box<Vram> {
+        let mut vram = Box::new([[0; constants::EIGHT_KB]; 2]);
+        for row in 0..8 {
+            vram[0][16 + row * 2] = 0xFF;
+            vram[0][32 + row * 2 + 1] = 0xFF;
+            vram[0][48 + row * 2] = 0xFF;
+            vram[0][48 + row * 2 + 1] = 0xFF;
+            vram[0][64 + row * 2] = 0xF0;
+        }
+        return vram;
+    }
+
+    //LCD switched on from the off state, with identity palettes so each colour index maps to its own shade
+    fn lcd_on(lcdc: u8, vram: &Vram, oam: &Oam) -> PPU {
+        let mut ppu = PPU::new();
+        ppu.tick(vram, oam);
+        ppu.write_register(constants::BGP as u16, 0xE4);
+        ppu.write_register(constants::OBP0 as u16, 0xE4);
+        ppu.write_register(constants::OBP1 as u16, 0xE4);
+        ppu.write_register(constants::LCDC as u16, constants::LCDC_ENABLE | lcdc);
+        return ppu;
+    }
+
+    fn run_lines(ppu: &mut PPU, vram: &Vram, oam: &Oam, lines: u32) {
+        for _ in 0..lines * constants::DOTS_PER_LINE as u32 {
+            ppu.tick(vram, oam);
+        }
+    }
+
+    //LY and dot of every STAT interrupt over one whole frame (after a frame to settle), in line order
+    fn stat_interrupts(stat: u8, lyc: u8) -> Vec<(u8, u16)> {
+        let vram = vram();
+        let oam = [0; constants::OAM_SIZE];
+        let mut ppu = lcd_on(0, &vram, &oam);
+        ppu.write_register(constants::STAT as u16, stat);
+        ppu.write_register(constants::LYC as u16, lyc);
+        run_lines(&mut ppu, &vram, &oam, constants::LINES_PER_FRAME as u32);
+        let mut interrupts = Vec::new();
+        for _ in 0..constants::DOTS_PER_FRAME {
+            for interrupt in ppu.tick(&vram, &oam) {
+                match interrupt {
+                    Interrupt::LcdStat => interrupts.push((ppu.read_register(constants::LY as u16), ppu.dot)),
+                    _ => ()
+                }
+            }
+        }
+        interrupts.sort();
+        return interrupts;
+    }
+
+    fn pixel(ppu: &PPU, x: usize, y: usize) -> u16 {
+        ppu.framebuffer()[y * SCREEN_WIDTH + x]
+    }
+
+    fn set_object(oam: &mut Oam, index: usize, y: u8, x: u8, tile: u8, attributes: u8) {
+        oam[index * 4..index * 4 + 4].copy_from_slice(&[y, x, tile, attributes]);
+    }
+
+    #[test]
+    fn mode_sources_block_each_other() {
+        let lines: Vec<(u8, u16)> = (0..144).map(|ly| (ly, constants::DRAWING_END_DOT)).collect();
+        assert_eq!(stat_interrupts(constants::STAT_HBLANK_SOURCE, 0xFF), lines);
+        //HBlank holds the line high into the next OAM scan and into VBlank, only line 0's OAM scan is a new edge
+        let both = stat_interrupts(constants::STAT_HBLANK_SOURCE | constants::STAT_OAM_SOURCE, 0xFF);
+        assert_eq!(both.len(), 145);
+        assert_eq!(both[0], (0, 0));
+        assert_eq!(stat_interrupts(constants::STAT_HBLANK_SOURCE | constants::STAT_VBLANK_SOURCE, 0xFF), lines);
+    }
+
+    #[test]
+    fn vblank_entry_raises_the_oam_source() {
+        let mut lines: Vec<(u8, u16)> = (0..144).map(|ly| (ly, 0)).collect();
+        lines.push((144, 0));
+        assert_eq!(stat_interrupts(constants::STAT_OAM_SOURCE, 0xFF), lines);
+        assert_eq!(stat_interrupts(constants::STAT_VBLANK_SOURCE, 0xFF), vec![(144, 0)]);
+    }
+
+    #[test]
+    fn lyc_interrupts_once_per_match() {
+        assert_eq!(stat_interrupts(constants::STAT_LYC_SOURCE, 10), vec![(10, 0)]);
+        assert_eq!(stat_interrupts(constants::STAT_LYC_SOURCE, 153), vec![(153, 0)]);
+        //Line 153 already reads LY = 0
+        assert_eq!(stat_interrupts(constants::STAT_LYC_SOURCE, 0), vec![(0, constants::LAST_LINE_LY_RESET_DOT)]);
+        //Still high from the OAM scan of line 10, and keeps the line high into the OAM scan of line 11
+        let both = stat_interrupts(constants::STAT_LYC_SOURCE | constants::STAT_OAM_SOURCE, 10);
+        assert_eq!(both.len(), 144);
+        assert!(!both.contains(&(11, 0)));
+    }
+
+    #[test]
+    fn ly_reads_0_for_most_of_line_153() {
+        let vram = vram();
+        let oam = [0; constants::OAM_SIZE];
+        let mut ppu = lcd_on(0, &vram, &oam);
+        run_lines(&mut ppu, &vram, &oam, 153);
+        for _ in 0..constants::LAST_LINE_LY_RESET_DOT {
+            assert_eq!(ppu.read_register(constants::LY as u16), 153);
+            ppu.tick(&vram, &oam);
         }
+        assert_eq!(ppu.read_register(constants::LY as u16), 0);
+        ppu.write_register(constants::LYC as u16, 0);
+        assert_eq!(ppu.read_register(constants::STAT as u16), 0x80 | constants::STAT_COINCIDENCE | PpuMode::VBlank as u8);
+        run_lines(&mut ppu, &vram, &oam, 1);
+        assert_eq!(ppu.read_register(constants::LY as u16), 0);
+        assert_eq!(ppu.mode(), PpuMode::OamScan);
+        run_lines(&mut ppu, &vram, &oam, 1);
+        assert_eq!(ppu.read_register(constants::LY as u16), 1);
+    }
+
+    //BG map 0x9800 is blank, the window map 0x9C00 is tile 3 on its first tile row and tile 1 on the second
+    fn window_vram() -> Box<Vram> {
+        let mut vram = vram();
+        for x in 0..32 {
+            vram[0][0x1C00 + x] = 3;
+            vram[0][0x1C00 + 32 + x] = 1;
+        }
+        return vram;
+    }
+
+    const WINDOW_LCDC: u8 = constants::LCDC_BG_ENABLE | constants::LCDC_TILE_DATA | constants::LCDC_WINDOW_ENABLE
+        | constants::LCDC_WINDOW_MAP;
+
+    #[test]
+    fn window_stays_on_once_wy_has_matched() {
+        let vram = window_vram();
+        let oam = [0; constants::OAM_SIZE];
+        let mut ppu = lcd_on(WINDOW_LCDC, &vram, &oam);
+        ppu.write_register(constants::WY as u16, 10);
+        ppu.write_register(constants::WX as u16, 7);
+        run_lines(&mut ppu, &vram, &oam, 20);
+        ppu.write_register(constants::WY as u16, 100);
+        run_lines(&mut ppu, &vram, &oam, 124);
+        assert_eq!(pixel(&ppu, 0, 9), DMG_SHADES[0]);
+        assert_eq!(pixel(&ppu, 0, 10), DMG_SHADES[3]);
+        assert_eq!(pixel(&ppu, 0, 25), DMG_SHADES[1]);
+
+        //A WY the frame has already passed never matches
+        run_lines(&mut ppu, &vram, &oam, 10 + 10);
+        ppu.write_register(constants::WY as u16, 5);
+        run_lines(&mut ppu, &vram, &oam, 20);
+        assert_eq!(pixel(&ppu, 0, 10), DMG_SHADES[0]);
+        assert_eq!(pixel(&ppu, 0, 25), DMG_SHADES[0]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn window_line_counter_pauses_while_the_window_is_off() {
+        let vram = window_vram();
+        let oam = [0; constants::OAM_SIZE];
+        let mut ppu = lcd_on(WINDOW_LCDC, &vram, &oam);
+        ppu.write_register(constants::WY as u16, 10);
+        ppu.write_register(constants::WX as u16, 7);
+        run_lines(&mut ppu, &vram, &oam, 12);
+        ppu.write_register(constants::LCDC as u16, constants::LCDC_ENABLE | (WINDOW_LCDC & !constants::LCDC_WINDOW_ENABLE));
+        run_lines(&mut ppu, &vram, &oam, 4);
+        ppu.write_register(constants::LCDC as u16, constants::LCDC_ENABLE | WINDOW_LCDC);
+        run_lines(&mut ppu, &vram, &oam, 10);
+        assert_eq!(pixel(&ppu, 0, 11), DMG_SHADES[3]);
+        assert_eq!(pixel(&ppu, 0, 12), DMG_SHADES[0]);
+        assert_eq!(pixel(&ppu, 0, 16), DMG_SHADES[3]);
+        //Window lines 0-1 on lines 10-11, 2-7 on lines 16-21
+        assert_eq!(pixel(&ppu, 0, 21), DMG_SHADES[3]);
+        assert_eq!(pixel(&ppu, 0, 22), DMG_SHADES[1]);
+    }
+
+    const OBJ_LCDC: u8 = constants::LCDC_BG_ENABLE | constants::LCDC_OBJ_ENABLE | constants::LCDC_TILE_DATA;
+
+    #[test]
+    fn smaller_x_wins_then_lower_oam_index() {
+        let vram = vram();
+        let mut oam = [0; constants::OAM_SIZE];
+        set_object(&mut oam, 0, 16, 20, 1, 0);
+        set_object(&mut oam, 1, 16, 16, 2, 0);
+        set_object(&mut oam, 2, 16, 40, 1, 0);
+        set_object(&mut oam, 3, 16, 40, 3, 0);
+        let mut ppu = lcd_on(OBJ_LCDC, &vram, &oam);
+        run_lines(&mut ppu, &vram, &oam, 1);
+        assert_eq!(pixel(&ppu, 8, 0), DMG_SHADES[2]);
+        assert_eq!(pixel(&ppu, 15, 0), DMG_SHADES[2]);
+        assert_eq!(pixel(&ppu, 16, 0), DMG_SHADES[1]);
+        assert_eq!(pixel(&ppu, 32, 0), DMG_SHADES[1]);
+    }
+
+    #[test]
+    fn transparent_pixels_show_the_object_below() {
+        let vram = vram();
+        let mut oam = [0; constants::OAM_SIZE];
+        set_object(&mut oam, 0, 16, 8, 4, 0);
+        set_object(&mut oam, 1, 16, 8, 2, 0);
+        let mut ppu = lcd_on(OBJ_LCDC, &vram, &oam);
+        run_lines(&mut ppu, &vram, &oam, 1);
+        assert_eq!(pixel(&ppu, 3, 0), DMG_SHADES[1]);
+        assert_eq!(pixel(&ppu, 4, 0), DMG_SHADES[2]);
+    }
+
+    #[test]
+    fn only_the_first_10_objects_in_oam_are_drawn() {
+        let vram = vram();
+        let mut oam = [0; constants::OAM_SIZE];
+        //An object off the line doesn't count towards the limit
+        set_object(&mut oam, 0, 40, 8, 1, 0);
+        for index in 1..11 {
+            set_object(&mut oam, index, 16, 16 + index as u8 * 8, 1, 0);
+        }
+        //The leftmost object comes last in OAM, so it is the one dropped
+        set_object(&mut oam, 11, 16, 8, 1, 0);
+        let mut ppu = lcd_on(OBJ_LCDC, &vram, &oam);
+        run_lines(&mut ppu, &vram, &oam, 1);
+        assert_eq!(pixel(&ppu, 0, 0), DMG_SHADES[0]);
+        for index in 1..11 {
+            assert_eq!(pixel(&ppu, 8 + index * 8, 0), DMG_SHADES[1], "object {}", index);
+        }
+    }
+
+    #[test]
+    fn bg_priority_hides_objects_behind_bg_colours_1_to_3() {
+        let mut vram = vram();
+        vram[0][0x1800] = 3;
+        let mut oam = [0; constants::OAM_SIZE];
+        set_object(&mut oam, 0, 16, 12, 1, constants::OBJ_BG_PRIORITY);
+        set_object(&mut oam, 1, 16, 28, 2, 0);
+        let mut ppu = lcd_on(OBJ_LCDC, &vram, &oam);
+        run_lines(&mut ppu, &vram, &oam, 1);
+        assert_eq!(pixel(&ppu, 4, 0), DMG_SHADES[3]);
+        assert_eq!(pixel(&ppu, 8, 0), DMG_SHADES[1]);
+        assert_eq!(pixel(&ppu, 20, 0), DMG_SHADES[2]);
+    }
+}
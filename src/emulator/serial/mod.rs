@@ -0,0 +1,2 @@
+pub mod serial;
+pub mod tcp;
\ No newline at end of file
@@ -0,0 +1,272 @@
+/*
+Serial port: https://gbdev.io/pandocs/Serial_Data_Transfer_(Link_Cable).html
+
+SB holds the byte being shifted out, the peer's byte is shifted in as it goes. SC bit 7 starts a transfer and stays
+set until it completes, bit 0 selects the internal clock (this side drives the transfer) or the external clock
+(the peer drives it), bit 1 selects the CGB fast clock.
+
+Transfers are exchanged a whole byte at a time:
+    * internal clock: after 8 bit periods the byte is handed to the link cable (take_transfer) and the transfer
+      finishes once the peer's byte comes back (complete). With nothing connected 0xFF is shifted in.
+    * external clock: the transfer finishes when the peer's byte arrives (receive), this side's byte goes back.
+
+data: SB
+control: SC, only the bits that exist on the current hardware
+clock: clocks into the current bit, internal clock only
+bits: bits shifted so far, 8 means the transfer is waiting for the peer
+outgoing: byte waiting to be picked up by the link cable
+connected: a link cable is attached
+fast_clock_used: the game has started a fast clock transfer, link cables sync more often from then on
+ */
+use crate::emulator::constants;
+
+pub struct Serial {
+    data: u8,
+    control: u8,
+    cgb_mode: bool,
+    clock: u32,
+    bits: u8,
+    outgoing: Option<u8>,
+    connected: bool,
+    fast_clock_used: bool,
+}
+
+impl Serial {
+    pub fn new() -> Self {
+        Self {
+            data: 0,
+            control: 0,
+            cgb_mode: false,
+            clock: 0,
+            bits: 0,
+            outgoing: None,
+            connected: false,
+            fast_clock_used: false,
+        }
+    }
+
+    pub fn set_cgb_mode(&mut self, cgb_mode: bool) {
+        self.cgb_mode = cgb_mode;
+    }
+
+    //Plugging the cable out finishes a transfer that was waiting for the peer. Returns true if that raises the interrupt.
+    pub fn set_connected(&mut self, connected: bool) -> bool {
+        self.connected = connected;
+        if connected || self.bits < 8 { return false; }
+        return self.complete(0xFF);
+    }
+
+    fn active(&self) -> bool {
+        self.control & constants::SC_TRANSFER > 0
+    }
+
+    fn internal_clock(&self) -> bool {
+        self.control & constants::SC_INTERNAL_CLOCK > 0
+    }
+
+    fn clocks_per_bit(&self) -> u32 {
+        if self.control & constants::SC_FAST_CLOCK > 0 { constants::SERIAL_FAST_CLOCKS_PER_BIT } else { constants::SERIAL_CLOCKS_PER_BIT }
+    }
+
+    //CPU clocks until a running internally clocked transfer has shifted its last bit
+    pub fn clocks_remaining(&self) -> Option<u32> {
+        if !self.active() || !self.internal_clock() || self.bits == 8 { return None; }
+        return Some((8 - self.bits) as u32 * self.clocks_per_bit() - self.clock);
+    }
+
+    pub fn fast_clock_used(&self) -> bool {
+        self.fast_clock_used
+    }
+
+    /*
+    Advance an internally clocked transfer by one CPU clock (twice as fast in double-speed mode).
+    Returns true if the transfer completed, requesting the serial interrupt.
+     */
+    pub fn tick(&mut self) -> bool {
+        if !self.active() || !self.internal_clock() || self.bits == 8 { return false; }
+        self.clock += 1;
+        if self.clock < self.clocks_per_bit() { return false; }
+        self.clock = 0;
+        self.bits += 1;
+        if self.bits < 8 { return false; }
+        if self.connected {
+            self.outgoing = Some(self.data);
+            return false;
+        }
+        //Nothing drives the data line, so it reads high
+        return self.complete(0xFF);
+    }
+
+    //Byte sent by an internally clocked transfer that has finished shifting, if one is waiting for the cable
+    pub fn take_transfer(&mut self) -> Option<u8> {
+        self.outgoing.take()
+    }
+
+    //The peer's byte for the transfer started with take_transfer. Returns true if a transfer completed.
+    pub fn complete(&mut self, data: u8) -> bool {
+        if !self.active() || !self.internal_clock() || self.bits < 8 { return false; }
+        self.data = data;
+        self.finish();
+        return true;
+    }
+
+    /*
+    The peer clocked a byte in. If an externally clocked transfer is waiting it completes (the caller raises the
+    interrupt) and the byte that was in SB is returned. Otherwise the data line stays high and None is returned.
+     */
+    pub fn receive(&mut self, data: u8) -> Option<u8> {
+        if !self.active() || self.internal_clock() { return None; }
+        let reply = self.data;
+        self.data = data;
+        self.finish();
+        return Some(reply);
+    }
+
+    fn finish(&mut self) {
+        self.control = self.control & !constants::SC_TRANSFER;
+        self.clock = 0;
+        self.bits = 0;
+        self.outgoing = None;
+    }
+
+    pub fn read_register(&self, addr: u16) -> u8 {
+        match addr as usize {
+            constants::SB => self.data,
+            constants::SC => self.control | if self.cgb_mode { 0b01111100 } else { 0b01111110 },
+            _ => 0xFF
+        }
+    }
+
+    //Writing SC restarts the transfer from its first bit, clearing bit 7 cancels it
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        match addr as usize {
+            constants::SB => self.data = data,
+            constants::SC => {
+                let mask = if self.cgb_mode { 0b10000011 } else { 0b10000001 };
+                self.control = data & mask;
+                if self.control & constants::SC_FAST_CLOCK > 0 { self.fast_clock_used = true; }
+                self.clock = 0;
+                self.bits = 0;
+                self.outgoing = None;
+            },
+            _ => ()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(serial: &mut Serial, clocks: u32) -> bool {
+        let mut completed = false;
+        for _ in 0..clocks {
+            completed = serial.tick() || completed;
+        }
+        return completed;
+    }
+
+    //Internal clock, transfer running, SB = data
+    fn start(serial: &mut Serial, data: u8, control: u8) {
+        serial.write_register(constants::SB as u16, data);
+        serial.write_register(constants::SC as u16, control);
+    }
+
+    #[test]
+    fn internal_clock_shifts_a_bit_every_512_clocks() {
+        let mut serial = Serial::new();
+        start(&mut serial, 0x42, 0x81);
+        assert_eq!(serial.clocks_remaining(), Some(8 * constants::SERIAL_CLOCKS_PER_BIT));
+        assert!(!run(&mut serial, constants::SERIAL_CLOCKS_PER_BIT));
+        assert_eq!(serial.clocks_remaining(), Some(7 * constants::SERIAL_CLOCKS_PER_BIT));
+        assert!(!run(&mut serial, 7 * constants::SERIAL_CLOCKS_PER_BIT - 1));
+        assert_eq!(serial.read_register(constants::SC as u16) & constants::SC_TRANSFER, constants::SC_TRANSFER);
+        //With no cable attached the data line reads high
+        assert!(serial.tick());
+        assert_eq!(serial.read_register(constants::SB as u16), 0xFF);
+        assert_eq!(serial.read_register(constants::SC as u16) & constants::SC_TRANSFER, 0);
+        assert_eq!(serial.clocks_remaining(), None);
+    }
+
+    #[test]
+    fn internal_clock_waits_for_the_peer_when_connected() {
+        let mut serial = Serial::new();
+        serial.set_connected(true);
+        start(&mut serial, 0x42, 0x81);
+        assert!(!run(&mut serial, 8 * constants::SERIAL_CLOCKS_PER_BIT));
+        assert_eq!(serial.clocks_remaining(), None);
+        assert_eq!(serial.take_transfer(), Some(0x42));
+        assert_eq!(serial.take_transfer(), None);
+        //Still running until the peer's byte arrives
+        assert!(!run(&mut serial, 10000));
+        assert!(serial.complete(0x99));
+        assert_eq!(serial.read_register(constants::SB as u16), 0x99);
+        assert_eq!(serial.read_register(constants::SC as u16) & constants::SC_TRANSFER, 0);
+        assert!(!serial.complete(0x11));
+    }
+
+    #[test]
+    fn unplugging_finishes_a_transfer_waiting_for_the_peer() {
+        let mut serial = Serial::new();
+        serial.set_connected(true);
+        start(&mut serial, 0x42, 0x81);
+        run(&mut serial, 8 * constants::SERIAL_CLOCKS_PER_BIT);
+        assert!(serial.set_connected(false));
+        assert_eq!(serial.read_register(constants::SB as u16), 0xFF);
+        assert!(!serial.set_connected(false));
+    }
+
+    #[test]
+    fn external_clock_only_moves_when_the_peer_sends() {
+        let mut serial = Serial::new();
+        serial.set_connected(true);
+        start(&mut serial, 0x42, 0x80);
+        assert!(!run(&mut serial, 10000));
+        assert_eq!(serial.clocks_remaining(), None);
+        assert_eq!(serial.take_transfer(), None);
+        assert_eq!(serial.receive(0x99), Some(0x42));
+        assert_eq!(serial.read_register(constants::SB as u16), 0x99);
+        assert_eq!(serial.read_register(constants::SC as u16) & constants::SC_TRANSFER, 0);
+        //Nothing is waiting any more, the peer's byte is not taken
+        assert_eq!(serial.receive(0x11), None);
+        assert_eq!(serial.read_register(constants::SB as u16), 0x99);
+    }
+
+    #[test]
+    fn internal_clock_ignores_bytes_from_the_peer() {
+        let mut serial = Serial::new();
+        start(&mut serial, 0x42, 0x81);
+        assert_eq!(serial.receive(0x99), None);
+        assert_eq!(serial.read_register(constants::SB as u16), 0x42);
+    }
+
+    #[test]
+    fn fast_clock_only_exists_on_cgb() {
+        let mut serial = Serial::new();
+        start(&mut serial, 0x42, 0x83);
+        assert_eq!(serial.read_register(constants::SC as u16), 0xFF);
+        assert_eq!(serial.clocks_remaining(), Some(8 * constants::SERIAL_CLOCKS_PER_BIT));
+        assert!(!serial.fast_clock_used());
+
+        serial.set_cgb_mode(true);
+        start(&mut serial, 0x42, 0x83);
+        assert_eq!(serial.read_register(constants::SC as u16), 0xFF);
+        assert_eq!(serial.clocks_remaining(), Some(8 * constants::SERIAL_FAST_CLOCKS_PER_BIT));
+        assert!(serial.fast_clock_used());
+        assert!(run(&mut serial, 8 * constants::SERIAL_FAST_CLOCKS_PER_BIT));
+        assert_eq!(serial.read_register(constants::SC as u16), 0x7F);
+    }
+
+    #[test]
+    fn writing_sc_restarts_or_cancels_the_transfer() {
+        let mut serial = Serial::new();
+        start(&mut serial, 0x42, 0x81);
+        run(&mut serial, 3 * constants::SERIAL_CLOCKS_PER_BIT);
+        serial.write_register(constants::SC as u16, 0x81);
+        assert_eq!(serial.clocks_remaining(), Some(8 * constants::SERIAL_CLOCKS_PER_BIT));
+        serial.write_register(constants::SC as u16, 0x01);
+        assert!(!run(&mut serial, 8 * constants::SERIAL_CLOCKS_PER_BIT));
+        assert_eq!(serial.read_register(constants::SB as u16), 0x42);
+        assert_eq!(serial.read_register(constants::SC as u16), 0x7F);
+    }
+}
@@ -0,0 +1,216 @@
+/*
+Link cable over TCP, for connecting two instances (e.g. on localhost).
+
+Both sides run in lockstep windows and meet at the end of every window, so neither emulator gets ahead of the
+other. At each sync both sides propose how long the next window can be and the shorter proposal is used:
+    * a side with an internally clocked transfer running proposes the ticks until its last bit is shifted, so the
+      window ends on exactly the tick the transfer finishes and the byte is exchanged on time
+    * otherwise LINK_SYNC_TICKS, which is shorter than any 8192 Hz transfer, so a transfer started during a window
+      is always announced before it can finish. Fast clock transfers can finish within a single long window, so once
+      a game has started one LINK_FAST_SYNC_TICKS is proposed instead (only that first one may finish late).
+Each sync:
+    1. both sides send SYNC with the byte of a transfer that finished during the window (if any) and their proposal
+    2. both sides read the peer's SYNC
+    3. only the peer sent a byte: it is clocked into our serial port and our byte goes back as REPLY
+       only we sent a byte: wait for the peer's REPLY and complete our transfer with it
+       both sent a byte: both drove the clock, each side completes with the other's byte
+A peer that doesn't answer within LINK_TIMEOUT_MILLIS (closed, or not running) counts as disconnected.
+
+Messages:
+    HELLO version - sent by both sides on connect, the connection is refused unless the versions match
+    SYNC flag data window(u32 LE) - flag 1 if data holds a transfer byte, 0 if not
+    REPLY data
+ */
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::emulator::constants;
+use crate::emulator::memory::memory::Memory;
+
+const MSG_HELLO: u8 = 0x01;
+const MSG_SYNC: u8 = 0x02;
+const MSG_REPLY: u8 = 0x03;
+
+pub struct TcpLink {
+    stream: TcpStream,
+}
+
+impl TcpLink {
+    //Wait for the other instance to connect
+    pub fn host<A: ToSocketAddrs>(addr: A) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let (stream, _) = listener.accept()?;
+        return TcpLink::from_stream(stream);
+    }
+
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> std::io::Result<Self> {
+        return TcpLink::from_stream(TcpStream::connect(addr)?);
+    }
+
+    //Handshake on an established connection, e.g. one accepted from a non-blocking listener
+    pub fn from_stream(stream: TcpStream) -> std::io::Result<Self> {
+        let timeout = Some(Duration::from_millis(constants::LINK_TIMEOUT_MILLIS));
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(timeout)?;
+        stream.set_write_timeout(timeout)?;
+        //Every sync is a few bytes each way, don't let them wait to be batched
+        stream.set_nodelay(true)?;
+        let mut link = Self { stream };
+        link.stream.write_all(&[MSG_HELLO, constants::LINK_PROTOCOL_VERSION])?;
+        let hello = link.read_message(MSG_HELLO, 1)?;
+        if hello[0] != constants::LINK_PROTOCOL_VERSION {
+            return Err(Error::new(ErrorKind::InvalidData, "Link cable peer uses a different protocol version"));
+        }
+        return Ok(link);
+    }
+
+    /*
+    Called by both sides at the end of every window. Blocks until the peer reaches the same point (or times out)
+    and returns the length of the next window in ticks.
+     */
+    pub fn sync(&mut self, memory: &mut Memory) -> std::io::Result<u32> {
+        let outgoing = memory.serial_transfer();
+        let window = TcpLink::proposed_window(memory);
+        let (flag, data) = match outgoing {
+            Some(x) => (1, x),
+            None => (0, 0),
+        };
+        let window_bytes = window.to_le_bytes();
+        self.stream.write_all(&[MSG_SYNC, flag, data, window_bytes[0], window_bytes[1], window_bytes[2], window_bytes[3]])?;
+        let sync = self.read_message(MSG_SYNC, 6)?;
+        let incoming = if sync[0] == 1 { Some(sync[1]) } else { None };
+        let peer_window = u32::from_le_bytes([sync[2], sync[3], sync[4], sync[5]]);
+        match (outgoing, incoming) {
+            (Some(_), Some(x)) => memory.serial_complete(x),
+            (None, Some(x)) => {
+                let reply = memory.serial_receive(x);
+                self.stream.write_all(&[MSG_REPLY, reply])?;
+            },
+            (Some(_), None) => {
+                let reply = self.read_message(MSG_REPLY, 1)?;
+                memory.serial_complete(reply[0]);
+            },
+            (None, None) => ()
+        }
+        return Ok(std::cmp::max(1, std::cmp::min(window, peer_window)));
+    }
+
+    //A transfer handed over in this sync has finished shifting, so only one still running shortens the window
+    fn proposed_window(memory: &Memory) -> u32 {
+        let idle = if memory.serial.fast_clock_used() { constants::LINK_FAST_SYNC_TICKS } else { constants::LINK_SYNC_TICKS };
+        match memory.serial_ticks_remaining() {
+            Some(x) => std::cmp::max(1, std::cmp::min(x, idle)),
+            None => idle,
+        }
+    }
+
+    fn read_message(&mut self, tag: u8, length: usize) -> std::io::Result<Vec<u8>> {
+        let mut received = [0u8; 1];
+        self.stream.read_exact(&mut received)?;
+        if received[0] != tag {
+            return Err(Error::new(ErrorKind::InvalidData, format!("Unexpected link cable message {:#04x}", received[0])));
+        }
+        let mut payload = vec![0; length];
+        self.stream.read_exact(&mut payload)?;
+        return Ok(payload);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use std::sync::mpsc;
+    use std::thread::JoinHandle;
+    use std::time::Instant;
+    use crate::emulator::cpu::interrupts::Interrupt;
+
+    fn memory() -> Memory {
+        let mut memory = Memory::from_rom(vec![0; 0x8000], None).unwrap();
+        memory.set_serial_connected(true);
+        return memory;
+    }
+
+    //Loopback listener on a free port, the peer runs `peer` on its end of the link in its own thread
+    fn listen<T, F>(peer: F) -> (SocketAddr, JoinHandle<T>)
+        where T: Send + 'static, F: FnOnce(TcpLink) -> T + Send + 'static {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            return peer(TcpLink::from_stream(stream).unwrap());
+        });
+        return (addr, handle);
+    }
+
+    #[test]
+    fn stalled_peer_times_out() {
+        let (done_sender, done) = mpsc::channel::<()>();
+        //Connected, but never reaches a sync
+        let (addr, peer) = listen(move |link| {
+            done.recv().unwrap();
+            drop(link);
+        });
+        let mut link = TcpLink::connect(addr).unwrap();
+        let mut memory = memory();
+        let started = Instant::now();
+        let error = link.sync(&mut memory).unwrap_err();
+        assert!(started.elapsed() >= Duration::from_millis(constants::LINK_TIMEOUT_MILLIS - 100));
+        assert!(error.kind() == ErrorKind::WouldBlock || error.kind() == ErrorKind::TimedOut, "{:?}", error);
+        done_sender.send(()).unwrap();
+        peer.join().unwrap();
+    }
+
+    #[test]
+    fn mismatched_protocol_version_is_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let peer = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(&[MSG_HELLO, constants::LINK_PROTOCOL_VERSION + 1]).unwrap();
+            let mut hello = [0u8; 2];
+            stream.read_exact(&mut hello).unwrap();
+        });
+        let error = TcpLink::connect(addr).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        peer.join().unwrap();
+    }
+
+    #[test]
+    fn windows_end_on_the_tick_a_transfer_finishes() {
+        //The peer waits for a byte on the external clock and syncs twice
+        let (addr, peer) = listen(|mut link| {
+            let mut memory = memory();
+            memory.write(constants::SB as u16, 0x99);
+            memory.write(constants::SC as u16, 0x80);
+            let first = link.sync(&mut memory).unwrap();
+            let second = link.sync(&mut memory).unwrap();
+            return (first, second, memory.read(constants::SB as u16), memory.interrupts.get_flag(Interrupt::Serial));
+        });
+        let mut link = TcpLink::connect(addr).unwrap();
+        let mut memory = memory();
+        memory.write(constants::SB as u16, 0x42);
+        memory.write(constants::SC as u16, 0x81);
+        //Part way through the last bit period, well inside a normal window
+        let elapsed = 8 * constants::SERIAL_CLOCKS_PER_BIT - 300;
+        for _ in 0..elapsed {
+            memory.serial.tick();
+        }
+
+        let window = link.sync(&mut memory).unwrap();
+        assert_eq!(window, 300);
+        for _ in 0..window {
+            memory.serial.tick();
+        }
+        //The byte is exchanged at the very next sync, which goes back to the idle window
+        assert_eq!(link.sync(&mut memory).unwrap(), constants::LINK_SYNC_TICKS);
+        assert_eq!(memory.read(constants::SB as u16), 0x99);
+        assert!(memory.interrupts.get_flag(Interrupt::Serial));
+
+        let (first, second, peer_data, peer_interrupt) = peer.join().unwrap();
+        assert_eq!((first, second), (300, constants::LINK_SYNC_TICKS));
+        assert_eq!(peer_data, 0x42);
+        assert!(peer_interrupt);
+    }
+}
@@ -30,6 +30,16 @@ impl Timer {
         }
     }
 
+    //Divider starts from 0 at power on, the DMG_DIV/GBC_DIV values are what is left after the boot ROM runs
+    pub fn power_on() -> Self {
+        Self {
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+        }
+    }
+
     /* Returns true if TIMA overflowed*/
     pub fn tick(&mut self) -> bool {
         let freq_bit = match self.tac & 0b11 {
@@ -68,4 +78,28 @@ impl Timer {
     pub fn div(&self) -> u8 {
         return (self.counter >> 8) as u8;
     }
+
+    /*
+    CPU access to DIV/TIMA/TMA/TAC. Writing any value to DIV resets the whole internal counter,
+    the upper 5 bits of TAC are unused and read as 1.
+     */
+    pub fn read_register(&self, addr: u16) -> u8 {
+        match addr as usize {
+            constants::DIV => self.div(),
+            constants::TIMA => self.tima,
+            constants::TMA => self.tma,
+            constants::TAC => 0b11111000 | self.tac,
+            _ => 0xFF
+        }
+    }
+
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        match addr as usize {
+            constants::DIV => self.write_counter(),
+            constants::TIMA => self.tima = data,
+            constants::TMA => self.tma = data,
+            constants::TAC => self.tac = data & 0b111,
+            _ => ()
+        }
+    }
 }
\ No newline at end of file
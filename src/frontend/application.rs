@@ -1,20 +1,26 @@
 use iced::{button, executor, keyboard, slider, time,
            Application, Clipboard, Column, Command, Container, Element, Subscription};
-use rodio::{
-    source::{SineWave, Source},
-    Sink,
-};
 use std::time::Duration;
+use std::net::TcpListener;
 use nfd2::Response;
 
 use crate::frontend::constants;
+use gameboyo::emulator::constants as emulator_constants;
 use crate::frontend::views;
-use crate::emulator::emulator;
+use crate::frontend::audio::AudioOutput;
+use gameboyo::emulator::emulator;
+use gameboyo::emulator::joypad::joypad::Button;
+use gameboyo::emulator::serial::tcp::TcpLink;
 
 //ICED STATE
 pub struct Gameboyo {
     current_view: PageModel,
     emulator: Option<emulator::Emulator>,
+    rom_info: Option<String>,
+    running: bool,
+    audio: Option<AudioOutput>,
+    record_stems: bool,
+    link_listener: Option<TcpListener>,
 }
 
 #[derive(Debug, Clone)]
@@ -23,6 +29,10 @@ pub enum Message {
     Goto(PageModel),
     LaunchEmulator,
     ChooseRom,
+    ToggleRecording,
+    SetRecordStems(bool),
+    HostLink,
+    JoinLink,
     Tick,
     RedrawScreen,
 }
@@ -32,14 +42,135 @@ pub enum PageModel {
     Init {
         rom_button: button::State,
         start_button: button::State,
+        record_button: button::State,
+        host_button: button::State,
+        join_button: button::State,
     }
 }
 
 impl Default for Gameboyo {
     fn default() -> Self {
         Self {
-            current_view: PageModel::Init{ rom_button: button::State::new(), start_button: button::State::new() },
+            current_view: PageModel::Init{
+                rom_button: button::State::new(),
+                start_button: button::State::new(),
+                record_button: button::State::new(),
+                host_button: button::State::new(),
+                join_button: button::State::new(),
+            },
             emulator: None,
+            rom_info: None,
+            running: false,
+            audio: None,
+            record_stems: false,
+            link_listener: None,
+        }
+    }
+}
+
+impl Gameboyo {
+    //Listen for the other instance without blocking the UI, the connection is picked up by poll_link
+    fn host_link(&mut self) {
+        if self.emulator.is_none() || self.link_listener.is_some() { return; }
+        let listener = match TcpListener::bind(constants::LINK_ADDRESS) {
+            Ok(x) => x,
+            Err(e) => {
+                println!("Unable to host link cable on {}: {}", constants::LINK_ADDRESS, e);
+                return;
+            }
+        };
+        match listener.set_nonblocking(true) {
+            Ok(_) => self.link_listener = Some(listener),
+            Err(e) => println!("Unable to host link cable on {}: {}", constants::LINK_ADDRESS, e),
+        }
+    }
+
+    fn poll_link(&mut self) {
+        let stream = match &self.link_listener {
+            Some(listener) => match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    println!("Link cable connection failed: {}", e);
+                    return;
+                }
+            },
+            None => return,
+        };
+        self.link_listener = None;
+        self.attach_link(TcpLink::from_stream(stream));
+    }
+
+    fn join_link(&mut self) {
+        if self.emulator.is_none() { return; }
+        self.attach_link(TcpLink::connect(constants::LINK_ADDRESS));
+    }
+
+    fn attach_link(&mut self, link: std::io::Result<TcpLink>) {
+        match (&mut self.emulator, link) {
+            (Some(emulator), Ok(link)) => emulator.attach_link(link),
+            (_, Err(e)) => println!("Link cable connection failed: {}", e),
+            (None, Ok(_)) => ()
+        }
+    }
+
+    //Stop the current recording, or ask where to save a new one
+    fn toggle_recording(&mut self) {
+        let record_stems = self.record_stems;
+        let emulator = match &mut self.emulator {
+            Some(x) => x,
+            None => return,
+        };
+        if emulator.recording() {
+            match emulator.stop_recording() {
+                Ok(_) => (),
+                Err(e) => println!("Unable to finish audio recording: {}", e),
+            }
+            return;
+        }
+        let path = match nfd2::open_save_dialog(Some("wav"), None).expect("Unable to open file dialog") {
+            Response::Okay(file_path) => file_path.with_extension("wav"),
+            _ => return,
+        };
+        match emulator.start_recording(&path.to_string_lossy(), record_stems) {
+            Ok(_) => (),
+            Err(e) => println!("Unable to start audio recording: {}", e),
+        }
+    }
+
+    //Forward a mapped key to the emulator's joypad, unmapped keys are ignored
+    fn set_button(&mut self, key_code: keyboard::KeyCode, pressed: bool) {
+        let button = match key_code {
+            constants::KEY_UP => Button::Up,
+            constants::KEY_DOWN => Button::Down,
+            constants::KEY_LEFT => Button::Left,
+            constants::KEY_RIGHT => Button::Right,
+            constants::KEY_A => Button::A,
+            constants::KEY_B => Button::B,
+            constants::KEY_SELECT => Button::Select,
+            constants::KEY_START => Button::Start,
+            _ => return,
+        };
+        match &mut self.emulator {
+            Some(x) => x.set_button(button, pressed),
+            None => ()
+        }
+    }
+
+    //Only does anything while a .gbs file is loaded
+    fn change_track(&mut self, key_code: keyboard::KeyCode) {
+        let emulator = match &mut self.emulator {
+            Some(x) => x,
+            None => return,
+        };
+        let result = match key_code {
+            constants::KEY_NEXT_TRACK => emulator.next_track(),
+            constants::KEY_PREVIOUS_TRACK => emulator.previous_track(),
+            _ => return,
+        };
+        match result {
+            Ok(_) => (),
+            Err(e) => println!("Unable to change track: {}", e),
         }
     }
 }
@@ -60,27 +191,70 @@ impl Application for Gameboyo {
     fn view(&mut self) -> Element<Message> {
         //TODO: match on current page model
         match &mut self.current_view {
-            PageModel::Init{ rom_button, start_button } => views::init::draw(rom_button, start_button),
+            PageModel::Init{ rom_button, start_button, record_button, host_button, join_button } => {
+                let recording = match &self.emulator {
+                    Some(x) => x.recording(),
+                    None => false,
+                };
+                let recording_error = match &self.emulator {
+                    Some(x) => x.recording_error().map(|e| format!("Recording stopped: {}", e)),
+                    None => None,
+                };
+                let link_status = match (&self.emulator, &self.link_listener) {
+                    (Some(x), _) if x.linked() => String::from("Link cable connected"),
+                    (_, Some(_)) => String::from("Waiting for the other instance to join"),
+                    (Some(x), None) => match x.link_error() {
+                        Some(e) => format!("Link cable disconnected: {}", e),
+                        None => String::from("Link cable not connected"),
+                    },
+                    _ => String::from("Link cable not connected"),
+                };
+                views::init::draw(rom_button, start_button, record_button, host_button, join_button,
+                                  recording, self.record_stems, &recording_error, &link_status, &self.rom_info)
+            },
         }
     }
 
     fn update(&mut self, message: Message, _clipboard: &mut Clipboard) -> Command<Message> {
         match message {
             Message::ChooseRom => {
-                let mut path: String = String::from("");
-                match nfd2::open_file_dialog(None, None).expect("Unable to open file dialog") {
-                    Response::Okay(file_path) => {
-                        path = file_path.clone().into_os_string().into_string().unwrap();
+                let path = match nfd2::open_file_dialog(None, None).expect("Unable to open file dialog") {
+                    Response::Okay(file_path) => file_path.into_os_string().into_string().unwrap(),
+                    _ => return Command::none(),
+                };
+                //Keep the progress of the game being replaced, before the new one reads its save file
+                match &mut self.emulator {
+                    Some(x) => match x.save() {
+                        Ok(_) => (),
+                        Err(e) => println!("Unable to write save file: {}", e),
                     },
-                    _ => println!("User canceled")
+                    None => ()
+                }
+                //A file that fails to load leaves the current game in place
+                match emulator::Emulator::new(path) {
+                    Ok(x) => {
+                        match x.boot_rom_error() {
+                            Some(e) => println!("Starting without the boot ROM: {}", e),
+                            None => ()
+                        }
+                        self.rom_info = Some(x.header().to_string());
+                        self.emulator = Some(x);
+                    },
+                    Err(e) => println!("{}", e),
+                }
+            },
+            Message::ToggleRecording => self.toggle_recording(),
+            Message::SetRecordStems(x) => self.record_stems = x,
+            Message::HostLink => self.host_link(),
+            Message::JoinLink => self.join_link(),
+            Message::LaunchEmulator => {
+                if self.emulator.is_none() { return Command::none(); }
+                self.running = true;
+                //Without an output device the emulator still runs, the samples are just discarded
+                if self.audio.is_none() {
+                    self.audio = AudioOutput::new();
                 }
-                self.emulator = Some(emulator::Emulator::new(path));
-                match &self.emulator {
-                    Some(x) => true, //x.validate_logo(),
-                    _ => false
-                };
             },
-            Message::LaunchEmulator => (),
             Message::Tick => {
                 /*
                     - Fires 59.7275 times per second
@@ -88,7 +262,27 @@ impl Application for Gameboyo {
                         Loop 70224 times (Machine Cycles per Frame):
                             emulator.tick()
                                 //This ticks with the 4 MHz clock
+                        Queue the frame's audio for playback
                 */
+                self.poll_link();
+                if !self.running { return Command::none(); }
+                match &mut self.emulator {
+                    Some(emulator) => {
+                        for _ in 0..emulator_constants::DOTS_PER_FRAME {
+                            emulator.tick();
+                        }
+                        match emulator.flush_save() {
+                            Ok(_) => (),
+                            Err(e) => println!("Unable to write save file: {}", e),
+                        }
+                        let samples = emulator.take_samples();
+                        match &mut self.audio {
+                            Some(audio) => audio.push(&samples),
+                            None => ()
+                        }
+                    },
+                    None => ()
+                }
             }
             Message::Goto(p) => {
                 self.current_view = p;
@@ -97,9 +291,12 @@ impl Application for Gameboyo {
                 match event {
                     iced_native::Event::Keyboard(keyboard_event) => match keyboard_event {
                         keyboard::Event::KeyPressed { key_code, .. } => {
+                            self.change_track(key_code);
+                            self.set_button(key_code, true);
                             return Command::none();
                         },
                         keyboard::Event::KeyReleased { key_code, .. } => {
+                            self.set_button(key_code, false);
                             return Command::none();
                         },
                         _ => ()
@@ -0,0 +1,210 @@
+/*
+Audio output: APU samples -> DC blocker -> resampler -> ring buffer -> rodio.
+
+The UI thread produces a frame's worth of audio at a time, the rodio thread consumes it sample by sample. The two
+only share a single-producer / single-consumer ring buffer, so neither side ever blocks the other.
+
+Underruns fade the last sample out instead of dropping to silence, and playback fades back in once data arrives again.
+Overruns are avoided by steering the resampler ratio to keep the buffer around half full; if it does fill up anyway
+whole frames are dropped so the left / right channels never swap.
+ */
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rodio::{OutputStream, OutputStreamHandle, Sink, Source, DeviceTrait};
+use rodio::cpal::traits::HostTrait;
+
+use gameboyo::emulator::constants as emulator_constants;
+use gameboyo::emulator::apu::resampler::{DcBlocker, Resampler};
+use crate::frontend::constants;
+
+/*
+Lock-free SPSC ring buffer of stereo frames. Samples are stored as f32 bit patterns.
+read / write count frames pushed and popped since creation, their difference is the fill level.
+ */
+pub struct RingBuffer {
+    data: Box<[AtomicU32]>,
+    capacity: usize,
+    read: AtomicUsize,
+    write: AtomicUsize,
+}
+
+impl RingBuffer {
+    pub fn new(capacity: usize) -> Self {
+        let mut data: Vec<AtomicU32> = Vec::with_capacity(capacity * 2);
+        for _ in 0..capacity * 2 {
+            data.push(AtomicU32::new(0));
+        }
+        Self {
+            data: data.into_boxed_slice(),
+            capacity,
+            read: AtomicUsize::new(0),
+            write: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.write.load(Ordering::Acquire).wrapping_sub(self.read.load(Ordering::Acquire))
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    //Producer side. Returns false (and drops the frame) if the buffer is full.
+    pub fn push(&self, frame: [f32; 2]) -> bool {
+        let write = self.write.load(Ordering::Relaxed);
+        let read = self.read.load(Ordering::Acquire);
+        if write.wrapping_sub(read) == self.capacity { return false; }
+        let index = (write % self.capacity) * 2;
+        self.data[index].store(frame[0].to_bits(), Ordering::Relaxed);
+        self.data[index + 1].store(frame[1].to_bits(), Ordering::Relaxed);
+        self.write.store(write.wrapping_add(1), Ordering::Release);
+        return true;
+    }
+
+    //Consumer side
+    pub fn pop(&self) -> Option<[f32; 2]> {
+        let read = self.read.load(Ordering::Relaxed);
+        let write = self.write.load(Ordering::Acquire);
+        if read == write { return None; }
+        let index = (read % self.capacity) * 2;
+        let frame = [
+            f32::from_bits(self.data[index].load(Ordering::Relaxed)),
+            f32::from_bits(self.data[index + 1].load(Ordering::Relaxed)),
+        ];
+        self.read.store(read.wrapping_add(1), Ordering::Release);
+        return Some(frame);
+    }
+}
+
+/*
+rodio source reading interleaved stereo from the ring buffer
+frame: frame currently being output, channel: which side of it comes next
+gain: 1.0 while data is flowing, decays towards 0 on underrun and ramps back up afterwards
+ */
+struct RingSource {
+    buffer: Arc<RingBuffer>,
+    sample_rate: u32,
+    frame: [f32; 2],
+    channel: usize,
+    gain: f32,
+}
+
+impl Iterator for RingSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.channel == 0 {
+            match self.buffer.pop() {
+                Some(frame) => {
+                    self.frame = frame;
+                    self.gain = f32::min(1.0, self.gain + constants::AUDIO_FADE_IN_STEP);
+                },
+                None => {
+                    //Keep the waveform where it was and let it die away
+                    self.gain = self.gain * constants::AUDIO_UNDERRUN_DECAY;
+                }
+            }
+        }
+        let sample = self.frame[self.channel] * self.gain;
+        self.channel = (self.channel + 1) % 2;
+        return Some(sample);
+    }
+}
+
+impl Source for RingSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        2
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+pub struct AudioOutput {
+    _stream: OutputStream,
+    _handle: OutputStreamHandle,
+    _sink: Sink,
+    buffer: Arc<RingBuffer>,
+    dc_blocker: DcBlocker,
+    resampler: Resampler,
+}
+
+impl AudioOutput {
+    //Opens the default output device at its preferred rate. Returns None if there is no usable device.
+    pub fn new() -> Option<Self> {
+        let sample_rate = AudioOutput::device_sample_rate();
+        let (stream, handle) = match OutputStream::try_default() {
+            Ok(x) => x,
+            Err(e) => {
+                println!("Unable to open audio output: {}", e);
+                return None;
+            }
+        };
+        let sink = match Sink::try_new(&handle) {
+            Ok(x) => x,
+            Err(e) => {
+                println!("Unable to open audio output: {}", e);
+                return None;
+            }
+        };
+        let capacity = (sample_rate as f32 * constants::AUDIO_BUFFER_SECONDS) as usize;
+        let buffer = Arc::new(RingBuffer::new(capacity));
+        sink.append(RingSource {
+            buffer: Arc::clone(&buffer),
+            sample_rate,
+            frame: [0.0, 0.0],
+            channel: 0,
+            gain: 0.0,
+        });
+        Some(Self {
+            _stream: stream,
+            _handle: handle,
+            _sink: sink,
+            buffer,
+            dc_blocker: DcBlocker::new(emulator_constants::APU_SAMPLE_RATE),
+            resampler: Resampler::new(emulator_constants::APU_SAMPLE_RATE, sample_rate),
+        })
+    }
+
+    //Resampling straight to the device rate avoids a second, lower quality conversion inside rodio
+    fn device_sample_rate() -> u32 {
+        let device = match rodio::cpal::default_host().default_output_device() {
+            Some(x) => x,
+            None => return constants::AUDIO_DEFAULT_SAMPLE_RATE,
+        };
+        match device.default_output_config() {
+            Ok(config) => config.sample_rate().0,
+            Err(_) => constants::AUDIO_DEFAULT_SAMPLE_RATE,
+        }
+    }
+
+    /*
+    Queue APU samples for playback. Before resampling, the ratio is nudged by up to AUDIO_RATE_CONTROL
+    in proportion to how far the buffer is from half full.
+     */
+    pub fn push(&mut self, samples: &[[f32; 2]]) {
+        let fill = self.buffer.len() as f64 / self.buffer.capacity() as f64;
+        self.resampler.set_step_scale(1.0 + (fill - 0.5) * 2.0 * constants::AUDIO_RATE_CONTROL);
+        for sample in samples {
+            self.resampler.push(self.dc_blocker.process(*sample));
+            loop {
+                match self.resampler.pull() {
+                    Some(x) => { self.buffer.push(x); },
+                    None => break,
+                }
+            }
+        }
+    }
+}
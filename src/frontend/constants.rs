@@ -1,5 +1,32 @@
+use iced::keyboard::KeyCode;
+
 //Application constants
 pub const APPLICATION_TITLE: &str = "Gameboyo";
 
 //Clock constants
-pub const CLOCK_SPEED_HZ: u32 = 4_194_304;
\ No newline at end of file
+pub const CLOCK_SPEED_HZ: u32 = 4_194_304;
+pub const FPS_MILLIS: u64 = 16; //UI tick, roughly one 59.73 Hz frame
+
+//Audio constants
+pub const AUDIO_DEFAULT_SAMPLE_RATE: u32 = 48000;
+pub const AUDIO_BUFFER_SECONDS: f32 = 0.1;
+pub const AUDIO_RATE_CONTROL: f64 = 0.005; //max +-0.5% resampling ratio adjustment to hold the buffer half full
+pub const AUDIO_UNDERRUN_DECAY: f32 = 0.995;
+pub const AUDIO_FADE_IN_STEP: f32 = 1.0 / 256.0;
+
+//Keyboard mapping
+pub const KEY_UP: KeyCode = KeyCode::Up;
+pub const KEY_DOWN: KeyCode = KeyCode::Down;
+pub const KEY_LEFT: KeyCode = KeyCode::Left;
+pub const KEY_RIGHT: KeyCode = KeyCode::Right;
+pub const KEY_A: KeyCode = KeyCode::X;
+pub const KEY_B: KeyCode = KeyCode::Z;
+pub const KEY_SELECT: KeyCode = KeyCode::Backspace;
+pub const KEY_START: KeyCode = KeyCode::Enter;
+
+//GBS player
+pub const KEY_NEXT_TRACK: KeyCode = KeyCode::PageDown;
+pub const KEY_PREVIOUS_TRACK: KeyCode = KeyCode::PageUp;
+
+//Link cable, the hosting instance listens here and the joining one connects to it
+pub const LINK_ADDRESS: &str = "127.0.0.1:5423";
\ No newline at end of file
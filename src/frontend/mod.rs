@@ -1,3 +1,4 @@
 pub mod application;
 pub mod views;
-pub mod constants;
\ No newline at end of file
+pub mod constants;
+pub mod audio;
\ No newline at end of file
@@ -1,8 +1,12 @@
-use iced::{button, Align, Button, Column, Container, Element, Length, Row, Text};
+use iced::{button, Align, Button, Checkbox, Column, Container, Element, Length, Row, Text};
 use crate::frontend::application::Message;
 
-pub fn draw<'a>(rom_button: &'a mut button::State, start_button: &'a mut button::State) -> Element<'a, Message> {
-    let content = Column::new()
+pub fn draw<'a>(rom_button: &'a mut button::State, start_button: &'a mut button::State, record_button: &'a mut button::State,
+                host_button: &'a mut button::State, join_button: &'a mut button::State,
+                recording: bool, record_stems: bool, recording_error: &Option<String>, link_status: &str,
+                rom_info: &Option<String>) -> Element<'a, Message> {
+    let record_label = if recording { "Stop recording" } else { "Record audio" };
+    let mut content = Column::new()
         .spacing(20)
         .align_items(Align::Center)
         .push(
@@ -15,7 +19,37 @@ pub fn draw<'a>(rom_button: &'a mut button::State, start_button: &'a mut button:
                     Button::new(start_button, Text::new(String::from("Launch")))
                         .on_press(Message::LaunchEmulator)
                 )
-        );
+                .push(
+                    Button::new(record_button, Text::new(String::from(record_label)))
+                        .on_press(Message::ToggleRecording)
+                )
+        )
+        //Per-channel stems are chosen before a recording starts
+        .push(Checkbox::new(record_stems, "Record each channel to its own file", Message::SetRecordStems));
+    //Why the last recording ended early, if it did
+    match recording_error {
+        Some(error) => content = content.push(Text::new(error.clone()).size(16)),
+        None => ()
+    }
+    content = content
+        //Link cable between two instances on this machine, one hosts and the other joins
+        .push(
+            Row::new()
+                .push(
+                    Button::new(host_button, Text::new(String::from("Host link")))
+                        .on_press(Message::HostLink)
+                )
+                .push(
+                    Button::new(join_button, Text::new(String::from("Join link")))
+                        .on_press(Message::JoinLink)
+                )
+        )
+        .push(Text::new(String::from(link_status)).size(16));
+    //Cartridge header details and validation report for the selected ROM
+    match rom_info {
+        Some(info) => content = content.push(Text::new(info.clone()).size(16)),
+        None => ()
+    }
     Container::new(content)
         .width(Length::Fill)
         .height(Length::Fill)
@@ -1,5 +1,4 @@
 mod frontend;
-mod emulator;
 use iced::Application;
 
 fn main() {